@@ -0,0 +1,299 @@
+//! `chainsaw doctor`: environment checks to paste into a bug report.
+
+use std::collections::HashMap;
+
+use crate::{Bus, blocker_summary, connect, coverage_summary};
+
+const LSM_PATH: &str = "/sys/kernel/security/lsm";
+
+struct Check {
+    name: &'static str,
+    passed: bool,
+    // Optional checks report WARN and don't fail the run.
+    optional: bool,
+    detail: String,
+    hint: &'static str,
+}
+
+impl Check {
+    fn new(name: &'static str, passed: bool, detail: String, hint: &'static str) -> Self {
+        Self {
+            name,
+            passed,
+            optional: false,
+            detail,
+            hint,
+        }
+    }
+
+    fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+}
+
+fn capability_hint(name: &str) -> (&'static str, &'static str) {
+    match name {
+        "lsm_bpf_available" => ("BPF LSM programs", "needs kernel 5.7+ with bpf in lsm="),
+        "ringbuf_supported" => ("ring buffer (audit)", "needs kernel 5.8+"),
+        "pinning_supported" => ("map pinning", "mount bpffs on /sys/fs/bpf"),
+        "cgroup_id_helper_available" => ("cgroup blocking", "needs kernel 4.18+"),
+        _ => ("unknown feature", ""),
+    }
+}
+
+fn check_bpf_lsm() -> Check {
+    match std::fs::read_to_string(LSM_PATH) {
+        Ok(lsm) => Check::new(
+            "bpf LSM enabled",
+            lsm.trim().split(',').any(|name| name == "bpf"),
+            format!("lsm={}", lsm.trim()),
+            "add \"bpf\" to the lsm= kernel command line parameter and reboot",
+        ),
+        Err(err) => Check::new(
+            "bpf LSM enabled",
+            false,
+            format!("can't read {}: {}", LSM_PATH, err),
+            "securityfs must be mounted on /sys/kernel/security",
+        ),
+    }
+}
+
+async fn daemon_checks(
+    proxy: &zbus::Proxy<'static>,
+    expect_bpf_hash: Option<&str>,
+    checks: &mut Vec<Check>,
+) {
+    let status: HashMap<String, String> = match proxy
+        .call::<_, _, Vec<(String, String)>>("GetStatus", &())
+        .await
+    {
+        Ok(status) => status.into_iter().collect(),
+        Err(err) => {
+            checks.push(Check::new(
+                "daemon reachable",
+                false,
+                err.to_string(),
+                "check `systemctl status chainsawd` and the D-Bus policy",
+            ));
+            return;
+        }
+    };
+    let field = |key: &str| status.get(key).cloned().unwrap_or_default();
+
+    checks.push(Check::new(
+        "daemon reachable",
+        true,
+        format!("mode={}", field("mode")),
+        "",
+    ));
+    let blocker: zbus::Result<Vec<(String, String)>> = proxy.call("GetBlockerStatus", &()).await;
+    match blocker {
+        Ok(blocker) => {
+            let kernel = blocker
+                .iter()
+                .find(|(key, _)| key == "kernel")
+                .map(|(_, value)| value.as_str())
+                .unwrap_or("?");
+            checks.push(Check::new(
+                "eBPF hook attached",
+                field("ebpf") == "attached",
+                format!("{}, kernel {}", blocker_summary(&blocker), kernel),
+                "check the daemon log for load/attach errors and that the kernel has BPF LSM",
+            ));
+        }
+        Err(err) => checks.push(Check::new(
+            "eBPF hook attached",
+            field("ebpf") == "attached",
+            format!("ebpf={} ({})", field("ebpf"), err),
+            "rebuild with clang available and check the daemon log for load errors",
+        )),
+    }
+    let coverage: zbus::Result<Vec<String>> = proxy.call("ListEnforcementCoverage", &()).await;
+    checks.push(match coverage {
+        Ok(routes) => Check::new(
+            "enforcement coverage",
+            !routes.is_empty(),
+            coverage_summary(&routes),
+            "nothing is blocked until the eBPF hook attaches",
+        ),
+        Err(err) => Check::new(
+            "enforcement coverage",
+            false,
+            err.to_string(),
+            "update the daemon to a version with ListEnforcementCoverage",
+        )
+        .optional(),
+    });
+    let bpf_hash: zbus::Result<String> = proxy.call("GetBpfHash", &()).await;
+    checks.push(match (bpf_hash, expect_bpf_hash) {
+        (Ok(hash), None) => Check::new("eBPF object hash", true, hash, ""),
+        (Ok(hash), Some(expected)) => {
+            let matches = hash.eq_ignore_ascii_case(expected.trim());
+            Check::new(
+                "eBPF object hash",
+                matches,
+                if matches {
+                    format!("{} (as expected)", hash)
+                } else {
+                    format!("{}, expected {}", hash, expected.trim())
+                },
+                "the daemon runs a different build of bpf.o; reinstall the expected package",
+            )
+        }
+        (Err(err), expected) => {
+            let check = Check::new(
+                "eBPF object hash",
+                false,
+                err.to_string(),
+                "update the daemon to a version with GetBpfHash",
+            );
+            // Only an old daemon lacks the method; that's fatal only when asked to compare.
+            if expected.is_some() {
+                check
+            } else {
+                check.optional()
+            }
+        }
+    });
+    checks.push(Check::new(
+        "config parseable",
+        field("config") == "ok",
+        field("config"),
+        "fix /etc/chainsaw.toml, the daemon log lists unknown keys",
+    ));
+    let audit_log = field("audit_log");
+    checks.push(Check::new(
+        "audit logging",
+        !audit_log.starts_with("error"),
+        match audit_log.as_str() {
+            "" | "off" => "off (audit_log not set)".to_string(),
+            path if !path.starts_with("error") => format!("active, {}", path),
+            error => error.to_string(),
+        },
+        "the daemon can't open audit_log for appending; check the path and its directory",
+    ));
+    let gpu_count: usize = field("gpu_count").parse().unwrap_or(0);
+    checks.push(Check::new(
+        "GPUs detected",
+        gpu_count > 0,
+        format!("{} GPU(s)", gpu_count),
+        "check that IOMMU is enabled and /sys/bus/pci is readable",
+    ));
+
+    let self_test: zbus::Result<String> = proxy.call("RunSelfTest", &()).await;
+    checks.push(match self_test {
+        Ok(detail) => Check::new("blocker map round-trip", true, detail, ""),
+        Err(err) => Check::new(
+            "blocker map round-trip",
+            false,
+            err.to_string(),
+            "the eBPF maps can't be written; check the daemon log and its privileges",
+        ),
+    });
+
+    let capabilities: zbus::Result<Vec<(String, bool)>> = proxy.call("GetCapabilities", &()).await;
+    match capabilities {
+        Ok(capabilities) => {
+            for (name, available) in capabilities {
+                let (label, hint) = capability_hint(&name);
+                checks.push(Check::new(label, available, name, hint).optional());
+            }
+        }
+        Err(err) => checks.push(
+            Check::new(
+                "kernel capabilities",
+                false,
+                err.to_string(),
+                "update the daemon to a version with GetCapabilities",
+            )
+            .optional(),
+        ),
+    }
+
+    let conflicts: zbus::Result<Vec<String>> = proxy.call("GetModuleParamConflicts", &()).await;
+    checks.push(match conflicts {
+        Ok(conflicts) => Check::new(
+            "module parameters",
+            conflicts.is_empty(),
+            if conflicts.is_empty() {
+                "no known conflicts".to_string()
+            } else {
+                conflicts.join("; ")
+            },
+            "blocks may not take full effect; change the parameter in /etc/modprobe.d if that matters",
+        )
+        .optional(),
+        Err(err) => Check::new(
+            "module parameters",
+            false,
+            err.to_string(),
+            "update the daemon to a version with GetModuleParamConflicts",
+        )
+        .optional(),
+    });
+
+    let consistency: zbus::Result<(bool, Vec<String>)> =
+        proxy.call("GetModeConsistency", &()).await;
+    match consistency {
+        Ok((consistent, mismatches)) => checks.push(Check::new(
+            "effective state matches mode",
+            consistent,
+            if consistent {
+                "all GPUs match".to_string()
+            } else {
+                mismatches.join("; ")
+            },
+            "re-apply the mode with `chainsaw set <mode>`",
+        )),
+        Err(err) => checks.push(Check::new(
+            "effective state matches mode",
+            false,
+            err.to_string(),
+            "update the daemon to a version with GetModeConsistency",
+        )),
+    }
+}
+
+pub async fn run(
+    bus: Bus,
+    address: Option<&str>,
+    expect_bpf_hash: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut checks = vec![check_bpf_lsm()];
+
+    match connect(bus, address).await {
+        Ok(proxy) => daemon_checks(&proxy, expect_bpf_hash, &mut checks).await,
+        Err(err) => checks.push(Check::new(
+            "daemon reachable",
+            false,
+            err.to_string(),
+            "start it with `systemctl start chainsawd`",
+        )),
+    }
+
+    for check in &checks {
+        println!(
+            "{}  {}: {}",
+            match (check.passed, check.optional) {
+                (true, _) => "PASS",
+                (false, true) => "WARN",
+                (false, false) => "FAIL",
+            },
+            check.name,
+            check.detail
+        );
+        if !check.passed && !check.hint.is_empty() {
+            println!("      hint: {}", check.hint);
+        }
+    }
+
+    let failed = checks
+        .iter()
+        .filter(|check| !check.passed && !check.optional)
+        .count();
+    if failed > 0 {
+        return Err(format!("{} check(s) failed", failed).into());
+    }
+    Ok(())
+}
@@ -1,12 +1,53 @@
-use clap::{Parser, Subcommand};
+use chainsaw_client::Target;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::collections::HashMap;
+use std::io::Write;
+
+mod doctor;
+
+/// Exit status of `list` when the daemon detected no GPUs.
+const EXIT_NO_GPUS: i32 = 3;
+const NO_GPUS_MESSAGE: &str = "No GPUs detected by the daemon (PCI scan found no display controllers). Run `chainsaw doctor` to check the environment.";
+#[cfg(feature = "tui")]
+mod tui;
 
 #[derive(Parser)]
 #[command(version, about)]
 struct Args {
+    /// D-Bus bus the daemon is running on
+    #[arg(long, value_enum, default_value_t = Bus::System, global = true)]
+    bus: Bus,
+    /// Custom D-Bus address (overrides --bus): unix:path=/tmp/chainsaw-bus, or
+    /// tcp:host=HOST,port=PORT (tcp6:, nonce-tcp:) for a remote daemon
+    #[arg(long, global = true, value_parser = chainsaw_client::parse_address)]
+    address: Option<String>,
+    /// Print times as raw Unix timestamps instead of "3m ago" / "in 1h 12m"
+    #[arg(long, global = true, conflicts_with = "iso")]
+    raw: bool,
+    /// Print times as ISO 8601 (UTC) instead of "3m ago" / "in 1h 12m"
+    #[arg(long, global = true)]
+    iso: bool,
+    /// Print the D-Bus calls a command that changes something would make, without
+    /// making them (`set` also shows the resulting block decisions)
+    #[arg(long, global = true)]
+    dry_run: bool,
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+enum Bus {
+    System,
+    Session,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum IndicatorFormat {
+    Text,
+    /// Waybar custom module JSON: {"text", "class", "tooltip"}
+    Json,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Set the mode
@@ -14,21 +55,147 @@ enum Commands {
         /// Mode
         /// "integrated"
         /// "hybrid"
+        /// "gaming"
         mode: String,
+        /// Revert to the current mode after this many seconds
+        #[arg(long = "for", value_name = "SECONDS")]
+        duration: Option<u64>,
+        /// Revert after SECONDS (default 15) unless Enter is pressed to keep the mode
+        #[arg(
+            long,
+            value_name = "SECONDS",
+            num_args = 0..=1,
+            default_missing_value = "15",
+            conflicts_with = "duration"
+        )]
+        confirm: Option<u64>,
+        /// Integrated mode only: keep this GPU (id, PCI address or alias) instead of the
+        /// boot GPU and block every other one; saved for later. `boot` undoes it
+        #[arg(long, value_name = "GPU", conflicts_with_all = ["duration", "confirm"])]
+        keep: Option<String>,
     },
     /// Get the current mode
     Get,
+    /// Print a short mode token (INT, HYB, GAM; ERR if the daemon is unreachable) for status bars
+    ModeIndicator {
+        #[arg(long, value_enum, default_value_t = IndicatorFormat::Text)]
+        format: IndicatorFormat,
+        /// Prefix the token with an icon
+        #[arg(long)]
+        icon: bool,
+    },
+    /// Confirm the current mode works (otherwise the next boot reverts it)
+    Confirm,
     /// List GPUs in a table
-    List,
+    List {
+        /// Comma-separated columns to show, in order:
+        /// id,name,pci,render,card,default,blocked,driver,managed,temp,hits,nodes
+        #[arg(long, value_delimiter = ',')]
+        columns: Option<Vec<String>>,
+        /// Write the table to this file instead of stdout
+        #[arg(long, short)]
+        output: Option<std::path::PathBuf>,
+    },
     /// GPU operations
+    #[command(subcommand_precedence_over_arg = true)]
     Gpu {
-        /// GPU numeric id
-        id: u32,
+        /// GPU numeric ids, PCI addresses or aliases (several only for `block`)
+        #[arg(required = true, num_args = 1..)]
+        ids: Vec<String>,
         #[command(subcommand)]
         command: GpuCommands,
     },
-    /// List supported modes
-    ListModes,
+    /// List the modes usable on this hardware
+    ListModes {
+        /// List every known mode, even ones this hardware can't use
+        #[arg(long)]
+        all: bool,
+        /// Describe what each known mode does
+        #[arg(long, conflicts_with = "all")]
+        detailed: bool,
+    },
+    /// Show daemon status
+    Status,
+    /// Stop blocking anything, keeping the block set
+    Pause {
+        /// Resume on its own after this many seconds
+        #[arg(long = "for", value_name = "SECONDS")]
+        duration: Option<u64>,
+    },
+    /// Enforce the block set again after `pause`
+    Resume,
+    /// Check the environment and print a report
+    Doctor {
+        /// Fail unless the daemon's eBPF object has this SHA-256
+        #[arg(long, value_name = "SHA256")]
+        expect_bpf_hash: Option<String>,
+    },
+    /// Daemon configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+    /// Release the boot GPU's firmware framebuffer (efifb/simpledrm); blanks the console
+    ReleaseFramebuffer,
+    /// Block or unblock GPU access for a cgroup v2 (e.g. a container)
+    Cgroup {
+        /// cgroup path, e.g. /system.slice/docker-<id>.scope
+        path: String,
+        /// on/off
+        state: String,
+    },
+    /// Block or unblock every PCI function of a slot, and keep blocking it after restarts
+    PciPattern {
+        /// bb:dd.*, dddd:bb:dd.* or a full PCI address, e.g. 01:00.*
+        pattern: String,
+        /// on/off
+        state: String,
+    },
+    /// Operations on every GPU bound to a kernel driver
+    Driver {
+        /// Driver name, e.g. nouveau or amdgpu
+        name: String,
+        #[command(subcommand)]
+        command: DriverCommands,
+    },
+    /// Print the current mode and block set as TOML
+    Export {
+        /// Write the state to this file instead of stdout
+        #[arg(long, short)]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Apply a state file produced by `export`
+    Import {
+        /// Path to the state file
+        path: std::path::PathBuf,
+    },
+    /// Show the daemon's recent events (mode and block changes, warnings), oldest first
+    Log {
+        /// Number of events to show (0 = every one kept, at most 100)
+        #[arg(long, short = 'n', default_value_t = 20)]
+        lines: u32,
+        /// One JSON object per event and line, for jq and log processors
+        #[arg(long)]
+        json_lines: bool,
+    },
+    /// Diagnostics for contributors
+    #[command(hide = true)]
+    Debug {
+        #[command(subcommand)]
+        command: DebugCommands,
+    },
+    /// Interactive dashboard
+    #[cfg(feature = "tui")]
+    Tui,
+}
+
+#[derive(Subcommand)]
+enum DriverCommands {
+    /// Block or unblock the driver's GPUs, and keep blocking them after restarts
+    Block {
+        /// on/off
+        state: String,
+    },
 }
 
 #[derive(Subcommand)]
@@ -37,61 +204,588 @@ enum GpuCommands {
     Block {
         /// on/off
         state: String,
+        /// Also block/unblock the other PCI functions in the GPU's slot (HDMI audio, USB-C, ...)
+        #[arg(long)]
+        all_functions: bool,
+    },
+    /// Show detailed information
+    Info,
+    /// List the display connectors and whether a screen is attached
+    Displays,
+    /// Show used and total VRAM (amdgpu only)
+    Mem,
+    /// Print one allow-listed sysfs attribute (runtime_status, power_state, ...)
+    Attr {
+        /// Attribute name
+        name: String,
+    },
+    /// Use only this GPU: block every other non-default GPU
+    Exclusive,
+    /// Show or force the power profile (auto, low, high, ...; amdgpu only)
+    Profile {
+        /// Level to set; prints the current one when omitted
+        level: Option<String>,
+    },
+    /// Run a command on this GPU with PRIME render offload
+    Run {
+        /// Command and its arguments, after `--`
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Print the settings the daemon is running with (after every source is merged)
+    Show,
+    /// Print a suggested systemd drop-in for chainsawd matching the running config
+    ExportUnit,
+}
+
+#[derive(Subcommand)]
+enum DebugCommands {
+    /// Time GPU enumeration and a block/unblock round-trip in the daemon
+    Bench,
+    /// Print every field the daemon knows for each GPU (ids, subsystem, nodes, ...)
+    Dump,
+    /// Compare the block state the current mode expects with what the eBPF maps hold
+    Diff {
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
     },
 }
 
-type GpuRow = (u32, String, String, String, bool, bool);
+// (id, name, pci, render, default, blocked, driver, managed, block_state)
+type GpuRow = (
+    u32,
+    String,
+    String,
+    String,
+    bool,
+    bool,
+    String,
+    bool,
+    String,
+);
 
-fn print_gpu_table(rows: &[GpuRow]) {
-    let mut id_w = 2usize;
-    let mut name_w = 4usize;
-    let mut pci_w = 3usize;
-    let mut render_w = 6usize;
+/// BLOCKED cell: `partial` when only some of the GPU's entries are set.
+fn blocked_label(blocked: bool, block_state: &str) -> &'static str {
+    match (blocked, block_state) {
+        (_, "partial") => "partial",
+        (true, _) => "on*",
+        (false, _) => "off",
+    }
+}
 
-    for (id, name, pci, render, _, _) in rows {
-        id_w = id_w.max(id.to_string().len());
-        name_w = name_w.max(name.len());
-        pci_w = pci_w.max(pci.len());
-        render_w = render_w.max(render.len());
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Column {
+    Id,
+    Name,
+    Pci,
+    Render,
+    Card,
+    Default,
+    Blocked,
+    Driver,
+    Managed,
+    Temp,
+    Hits,
+    Nodes,
+}
+
+const ALL_COLUMNS: [(&str, Column); 12] = [
+    ("id", Column::Id),
+    ("name", Column::Name),
+    ("pci", Column::Pci),
+    ("render", Column::Render),
+    ("card", Column::Card),
+    ("default", Column::Default),
+    ("blocked", Column::Blocked),
+    ("driver", Column::Driver),
+    ("managed", Column::Managed),
+    ("temp", Column::Temp),
+    ("hits", Column::Hits),
+    ("nodes", Column::Nodes),
+];
+
+const DEFAULT_COLUMNS: [Column; 9] = [
+    Column::Id,
+    Column::Name,
+    Column::Pci,
+    Column::Render,
+    Column::Default,
+    Column::Blocked,
+    Column::Driver,
+    Column::Managed,
+    Column::Nodes,
+];
+
+impl Column {
+    fn parse(name: &str) -> Result<Self, String> {
+        ALL_COLUMNS
+            .iter()
+            .find(|(known, _)| *known == name.trim())
+            .map(|(_, column)| *column)
+            .ok_or_else(|| {
+                let names: Vec<&str> = ALL_COLUMNS.iter().map(|(known, _)| *known).collect();
+                format!("Unknown column '{}'. Expected: {}", name, names.join(","))
+            })
     }
 
-    println!(
-        "{:<id_w$}  {:<name_w$}  {:<pci_w$}  {:<render_w$}  {:<7}  {:<7}",
-        "ID",
-        "NAME",
-        "PCI",
-        "RENDER",
-        "DEFAULT",
-        "BLOCKED",
-        id_w = id_w,
-        name_w = name_w,
-        pci_w = pci_w,
-        render_w = render_w,
-    );
-    println!(
-        "{}  {}  {}  {}  {}  {}",
-        "-".repeat(id_w),
-        "-".repeat(name_w),
-        "-".repeat(pci_w),
-        "-".repeat(render_w),
-        "-".repeat(7),
-        "-".repeat(7),
+    fn header(self) -> String {
+        let name = ALL_COLUMNS
+            .iter()
+            .find(|(_, column)| *column == self)
+            .map_or("", |(name, _)| *name);
+        name.to_uppercase()
+    }
+
+    /// Columns not in `ListGpus`, filled from `GetGpuDetail`.
+    fn needs_detail(self) -> bool {
+        matches!(
+            self,
+            Column::Card | Column::Temp | Column::Hits | Column::Nodes
+        )
+    }
+
+    fn cell(self, row: &GpuRow, detail: &HashMap<String, String>) -> String {
+        let (id, name, pci, render, is_default, blocked, driver, managed, block_state) = row;
+        let from_detail = |key: &str| detail.get(key).cloned().unwrap_or_else(|| "?".to_string());
+        match self {
+            Column::Id => id.to_string(),
+            Column::Name => name.clone(),
+            Column::Pci => pci.clone(),
+            Column::Render => render.clone(),
+            Column::Card => from_detail("card"),
+            Column::Default => String::from(if *is_default { "yes" } else { "no" }),
+            Column::Blocked => blocked_label(*blocked, block_state).to_string(),
+            Column::Driver => driver.clone(),
+            Column::Managed => String::from(if *managed { "yes" } else { "NO" }),
+            Column::Temp => from_detail("temp_c"),
+            Column::Hits => from_detail("block_hits"),
+            Column::Nodes => match detail.get("nodes_present").map(String::as_str) {
+                Some("true") => "ok".to_string(),
+                Some("false") => "MISSING".to_string(),
+                _ => "?".to_string(),
+            },
+        }
+    }
+}
+
+fn format_table(headers: &[String], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(String::len).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let line = |cells: &[String]| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+    };
+    let separators: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+    let mut table = String::new();
+    for cells in [headers, &separators]
+        .into_iter()
+        .chain(rows.iter().map(Vec::as_slice))
+    {
+        table.push_str(&line(cells));
+        table.push('\n');
+    }
+    table
+}
+
+async fn format_gpu_table(
+    proxy: &zbus::Proxy<'static>,
+    rows: &[GpuRow],
+    columns: &[Column],
+) -> zbus::Result<String> {
+    let mut cells = Vec::with_capacity(rows.len());
+    for row in rows {
+        let detail: HashMap<String, String> = if columns.iter().any(|column| column.needs_detail())
+        {
+            proxy
+                .call::<_, _, Vec<(String, String)>>("GetGpuDetail", &(row.0,))
+                .await?
+                .into_iter()
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        cells.push(
+            columns
+                .iter()
+                .map(|column| column.cell(row, &detail))
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    let headers: Vec<String> = columns.iter().map(|column| column.header()).collect();
+    Ok(format_table(&headers, &cells))
+}
+
+/// Print `contents`, or write it to `output` when given.
+fn write_output(output: Option<&std::path::Path>, contents: &str) -> Result<(), String> {
+    match output {
+        Some(path) => std::fs::write(path, contents)
+            .map_err(|err| format!("Failed to write {}: {}", path.display(), err)),
+        None => {
+            print!("{}", contents);
+            Ok(())
+        }
+    }
+}
+
+fn print_key_values(detail: &[(String, String)]) {
+    let key_w = detail.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+    for (key, value) in detail {
+        println!("{:<key_w$}  {}", key, value, key_w = key_w);
+    }
+}
+
+#[derive(Clone, Copy)]
+enum TimeStyle {
+    Relative,
+    Raw,
+    Iso,
+}
+
+/// Wait up to `window` for a line on stdin. False on timeout or closed stdin.
+async fn wait_for_enter(window: std::time::Duration) -> bool {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    // A plain thread, so a read still blocked at exit doesn't hold up the runtime.
+    std::thread::spawn(move || {
+        let mut line = String::new();
+        let read = std::io::stdin().read_line(&mut line);
+        let _ = tx.send(matches!(read, Ok(n) if n > 0));
+    });
+    matches!(tokio::time::timeout(window, rx).await, Ok(Ok(true)))
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+}
+
+/// Two most significant units of a duration, e.g. "1h 12m", "3m 5s", "2d 4h".
+fn format_duration(secs: u64) -> String {
+    let units = [(86400, "d"), (3600, "h"), (60, "m"), (1, "s")];
+    let Some(first) = units.iter().position(|(size, _)| secs >= *size) else {
+        return "0s".to_string();
+    };
+    units[first..]
+        .iter()
+        .take(2)
+        .scan(secs, |rest, (size, unit)| {
+            let value = *rest / size;
+            *rest %= size;
+            Some(format!("{}{}", value, unit))
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// `YYYY-MM-DDTHH:MM:SSZ` for a Unix timestamp.
+fn format_iso(epoch: u64) -> String {
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let days = (epoch / 86400) as i64;
+    let secs = epoch % 86400;
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+fn format_time(epoch: u64, style: TimeStyle) -> String {
+    match style {
+        TimeStyle::Raw => epoch.to_string(),
+        TimeStyle::Iso => format_iso(epoch),
+        TimeStyle::Relative => {
+            let now = unix_now();
+            if epoch > now {
+                format!("in {}", format_duration(epoch - now))
+            } else {
+                format!("{} ago", format_duration(now - epoch))
+            }
+        }
+    }
+}
+
+/// Format the `*_at` (Unix timestamp) values of a key/value listing in `style`.
+fn format_time_fields(fields: &mut [(String, String)], style: TimeStyle) {
+    for (key, value) in fields.iter_mut() {
+        if key.ends_with("_at")
+            && let Ok(epoch) = value.parse()
+        {
+            *value = format_time(epoch, style);
+        }
+    }
+}
+
+/// Suggested `chainsawd.service` drop-in for a daemon running with `config` (the
+/// TOML from `GetEffectiveConfig`), reading its settings from `config_path`.
+fn unit_override(config: &str, config_path: &str, log_filter: &str) -> String {
+    // Top-level `key = value` lines; tables (`[gpu_aliases]`) come last.
+    let setting = |key: &str| {
+        config
+            .lines()
+            .take_while(|line| !line.starts_with('['))
+            .find_map(|line| {
+                let (name, value) = line.split_once('=')?;
+                (name.trim() == key).then(|| value.trim().trim_matches('"').to_string())
+            })
+            .unwrap_or_default()
+    };
+
+    let mut unit = String::from(
+        "# Suggested drop-in generated by `chainsaw config export-unit` from the running\n\
+         # daemon. It is only a suggestion: review it, save it as\n\
+         #   /etc/systemd/system/chainsawd.service.d/override.conf\n\
+         # and run `systemctl daemon-reload`. The settings themselves stay in the config\n\
+         # file below; deploy it too (`chainsaw config show` prints what's in effect).\n",
     );
+    unit.push_str("\n[Unit]\n");
+    unit.push_str(&format!("AssertPathExists={}\n", config_path));
+    if !setting("switcheroo_policy").is_empty() {
+        unit.push_str("# switcheroo_policy is set: start once switcheroo-control is up.\n");
+        unit.push_str("After=switcheroo-control.service\n");
+    }
 
-    for (id, name, pci, render, is_default, blocked) in rows {
-        println!(
-            "{:<id_w$}  {:<name_w$}  {:<pci_w$}  {:<render_w$}  {:<7}  {:<7}",
-            id,
-            name,
-            pci,
-            render,
-            if *is_default { "yes" } else { "no" },
-            if *blocked { "on*" } else { "off" },
-            id_w = id_w,
-            name_w = name_w,
-            pci_w = pci_w,
-            render_w = render_w,
+    unit.push_str("\n[Service]\n");
+    if !log_filter.is_empty() {
+        unit.push_str(&format!("Environment=RUST_LOG={}\n", log_filter));
+    }
+    if setting("require_enforcement") == "true" {
+        unit.push_str(
+            "# 3: another instance owns the bus name; 4: require_enforcement is set and the\n\
+             # eBPF blocker isn't fully attached. Restarting won't help either.\n",
         );
+        unit.push_str("RestartPreventExitStatus=3 4\n");
+    } else {
+        unit.push_str("# 3: another instance owns the bus name; restarting won't help.\n");
+        unit.push_str("RestartPreventExitStatus=3\n");
+    }
+    unit
+}
+
+/// Bytes as MiB, or GiB from 1 GiB up.
+fn format_bytes(bytes: u64) -> String {
+    const MIB: f64 = 1024.0 * 1024.0;
+    const GIB: f64 = MIB * 1024.0;
+    let bytes = bytes as f64;
+    if bytes >= GIB {
+        format!("{:.1} GiB", bytes / GIB)
+    } else {
+        format!("{:.0} MiB", bytes / MIB)
+    }
+}
+
+/// With `--dry-run`, print the D-Bus call a command would make instead of making
+/// it. Returns whether the call must be skipped.
+fn skip_call(dry_run: bool, method: &str, body: &impl std::fmt::Debug) -> bool {
+    if dry_run {
+        // Tuples print as `(2, true)`, or `(2,)` with one element.
+        let body = format!("{:?}", body);
+        let body = body
+            .strip_suffix(",)")
+            .map_or(body.clone(), |args| format!("{})", args));
+        println!("would call {}{}", method, body);
+    }
+    dry_run
+}
+
+/// What `mode` would block, from the daemon's preview, for `set --dry-run`.
+async fn print_mode_preview(
+    proxy: &zbus::Proxy<'static>,
+    mode: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let previews: Vec<(String, Vec<(u32, bool)>)> = proxy.call("PreviewAllModes", &()).await?;
+    match previews.into_iter().find(|(name, _)| name == mode) {
+        Some((_, decisions)) => {
+            for (id, blocked) in decisions {
+                println!(
+                    "  GPU {}: {}",
+                    id,
+                    if blocked { "blocked" } else { "not blocked" }
+                );
+            }
+            print_session_restart_note(&session_restart_reasons(proxy, mode).await);
+        }
+        None => println!("  {} isn't a known mode, the daemon would reject it", mode),
+    }
+    Ok(())
+}
+
+/// Why switching to `mode` needs a session restart to fully apply, from
+/// `PreviewMode`. Empty when it doesn't, or when the daemon can't tell.
+async fn session_restart_reasons(proxy: &zbus::Proxy<'static>, mode: &str) -> Vec<String> {
+    proxy
+        .call::<_, _, (Vec<(u32, bool)>, bool, Vec<String>)>("PreviewMode", &(mode,))
+        .await
+        .map(|(_, _, reasons)| reasons)
+        .unwrap_or_default()
+}
+
+fn print_session_restart_note(reasons: &[String]) {
+    for reason in reasons {
+        eprintln!("Note: {}", reason);
+    }
+}
+
+/// `value` as a JSON string literal. Unlike `{:?}`, safe for arbitrary text such as
+/// log messages.
+fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            ch if u32::from(ch) < 0x20 => quoted.push_str(&format!("\\u{:04x}", u32::from(ch))),
+            ch => quoted.push(ch),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn parse_state(state: &str) -> Result<bool, String> {
+    match state {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("Invalid state '{}'. Expected: on or off", state)),
+    }
+}
+
+/// `ListEnforcementCoverage` as a comma-separated list, e.g. "render, card, pci".
+fn coverage_summary(routes: &[String]) -> String {
+    if routes.is_empty() {
+        "none (hook not attached)".to_string()
+    } else {
+        routes.join(", ")
+    }
+}
+
+/// One-line summary of `GetBlockerStatus`, e.g. "eBPF: attached (file_open LSM)".
+fn blocker_summary(blocker: &[(String, String)]) -> String {
+    let field = |key: &str| {
+        blocker
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+            .unwrap_or("?")
+    };
+    format!("eBPF: {} ({})", field("status"), field("hook"))
+}
+
+/// Turn `gpu` arguments into numeric ids: PCI addresses (anything with a `:`) are
+/// looked up with `GetGpuByPci`, other non-numbers as `gpu_aliases` labels.
+async fn resolve_gpu_ids(
+    proxy: &zbus::Proxy<'static>,
+    args: &[String],
+) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+    let mut ids = Vec::with_capacity(args.len());
+    for arg in args {
+        if let Ok(id) = arg.parse() {
+            ids.push(id);
+            continue;
+        }
+        let method = if arg.contains(':') {
+            "GetGpuByPci"
+        } else {
+            "GetGpuByAlias"
+        };
+        let detail: Vec<(String, String)> = proxy.call(method, &(arg,)).await?;
+        let id = detail
+            .iter()
+            .find(|(key, _)| key == "id")
+            .and_then(|(_, value)| value.parse().ok())
+            .ok_or_else(|| format!("Daemon returned no id for GPU {}", arg))?;
+        ids.push(id);
+    }
+    Ok(ids)
+}
+
+async fn connect(bus: Bus, address: Option<&str>) -> zbus::Result<zbus::Proxy<'static>> {
+    let target = match (address, bus) {
+        (Some(address), _) => Target::Address(address.to_string()),
+        (None, Bus::System) => Target::System,
+        (None, Bus::Session) => Target::Session,
+    };
+    chainsaw_client::connect(&target).await
+}
+
+/// Status bar token, icon and class for a mode; `None` is the unreachable state.
+fn mode_indicator(mode: Option<&str>) -> (String, &'static str, String) {
+    match mode {
+        Some("integrated") => ("INT".to_string(), "🔋", "integrated".to_string()),
+        Some("hybrid") => ("HYB".to_string(), "⚡", "hybrid".to_string()),
+        Some("gaming") => ("GAM".to_string(), "🎮", "gaming".to_string()),
+        Some(other) => (
+            other.chars().take(3).collect::<String>().to_uppercase(),
+            "⚙",
+            other.to_string(),
+        ),
+        None => ("ERR".to_string(), "⚠", "error".to_string()),
+    }
+}
+
+/// Print the mode indicator. Connects directly, without the retrying client or any
+/// GPU enumeration, so it stays fast when polled by a bar.
+async fn print_mode_indicator(
+    bus: Bus,
+    address: Option<&str>,
+    format: IndicatorFormat,
+    icon: bool,
+) {
+    let mode = match connect(bus, address).await {
+        Ok(proxy) => proxy.call::<_, _, String>("GetMode", &()).await.ok(),
+        Err(_) => None,
+    };
+    let (token, glyph, class) = mode_indicator(mode.as_deref());
+    let text = if icon {
+        format!("{} {}", glyph, token)
+    } else {
+        token
+    };
+    match format {
+        IndicatorFormat::Text => println!("{}", text),
+        IndicatorFormat::Json => {
+            let tooltip = match &mode {
+                Some(mode) => format!("chainsaw: {}", mode),
+                None => "chainsaw: daemon unreachable".to_string(),
+            };
+            // Debug-formatted strings are valid JSON strings for these values.
+            println!(
+                "{{\"text\":{:?},\"class\":{:?},\"tooltip\":{:?}}}",
+                text, class, tooltip
+            );
+        }
     }
 }
 
@@ -99,49 +793,476 @@ fn print_gpu_table(rows: &[GpuRow]) {
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
-    let connection = zbus::connection::Builder::system()?.build().await?;
+    if let Commands::Doctor { expect_bpf_hash } = &args.command {
+        return doctor::run(
+            args.bus,
+            args.address.as_deref(),
+            expect_bpf_hash.as_deref(),
+        )
+        .await;
+    }
+    if let Commands::ModeIndicator { format, icon } = args.command {
+        print_mode_indicator(args.bus, args.address.as_deref(), format, icon).await;
+        return Ok(());
+    }
 
-    let proxy = zbus::Proxy::new(
-        &connection,
-        "com.chainsaw.daemon",
-        "/com/chainsaw/daemon",
-        "com.chainsaw.daemon",
-    )
-    .await?;
+    let proxy = connect(args.bus, args.address.as_deref()).await?;
+    let dry_run = args.dry_run;
+    let time_style = match (args.raw, args.iso) {
+        (true, _) => TimeStyle::Raw,
+        (_, true) => TimeStyle::Iso,
+        _ => TimeStyle::Relative,
+    };
 
     match args.command {
-        Commands::Set { mode } => {
+        Commands::Set {
+            mode,
+            keep: Some(keep),
+            ..
+        } => {
+            let response: String = if keep == "boot" {
+                if skip_call(dry_run, "ClearModeKeep", &()) {
+                    skip_call(dry_run, "SetMode", &(&mode,));
+                    return print_mode_preview(&proxy, &mode).await;
+                }
+                let cleared: String = proxy.call("ClearModeKeep", &()).await?;
+                println!("{}", cleared);
+                proxy.call("SetMode", &(mode,)).await?
+            } else {
+                let ids = resolve_gpu_ids(&proxy, std::slice::from_ref(&keep)).await?;
+                // The preview doesn't know about the kept GPU, so there's none here.
+                if skip_call(dry_run, "SetModeKeeping", &(&mode, ids[0])) {
+                    return Ok(());
+                }
+                proxy.call("SetModeKeeping", &(mode, ids[0])).await?
+            };
+            println!("{}", response);
+        }
+        Commands::Set {
+            mode,
+            confirm: Some(window),
+            ..
+        } => {
+            let previous: String = proxy.call("GetMode", &()).await?;
+            if skip_call(dry_run, "SetModeTimed", &(&mode, window)) {
+                println!("then, once Enter is pressed in time:");
+                skip_call(dry_run, "SetMode", &(&mode,));
+                return print_mode_preview(&proxy, &mode).await;
+            }
+            let response: String = proxy.call("SetModeTimed", &(mode.clone(), window)).await?;
+            println!("{}", response);
+            println!(
+                "Press Enter within {}s to keep {}, otherwise reverting to {}.",
+                window, mode, previous
+            );
+            if !wait_for_enter(std::time::Duration::from_secs(window)).await {
+                return Err(format!("Not confirmed, reverting to {}", previous).into());
+            }
             let response: String = proxy.call("SetMode", &(mode,)).await?;
+            println!("Kept: {}", response);
+        }
+        Commands::Set { mode, duration, .. } => {
+            let skip = match duration {
+                Some(duration) => skip_call(dry_run, "SetModeTimed", &(&mode, duration)),
+                None => skip_call(dry_run, "SetMode", &(&mode,)),
+            };
+            if skip {
+                return print_mode_preview(&proxy, &mode).await;
+            }
+            // Asked before switching: afterwards there's nothing left to change.
+            let restart_reasons = session_restart_reasons(&proxy, &mode).await;
+            let response: String = match duration {
+                Some(duration) => proxy.call("SetModeTimed", &(mode, duration)).await?,
+                None => proxy.call("SetMode", &(mode,)).await?,
+            };
+            println!("{}", response);
+            if let Some(duration) = duration {
+                println!("Reverts {}", format_time(unix_now() + duration, time_style));
+            }
+            print_session_restart_note(&restart_reasons);
+        }
+        Commands::Confirm => {
+            if skip_call(dry_run, "ConfirmModeOk", &()) {
+                return Ok(());
+            }
+            let response: String = proxy.call("ConfirmModeOk", &()).await?;
             println!("{}", response);
         }
         Commands::Get => {
             let current_mode: String = proxy.call("GetMode", &()).await?;
             println!("Current gpu mode: {}", current_mode);
         }
-        Commands::List => {
+        Commands::List { columns, output } => {
+            let columns = match columns {
+                Some(names) => names
+                    .iter()
+                    .map(|name| Column::parse(name))
+                    .collect::<Result<Vec<_>, _>>()?,
+                None => DEFAULT_COLUMNS.to_vec(),
+            };
             let mut response: Vec<GpuRow> = proxy.call("ListGpus", &()).await?;
+            if response.is_empty() {
+                eprintln!("{}", NO_GPUS_MESSAGE);
+                std::process::exit(EXIT_NO_GPUS);
+            }
             response.sort_by_key(|row| row.0);
-            print_gpu_table(&response);
-        }
-        Commands::Gpu { id, command } => match command {
-            GpuCommands::Block { state } => {
-                let block = match state.as_str() {
-                    "on" => true,
-                    "off" => false,
-                    _ => {
-                        return Err(format!("Invalid state '{}'. Expected: on or off", state).into());
+            let table = format_gpu_table(&proxy, &response, &columns).await?;
+            write_output(output.as_deref(), &table)?;
+        }
+        Commands::Gpu { ids, command } => {
+            let ids = resolve_gpu_ids(&proxy, &ids).await?;
+            match (command, ids.as_slice()) {
+                (
+                    GpuCommands::Block {
+                        state,
+                        all_functions,
+                    },
+                    [id],
+                ) => {
+                    let block = parse_state(&state)?;
+                    if skip_call(dry_run, "SetGpuBlock", &(*id, block, all_functions)) {
+                        return Ok(());
+                    }
+                    let response: String = proxy
+                        .call("SetGpuBlock", &(*id, block, all_functions))
+                        .await?;
+                    println!("{}", response);
+                }
+                (
+                    GpuCommands::Block {
+                        all_functions: true,
+                        ..
+                    },
+                    _,
+                ) => {
+                    return Err("--all-functions takes a single GPU id".into());
+                }
+                (GpuCommands::Block { state, .. }, _) => {
+                    let block = parse_state(&state)?;
+                    if skip_call(dry_run, "SetGpuBlocks", &(&ids, block)) {
+                        return Ok(());
+                    }
+                    let results: Vec<(u32, bool, String)> =
+                        proxy.call("SetGpuBlocks", &(&ids, block)).await?;
+                    let mut failed = 0;
+                    for (id, ok, message) in &results {
+                        println!("GPU {}: {}", id, message);
+                        if !ok {
+                            failed += 1;
+                        }
+                    }
+                    if failed > 0 {
+                        return Err(format!("{} of {} GPU(s) failed", failed, results.len()).into());
+                    }
+                }
+                (GpuCommands::Exclusive, [id]) => {
+                    if skip_call(dry_run, "SetExclusiveGpu", &(*id,)) {
+                        return Ok(());
                     }
-                };
-                let response: String = proxy.call("SetGpuBlock", &(id, block)).await?;
-                println!("{}", response);
+                    let response: String = proxy.call("SetExclusiveGpu", &(*id,)).await?;
+                    println!("{}", response);
+                }
+                (GpuCommands::Profile { level: None }, [id]) => {
+                    let level: String = proxy.call("GetGpuPowerProfile", &(*id,)).await?;
+                    println!("{}", level);
+                }
+                (GpuCommands::Profile { level: Some(level) }, [id]) => {
+                    if skip_call(dry_run, "SetGpuPowerProfile", &(*id, &level)) {
+                        return Ok(());
+                    }
+                    let response: String = proxy.call("SetGpuPowerProfile", &(*id, level)).await?;
+                    println!("{}", response);
+                }
+                (GpuCommands::Displays, [id]) => {
+                    let connectors: Vec<(String, String, String)> =
+                        proxy.call("GetGpuConnectors", &(*id,)).await?;
+                    if connectors.is_empty() {
+                        println!(
+                            "GPU {} has no display connectors (headless, or no DRM driver)",
+                            id
+                        );
+                    } else {
+                        let headers = ["NAME", "TYPE", "STATUS"].map(String::from);
+                        let rows: Vec<Vec<String>> = connectors
+                            .into_iter()
+                            .map(|(name, kind, status)| vec![name, kind, status])
+                            .collect();
+                        print!("{}", format_table(&headers, &rows));
+                    }
+                }
+                (GpuCommands::Attr { name }, [id]) => {
+                    let value: String = proxy.call("ReadGpuAttribute", &(*id, name)).await?;
+                    println!("{}", value);
+                }
+                (GpuCommands::Mem, [id]) => {
+                    let (used, total): (u64, u64) = proxy.call("GetGpuVram", &(*id,)).await?;
+                    let percent = if total > 0 {
+                        used as f64 * 100.0 / total as f64
+                    } else {
+                        0.0
+                    };
+                    println!(
+                        "{} / {} ({:.0}%)",
+                        format_bytes(used),
+                        format_bytes(total),
+                        percent
+                    );
+                }
+                (GpuCommands::Info, [id]) => {
+                    let mut detail: Vec<(String, String)> =
+                        proxy.call("GetGpuDetail", &(*id,)).await?;
+                    format_time_fields(&mut detail, time_style);
+                    print_key_values(&detail);
+                }
+                (GpuCommands::Run { command }, [id]) => {
+                    use std::os::unix::process::CommandExt;
+
+                    let env: HashMap<String, String> = proxy.call("GetOffloadEnv", &(*id,)).await?;
+                    let (program, program_args) =
+                        command.split_first().ok_or("No command given")?;
+                    // Only returns on failure.
+                    let err = std::process::Command::new(program)
+                        .args(program_args)
+                        .envs(&env)
+                        .exec();
+                    return Err(format!("Failed to run {}: {}", program, err).into());
+                }
+                (_, _) => return Err("Only `block` accepts several GPU ids".into()),
             }
-        },
-        Commands::ListModes => {
-            let response: Vec<String> = proxy.call("ListMode", &()).await?;
+        }
+        Commands::ListModes { detailed: true, .. } => {
+            let modes: Vec<(String, String)> = proxy.call("ListModesDetailed", &()).await?;
+            print_key_values(&modes);
+        }
+        Commands::ListModes { all, .. } => {
+            let method = if all { "ListAllModes" } else { "ListMode" };
+            let response: Vec<String> = proxy.call(method, &()).await?;
             for mode in response {
                 println!("{}", mode);
             }
         }
+        Commands::Status => {
+            let mut status: Vec<(String, String)> = proxy.call("GetStatus", &()).await?;
+            format_time_fields(&mut status, time_style);
+            print_key_values(&status);
+            if status
+                .iter()
+                .any(|(key, value)| key == "gpu_count" && value == "0")
+            {
+                println!();
+                println!("{}", NO_GPUS_MESSAGE);
+            }
+            let blocker: Vec<(String, String)> = proxy.call("GetBlockerStatus", &()).await?;
+            println!();
+            println!("{}", blocker_summary(&blocker));
+            print_key_values(&blocker);
+            // Older daemons don't report coverage.
+            if let Ok(routes) = proxy
+                .call::<_, _, Vec<String>>("ListEnforcementCoverage", &())
+                .await
+            {
+                println!("coverage: {}", coverage_summary(&routes));
+            }
+        }
+        Commands::Cgroup { path, state } => {
+            let block = parse_state(&state)?;
+            if skip_call(dry_run, "SetCgroupBlock", &(&path, block)) {
+                return Ok(());
+            }
+            let response: String = proxy.call("SetCgroupBlock", &(path, block)).await?;
+            println!("{}", response);
+        }
+        Commands::PciPattern { pattern, state } => {
+            let method = if parse_state(&state)? {
+                "BlockPciPattern"
+            } else {
+                "UnblockPciPattern"
+            };
+            if skip_call(dry_run, method, &(&pattern,)) {
+                return Ok(());
+            }
+            let changed: Vec<String> = proxy.call(method, &(pattern,)).await?;
+            if changed.is_empty() {
+                println!("No PCI function matches right now");
+            } else {
+                for address in changed {
+                    println!("{}", address);
+                }
+            }
+        }
+        Commands::Driver {
+            name,
+            command: DriverCommands::Block { state },
+        } => {
+            let block = parse_state(&state)?;
+            if skip_call(dry_run, "BlockByDriver", &(&name, block)) {
+                return Ok(());
+            }
+            let changed: Vec<u32> = proxy.call("BlockByDriver", &(name.clone(), block)).await?;
+            if changed.is_empty() {
+                println!("No GPU uses {} right now", name);
+            } else {
+                let state = if block { "blocked" } else { "unblocked" };
+                println!("{} GPUs {:?} ({})", state, changed, name);
+            }
+        }
+        Commands::Pause {
+            duration: Some(duration),
+        } => {
+            if skip_call(dry_run, "PauseFor", &(duration,)) {
+                return Ok(());
+            }
+            let response: String = proxy.call("PauseFor", &(duration,)).await?;
+            println!("{}", response);
+        }
+        Commands::Pause { duration: None } | Commands::Resume => {
+            let enabled = matches!(args.command, Commands::Resume);
+            if skip_call(dry_run, "SetBlockingEnabled", &(enabled,)) {
+                return Ok(());
+            }
+            let response: String = proxy.call("SetBlockingEnabled", &(enabled,)).await?;
+            println!("{}", response);
+        }
+        Commands::Config {
+            command: ConfigCommands::Show,
+        } => {
+            let config: String = proxy.call("GetEffectiveConfig", &()).await?;
+            print!("{}", config);
+        }
+        Commands::Config {
+            command: ConfigCommands::ExportUnit,
+        } => {
+            let config: String = proxy.call("GetEffectiveConfig", &()).await?;
+            let config_path: String = proxy.call("GetConfigPath", &()).await?;
+            let log_filter: String = proxy.call("GetLogFilter", &()).await?;
+            print!("{}", unit_override(&config, &config_path, &log_filter));
+        }
+        Commands::ReleaseFramebuffer => {
+            if skip_call(dry_run, "ReleaseBootFramebuffer", &()) {
+                return Ok(());
+            }
+            let response: String = proxy.call("ReleaseBootFramebuffer", &()).await?;
+            println!("{}", response);
+        }
+        Commands::Doctor { .. } | Commands::ModeIndicator { .. } => {
+            unreachable!("handled before connecting")
+        }
+        Commands::Export { output } => {
+            let state: String = proxy.call("ExportState", &()).await?;
+            write_output(output.as_deref(), &state)?;
+        }
+        Commands::Import { path } => {
+            let data = std::fs::read_to_string(&path)
+                .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+            let summary = format!("<{} bytes from {}>", data.len(), path.display());
+            if skip_call(dry_run, "ImportState", &(summary,)) {
+                return Ok(());
+            }
+            let response: String = proxy.call("ImportState", &(data,)).await?;
+            println!("{}", response);
+        }
+        Commands::Log { lines, json_lines } => {
+            let events: Vec<(u64, String, String)> =
+                proxy.call("GetRecentEvents", &(lines,)).await?;
+            if json_lines {
+                let mut stdout = std::io::stdout().lock();
+                for (timestamp, level, message) in events {
+                    writeln!(
+                        stdout,
+                        "{{\"time\":{},\"level\":{},\"message\":{}}}",
+                        timestamp,
+                        json_string(&level),
+                        json_string(&message)
+                    )?;
+                    // Consumers see each record as soon as it's written.
+                    stdout.flush()?;
+                }
+                return Ok(());
+            }
+            if events.is_empty() {
+                println!("No events recorded since the daemon started");
+            }
+            for (timestamp, level, message) in events {
+                println!(
+                    "{}  {:<5}  {}",
+                    format_time(timestamp, time_style),
+                    level,
+                    message
+                );
+            }
+        }
+        Commands::Debug {
+            command: DebugCommands::Bench,
+        } => {
+            let timings: Vec<(String, u64)> = proxy.call("DebugBench", &()).await?;
+            let timings: Vec<(String, String)> = timings
+                .into_iter()
+                .map(|(name, micros)| (name, format!("{} µs", micros)))
+                .collect();
+            print_key_values(&timings);
+        }
+        Commands::Debug {
+            command: DebugCommands::Dump,
+        } => {
+            let details: Vec<Vec<(String, String)>> = proxy.call("ListGpusDetailed", &()).await?;
+            for (index, mut detail) in details.into_iter().enumerate() {
+                if index > 0 {
+                    println!();
+                }
+                let id = detail
+                    .iter()
+                    .find(|(key, _)| key == "id")
+                    .map_or("?".to_string(), |(_, id)| id.clone());
+                println!("GPU {}", id);
+                format_time_fields(&mut detail, time_style);
+                print_key_values(&detail);
+            }
+        }
+        Commands::Debug {
+            command: DebugCommands::Diff { json },
+        } => {
+            let rows: Vec<(u32, String, String, String, bool, String)> =
+                proxy.call("GetBlockDiff", &()).await?;
+            if json {
+                let entries: Vec<String> = rows
+                    .iter()
+                    .map(|(id, pci, expected, actual, matches, entries)| {
+                        format!(
+                            "{{\"id\":{},\"pci\":{},\"expected\":{},\"actual\":{},\"match\":{},\"entries\":{}}}",
+                            id,
+                            json_string(pci),
+                            json_string(expected),
+                            json_string(actual),
+                            matches,
+                            json_string(entries)
+                        )
+                    })
+                    .collect();
+                println!("[{}]", entries.join(","));
+            } else {
+                let headers =
+                    ["ID", "PCI", "EXPECTED", "ACTUAL", "STATUS", "ENTRIES"].map(String::from);
+                let rows: Vec<Vec<String>> = rows
+                    .into_iter()
+                    .map(|(id, pci, expected, actual, matches, entries)| {
+                        let status = if matches { "MATCH" } else { "DIFFER" };
+                        vec![
+                            id.to_string(),
+                            pci,
+                            expected,
+                            actual,
+                            status.to_string(),
+                            entries,
+                        ]
+                    })
+                    .collect();
+                print!("{}", format_table(&headers, &rows));
+            }
+        }
+        #[cfg(feature = "tui")]
+        Commands::Tui => {
+            tui::run(proxy, args.bus, args.address).await?;
+        }
     }
 
     Ok(())
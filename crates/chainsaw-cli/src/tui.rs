@@ -0,0 +1,292 @@
+//! `chainsaw tui`: live GPU table with block and mode controls.
+
+use std::future::pending;
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{
+    Block, Clear, List, ListItem, ListState, Paragraph, Row, Table, TableState,
+};
+use ratatui::{DefaultTerminal, Frame};
+use tokio::sync::mpsc;
+use zbus::proxy::SignalStream;
+
+use crate::{Bus, GpuRow, blocked_label, connect};
+
+// Fallback refresh in case a signal is missed.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+const KEY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+enum Action {
+    None,
+    Quit,
+    Reconnect,
+}
+
+struct App {
+    rows: Vec<GpuRow>,
+    mode: String,
+    table: TableState,
+    // Open mode menu: available modes and selection.
+    mode_menu: Option<(Vec<String>, ListState)>,
+    status: String,
+    connected: bool,
+}
+
+impl App {
+    fn new() -> Self {
+        Self {
+            rows: Vec::new(),
+            mode: String::from("?"),
+            table: TableState::default().with_selected(Some(0)),
+            mode_menu: None,
+            status: String::new(),
+            connected: true,
+        }
+    }
+
+    fn disconnected(&mut self, err: zbus::Error) {
+        self.connected = false;
+        self.status = format!("Daemon unavailable ({}), press r to reconnect", err);
+    }
+
+    async fn refresh(&mut self, proxy: &zbus::Proxy<'static>) {
+        let rows: zbus::Result<Vec<GpuRow>> = proxy.call("ListGpus", &()).await;
+        let mode: zbus::Result<String> = proxy.call("GetMode", &()).await;
+        match (rows, mode) {
+            (Ok(mut rows), Ok(mode)) => {
+                rows.sort_by_key(|row| row.0);
+                self.rows = rows;
+                self.mode = mode;
+                if !self.connected {
+                    self.connected = true;
+                    self.status = String::from("Reconnected");
+                }
+                if self.table.selected().is_none_or(|i| i >= self.rows.len()) {
+                    self.table.select(Some(0));
+                }
+            }
+            (Err(err), _) | (_, Err(err)) => self.disconnected(err),
+        }
+    }
+
+    async fn handle_key(&mut self, key: KeyEvent, proxy: &zbus::Proxy<'static>) -> Action {
+        if let Some((modes, state)) = &mut self.mode_menu {
+            match key.code {
+                KeyCode::Up | KeyCode::Char('k') => state.select_previous(),
+                KeyCode::Down | KeyCode::Char('j') => state.select_next(),
+                KeyCode::Esc | KeyCode::Char('m') => self.mode_menu = None,
+                KeyCode::Enter => {
+                    let mode = state.selected().and_then(|i| modes.get(i)).cloned();
+                    self.mode_menu = None;
+                    if let Some(mode) = mode {
+                        match proxy.call::<_, _, String>("SetMode", &(mode,)).await {
+                            Ok(response) => self.status = response,
+                            Err(err) => self.disconnected(err),
+                        }
+                        self.refresh(proxy).await;
+                    }
+                }
+                _ => {}
+            }
+            return Action::None;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Action::Quit,
+            KeyCode::Char('r') if !self.connected => return Action::Reconnect,
+            KeyCode::Up | KeyCode::Char('k') => self.table.select_previous(),
+            KeyCode::Down | KeyCode::Char('j') => self.table.select_next(),
+            KeyCode::Char('b') | KeyCode::Char(' ') => {
+                let selected = self.table.selected().and_then(|i| self.rows.get(i));
+                if let Some((id, _, _, _, _, blocked, _, _, state)) = selected {
+                    // A partial block toggles to fully blocked.
+                    let request = (*id, !*blocked || state == "partial", false);
+                    match proxy.call::<_, _, String>("SetGpuBlock", &request).await {
+                        Ok(response) => self.status = response,
+                        Err(err) => self.disconnected(err),
+                    }
+                }
+            }
+            KeyCode::Char('m') => match proxy.call::<_, _, Vec<String>>("ListMode", &()).await {
+                Ok(modes) => {
+                    let current = modes.iter().position(|mode| *mode == self.mode);
+                    let state = ListState::default().with_selected(current.or(Some(0)));
+                    self.mode_menu = Some((modes, state));
+                }
+                Err(err) => self.disconnected(err),
+            },
+            _ => {}
+        }
+        Action::None
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header, body, status, help] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(
+            Paragraph::new(format!("chainsaw  mode: {}", self.mode))
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+            header,
+        );
+
+        let rows = self.rows.iter().map(
+            |(id, name, pci, render, is_default, blocked, driver, managed, state)| {
+                let row = Row::new(vec![
+                    id.to_string(),
+                    name.clone(),
+                    pci.clone(),
+                    render.clone(),
+                    String::from(if *is_default { "yes" } else { "no" }),
+                    blocked_label(*blocked, state).to_string(),
+                    driver.clone(),
+                ]);
+                if *managed {
+                    row
+                } else {
+                    row.style(Style::default().add_modifier(Modifier::DIM))
+                }
+            },
+        );
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(3),
+                Constraint::Fill(1),
+                Constraint::Length(12),
+                Constraint::Length(20),
+                Constraint::Length(7),
+                Constraint::Length(7),
+                Constraint::Length(10),
+            ],
+        )
+        .header(
+            Row::new([
+                "ID", "NAME", "PCI", "RENDER", "DEFAULT", "BLOCKED", "DRIVER",
+            ])
+            .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::bordered().title("GPUs"))
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, body, &mut self.table);
+
+        frame.render_widget(Paragraph::new(self.status.as_str()), status);
+        frame.render_widget(
+            Paragraph::new("↑/↓ select  b toggle block  m mode  r reconnect  q quit"),
+            help,
+        );
+
+        if let Some((modes, state)) = &mut self.mode_menu {
+            let area = centered(body, 30, modes.len() as u16 + 2);
+            let items: Vec<ListItem> = modes.iter().map(|m| ListItem::new(m.as_str())).collect();
+            let list = List::new(items)
+                .block(Block::bordered().title("Set mode"))
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+            frame.render_widget(Clear, area);
+            frame.render_stateful_widget(list, area, state);
+        }
+    }
+}
+
+fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+/// Forward terminal key presses from a blocking reader thread.
+fn spawn_key_reader() -> mpsc::Receiver<KeyEvent> {
+    let (tx, rx) = mpsc::channel(16);
+    tokio::task::spawn_blocking(move || {
+        while !tx.is_closed() {
+            match event::poll(KEY_POLL_INTERVAL) {
+                Ok(true) => {
+                    if let Ok(Event::Key(key)) = event::read()
+                        && key.kind == KeyEventKind::Press
+                        && tx.blocking_send(key).is_err()
+                    {
+                        break;
+                    }
+                }
+                Ok(false) => {}
+                Err(_) => break,
+            }
+        }
+    });
+    rx
+}
+
+async fn next_signal(signals: &mut Option<SignalStream<'static>>) {
+    match signals {
+        Some(stream) => {
+            if stream.next().await.is_none() {
+                *signals = None;
+            }
+        }
+        None => pending().await,
+    }
+}
+
+async fn event_loop(
+    terminal: &mut DefaultTerminal,
+    mut proxy: zbus::Proxy<'static>,
+    bus: Bus,
+    address: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut app = App::new();
+    let mut keys = spawn_key_reader();
+    let mut signals = proxy.receive_signal("GpuBlockChanged").await.ok();
+    let mut tick = tokio::time::interval(REFRESH_INTERVAL);
+
+    app.refresh(&proxy).await;
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+
+        tokio::select! {
+            key = keys.recv() => {
+                let Some(key) = key else { break };
+                match app.handle_key(key, &proxy).await {
+                    Action::None => {}
+                    Action::Quit => break,
+                    Action::Reconnect => match connect(bus, address.as_deref()).await {
+                        Ok(new_proxy) => {
+                            proxy = new_proxy;
+                            signals = proxy.receive_signal("GpuBlockChanged").await.ok();
+                            app.refresh(&proxy).await;
+                        }
+                        Err(err) => app.disconnected(err),
+                    },
+                }
+            }
+            _ = next_signal(&mut signals) => app.refresh(&proxy).await,
+            _ = tick.tick() => app.refresh(&proxy).await,
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn run(
+    proxy: zbus::Proxy<'static>,
+    bus: Bus,
+    address: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, proxy, bus, address).await;
+    ratatui::restore();
+    result
+}
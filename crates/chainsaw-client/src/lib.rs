@@ -0,0 +1,226 @@
+//! Client for the chainsaw daemon D-Bus API that survives daemon restarts.
+//!
+//! Calls that fail because the daemon (or the connection to it) went away rebuild the
+//! proxy and retry a bounded number of times before reporting `ClientError::Unavailable`.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+use zbus::zvariant::{DynamicDeserialize, DynamicType};
+use zbus::{Proxy, fdo};
+
+pub const BUS_NAME: &str = "com.chainsaw.daemon";
+pub const OBJECT_PATH: &str = "/com/chainsaw/daemon";
+pub const INTERFACE: &str = "com.chainsaw.daemon";
+
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Address transports `parse_address` accepts.
+pub const SUPPORTED_TRANSPORTS: &[&str] = &["unix", "tcp", "tcp6", "nonce-tcp"];
+
+// D-Bus errors meaning "nobody is serving the name right now".
+const UNAVAILABLE_ERRORS: &[&str] = &[
+    "org.freedesktop.DBus.Error.ServiceUnknown",
+    "org.freedesktop.DBus.Error.NameHasNoOwner",
+    "org.freedesktop.DBus.Error.NoReply",
+    "org.freedesktop.DBus.Error.Disconnected",
+];
+
+/// Where the daemon listens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    System,
+    Session,
+    /// Custom D-Bus address, e.g. `unix:path=/tmp/chainsaw-bus` or
+    /// `tcp:host=gpu-server,port=55556` (see `parse_address`).
+    Address(String),
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// The daemon couldn't be reached, even after retrying.
+    Unavailable { attempts: u32, last: zbus::Error },
+    /// The daemon answered with an error.
+    Call(zbus::Error),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Unavailable { attempts, last } => write!(
+                f,
+                "daemon unavailable after {} attempt(s): {}",
+                attempts, last
+            ),
+            ClientError::Call(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+/// Check a custom address for `Target::Address` and return it in the form zbus
+/// takes. `tcp6:` is shorthand for `tcp:` with `family=ipv6`; TCP addresses need
+/// `host` and `port`.
+pub fn parse_address(address: &str) -> Result<String, String> {
+    let (transport, options) = address
+        .trim()
+        .split_once(':')
+        .ok_or_else(|| format!("'{}' has no transport, e.g. tcp:host=...", address))?;
+    if !SUPPORTED_TRANSPORTS.contains(&transport) {
+        return Err(format!(
+            "unsupported D-Bus transport '{}' (supported: {})",
+            transport,
+            SUPPORTED_TRANSPORTS.join(", ")
+        ));
+    }
+    if transport == "unix" {
+        return Ok(address.trim().to_string());
+    }
+
+    let has_option = |key: &str| {
+        options
+            .split(',')
+            .any(|option| option.split_once('=').is_some_and(|(name, _)| name == key))
+    };
+    for key in ["host", "port"] {
+        if !has_option(key) {
+            return Err(format!(
+                "{} address '{}' needs {}=..., e.g. {}:host=gpu-server,port=55556",
+                transport, address, key, transport
+            ));
+        }
+    }
+    match transport {
+        "tcp6" if has_option("family") => Err(format!(
+            "tcp6 address '{}' already implies family=ipv6",
+            address
+        )),
+        "tcp6" => Ok(format!("tcp:{},family=ipv6", options)),
+        _ => Ok(format!("{}:{}", transport, options)),
+    }
+}
+
+/// Build a proxy to the daemon on `target`.
+pub async fn connect(target: &Target) -> zbus::Result<Proxy<'static>> {
+    let conn_builder = match target {
+        Target::Address(address) => zbus::connection::Builder::address(address.as_str())?,
+        Target::System => zbus::connection::Builder::system()?,
+        Target::Session => zbus::connection::Builder::session()?,
+    };
+    let connection = conn_builder.build().await?;
+
+    Proxy::new(&connection, BUS_NAME, OBJECT_PATH, INTERFACE).await
+}
+
+/// Whether `err` means the daemon or the connection went away, as opposed to the
+/// daemon rejecting the call.
+fn is_unavailable(err: &zbus::Error) -> bool {
+    match err {
+        zbus::Error::InputOutput(_) => true,
+        zbus::Error::FDO(fdo_err) => matches!(
+            **fdo_err,
+            fdo::Error::ServiceUnknown(_)
+                | fdo::Error::NameHasNoOwner(_)
+                | fdo::Error::NoReply(_)
+                | fdo::Error::Disconnected(_)
+                | fdo::Error::IOError(_)
+        ),
+        zbus::Error::MethodError(name, _, _) => UNAVAILABLE_ERRORS.contains(&name.as_str()),
+        _ => false,
+    }
+}
+
+pub struct Client {
+    target: Target,
+    proxy: Mutex<Option<Proxy<'static>>>,
+    retries: u32,
+    retry_delay: Duration,
+}
+
+impl Client {
+    /// Client for `target`. Nothing is connected until the first call.
+    pub fn new(target: Target) -> Self {
+        Self {
+            target,
+            proxy: Mutex::new(None),
+            retries: DEFAULT_RETRIES,
+            retry_delay: DEFAULT_RETRY_DELAY,
+        }
+    }
+
+    /// Retry budget for unavailable-daemon failures (default 3, 500ms apart).
+    pub fn with_retries(mut self, retries: u32, retry_delay: Duration) -> Self {
+        self.retries = retries;
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    pub fn target(&self) -> &Target {
+        &self.target
+    }
+
+    /// Current proxy, connecting first if needed.
+    pub async fn proxy(&self) -> zbus::Result<Proxy<'static>> {
+        let mut proxy = self.proxy.lock().await;
+        if let Some(proxy) = proxy.as_ref() {
+            return Ok(proxy.clone());
+        }
+        let fresh = connect(&self.target).await?;
+        *proxy = Some(fresh.clone());
+        Ok(fresh)
+    }
+
+    /// Call a daemon method, reconnecting and retrying while the daemon is unavailable.
+    pub async fn call<B, R>(&self, method: &str, body: &B) -> Result<R, ClientError>
+    where
+        B: serde::ser::Serialize + DynamicType,
+        R: for<'d> DynamicDeserialize<'d>,
+    {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let result = match self.proxy().await {
+                Ok(proxy) => proxy.call(method, body).await,
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(reply) => return Ok(reply),
+                Err(err) if is_unavailable(&err) => {
+                    // Drop the stale proxy so the next attempt reconnects.
+                    *self.proxy.lock().await = None;
+                    if attempts > self.retries {
+                        return Err(ClientError::Unavailable {
+                            attempts,
+                            last: err,
+                        });
+                    }
+                    tokio::time::sleep(self.retry_delay).await;
+                }
+                Err(err) => return Err(ClientError::Call(err)),
+            }
+        }
+    }
+
+    /// Wait until the daemon answers on `target`, polling until `timeout` passes.
+    pub async fn wait_for_daemon(target: Target, timeout: Duration) -> Result<Self, ClientError> {
+        let client = Self::new(target);
+        let deadline = Instant::now() + timeout;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match client.call::<_, String>("GetMode", &()).await {
+                Ok(_) => return Ok(client),
+                Err(ClientError::Unavailable { last, .. }) if Instant::now() >= deadline => {
+                    return Err(ClientError::Unavailable { attempts, last });
+                }
+                Err(ClientError::Unavailable { .. }) => {
+                    tokio::time::sleep(client.retry_delay).await;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
@@ -1,10 +1,147 @@
 use std::collections::HashMap;
 use std::fs;
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use crate::iommu::{self, Device};
 
+/// Levels accepted by amdgpu's `power_dpm_force_performance_level`.
+pub const POWER_PROFILE_LEVELS: &[&str] = &[
+    "auto",
+    "low",
+    "high",
+    "manual",
+    "profile_standard",
+    "profile_min_sclk",
+    "profile_min_mclk",
+    "profile_peak",
+];
+
+/// Attributes `Gpu::read_attribute` may read, with their path under the GPU's PCI
+/// device directory. Read-only status values; nothing that wakes the device.
+pub const READABLE_ATTRIBUTES: &[(&str, &str)] = &[
+    ("runtime_status", "power/runtime_status"),
+    ("runtime_active_time", "power/runtime_active_time"),
+    ("runtime_suspended_time", "power/runtime_suspended_time"),
+    ("control", "power/control"),
+    ("power_state", "power_state"),
+    ("d3cold_allowed", "d3cold_allowed"),
+    ("current_link_speed", "current_link_speed"),
+    ("current_link_width", "current_link_width"),
+    ("max_link_speed", "max_link_speed"),
+    ("max_link_width", "max_link_width"),
+    ("enable", "enable"),
+    ("boot_vga", "boot_vga"),
+    ("class", "class"),
+    ("vendor", "vendor"),
+    ("device", "device"),
+    ("subsystem_vendor", "subsystem_vendor"),
+    ("subsystem_device", "subsystem_device"),
+    ("revision", "revision"),
+    ("numa_node", "numa_node"),
+    ("local_cpulist", "local_cpulist"),
+    ("reset_method", "reset_method"),
+    ("gpu_busy_percent", "gpu_busy_percent"),
+    ("mem_busy_percent", "mem_busy_percent"),
+    ("mem_info_vram_used", "mem_info_vram_used"),
+    ("mem_info_vram_total", "mem_info_vram_total"),
+    (
+        "power_dpm_force_performance_level",
+        "power_dpm_force_performance_level",
+    ),
+];
+
+/// Module parameter values known to work against blocking, by the vendor whose GPUs
+/// they affect: (vendor, module, parameter, value, consequence).
+const CONFLICTING_MODULE_PARAMS: &[(Vendor, &str, &str, &str, &str)] = &[
+    (
+        Vendor::Nvidia,
+        "nvidia_drm",
+        "modeset",
+        "Y",
+        "nvidia-drm keeps the card open for KMS, so a blocked GPU stays in use and awake",
+    ),
+    (
+        Vendor::Nvidia,
+        "nouveau",
+        "runpm",
+        "0",
+        "runtime power management is off, so a blocked GPU never powers down",
+    ),
+    (
+        Vendor::Nvidia,
+        "nouveau",
+        "modeset",
+        "0",
+        "no DRM nodes are created, so blocking only covers PCI config access",
+    ),
+    (
+        Vendor::Amd,
+        "amdgpu",
+        "runpm",
+        "0",
+        "runtime power management is off, so a blocked GPU never powers down",
+    ),
+    (
+        Vendor::Intel,
+        "i915",
+        "modeset",
+        "0",
+        "no DRM nodes are created, so blocking only covers PCI config access",
+    ),
+];
+
+/// Loaded module parameters from `CONFLICTING_MODULE_PARAMS` that apply to one of
+/// `vendors`, as `module.parameter=value: consequence`. Only reads
+/// `/sys/module/*/parameters`; nothing is changed.
+pub fn module_param_conflicts(vendors: &[Vendor]) -> Vec<String> {
+    CONFLICTING_MODULE_PARAMS
+        .iter()
+        .filter(|(vendor, ..)| vendors.contains(vendor))
+        .filter_map(|(_, module, parameter, value, consequence)| {
+            let path = Path::new("/sys/module")
+                .join(module)
+                .join("parameters")
+                .join(parameter);
+            let current = fs::read_to_string(path).ok()?;
+            (current.trim() == *value)
+                .then(|| format!("{}.{}={}: {}", module, parameter, value, consequence))
+        })
+        .collect()
+}
+
+/// GPU vendor, from the PCI vendor id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Vendor {
+    Amd,
+    Intel,
+    Nvidia,
+    Other,
+}
+
+impl Vendor {
+    /// Vendor for a sysfs `vendor` value such as `0x1002`.
+    pub fn from_pci_id(vendor_id: &str) -> Self {
+        match vendor_id.trim().to_ascii_lowercase().as_str() {
+            "0x1002" => Vendor::Amd,
+            "0x8086" => Vendor::Intel,
+            "0x10de" => Vendor::Nvidia,
+            _ => Vendor::Other,
+        }
+    }
+}
+
+/// A display output of a GPU, from `/sys/class/drm/card<N>-<connector>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectorInfo {
+    /// Connector name without the card prefix, e.g. `HDMI-A-1`.
+    pub name: String,
+    /// Connector type, e.g. `eDP`, `DP`, `HDMI-A`.
+    pub kind: String,
+    /// `connected`, `disconnected` or `unknown`, as the driver reports it.
+    pub status: String,
+}
+
 /// Struct representing a GPU device
 #[derive(Debug, Clone)]
 pub struct Gpu {
@@ -14,10 +151,36 @@ pub struct Gpu {
     render: String,
     card: String,
     default: bool,
+    vendor: Vendor,
     slot: usize,
+    hwmon: Option<PathBuf>,
 }
 
 impl Gpu {
+    /// GPU built from known values instead of sysfs, e.g. for a daemon over synthetic
+    /// hardware. It has no PCI slot and no hwmon sensors.
+    pub fn new(
+        id: usize,
+        name: &str,
+        pci: &str,
+        render: &str,
+        card: &str,
+        default: bool,
+        vendor: Vendor,
+    ) -> Self {
+        Self {
+            id,
+            name: name.to_string(),
+            pci: iommu::normalize_pci(pci),
+            render: render.to_string(),
+            card: card.to_string(),
+            default,
+            vendor,
+            slot: 0,
+            hwmon: None,
+        }
+    }
+
     /// Returns the PCI address of the GPU
     pub fn pci_address(&self) -> &str {
         &self.pci
@@ -38,6 +201,46 @@ impl Gpu {
         &self.name
     }
 
+    /// Returns the GPU vendor
+    pub fn vendor(&self) -> Vendor {
+        self.vendor
+    }
+
+    /// Whether the render and card device nodes actually exist. They're missing when
+    /// no DRM driver is bound or the nodes haven't been created yet.
+    pub fn nodes_present(&self) -> bool {
+        Path::new(&self.render).exists() && Path::new(&self.card).exists()
+    }
+
+    /// Whether the PCI device is still in sysfs; false once it is unplugged or removed.
+    pub fn device_present(&self) -> bool {
+        Path::new("/sys/bus/pci/devices").join(&self.pci).exists()
+    }
+
+    /// Best guess at whether this is an integrated GPU: on-die GPUs sit on PCI bus 0.
+    /// Misses integrated GPUs exposed behind a bridge (e.g. some AMD APUs).
+    pub fn is_integrated(&self) -> bool {
+        self.pci.split(':').nth(1).is_some_and(|bus| bus == "00")
+    }
+
+    /// Environment variables that make an app render on this GPU with PRIME render
+    /// offload. `None` for vendors without a known offload mechanism.
+    pub fn offload_env(&self) -> Option<Vec<(&'static str, String)>> {
+        match self.vendor {
+            // Mesa picks the device by PCI tag, e.g. pci-0000_03_00_0.
+            Vendor::Amd | Vendor::Intel => Some(vec![(
+                "DRI_PRIME",
+                format!("pci-{}", self.pci.replace([':', '.'], "_")),
+            )]),
+            Vendor::Nvidia => Some(vec![
+                ("__NV_PRIME_RENDER_OFFLOAD", "1".to_string()),
+                ("__GLX_VENDOR_LIBRARY_NAME", "nvidia".to_string()),
+                ("__VK_LAYER_NV_optimus", "NVIDIA_only".to_string()),
+            ]),
+            Vendor::Other => None,
+        }
+    }
+
     /// Returns the DRM render node path (e.g., /dev/dri/renderD128)
     pub fn render_node(&self) -> &str {
         &self.render
@@ -52,6 +255,157 @@ impl Gpu {
     pub fn slot(&self) -> usize {
         self.slot
     }
+
+    /// Returns the instantaneous power draw in watts, if the driver exposes it
+    /// (`hwmon/*/power1_average`, in microwatts, on AMD).
+    pub fn power_draw_watts(&self) -> Option<f32> {
+        let microwatts: u64 = fs::read_to_string(self.hwmon.as_ref()?.join("power1_average"))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()?;
+        Some(microwatts as f32 / 1_000_000.0)
+    }
+
+    /// Returns the edge temperature in degrees Celsius (`hwmon/*/temp1_input`, in
+    /// millidegrees), if the driver exposes it.
+    pub fn temperature_celsius(&self) -> Option<f32> {
+        let millidegrees: i64 = fs::read_to_string(self.hwmon.as_ref()?.join("temp1_input"))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()?;
+        Some(millidegrees as f32 / 1000.0)
+    }
+
+    /// Returns the (used, total) VRAM in bytes, from amdgpu's `mem_info_vram_used` and
+    /// `mem_info_vram_total`. Cheap enough to poll. `None` elsewhere: the NVIDIA
+    /// driver only reports memory through NVML (`nvidia-smi`), and i915/xe expose no
+    /// VRAM counters in sysfs (integrated GPUs use system memory anyway).
+    pub fn vram_usage(&self) -> Option<(u64, u64)> {
+        let device_path = Path::new("/sys/bus/pci/devices").join(&self.pci);
+        let read_bytes = |file: &str| -> Option<u64> {
+            fs::read_to_string(device_path.join(file))
+                .ok()?
+                .trim()
+                .parse()
+                .ok()
+        };
+        Some((
+            read_bytes("mem_info_vram_used")?,
+            read_bytes("mem_info_vram_total")?,
+        ))
+    }
+
+    /// Returns the PCI subsystem (vendor, device) ids, which tell OEM boards sharing a
+    /// device id apart. `None` when sysfs doesn't expose them.
+    pub fn subsystem_ids(&self) -> Option<(u16, u16)> {
+        let device_path = Path::new("/sys/bus/pci/devices").join(&self.pci);
+        let read_id = |file: &str| {
+            let value = fs::read_to_string(device_path.join(file)).ok()?;
+            let value = value.trim();
+            u16::from_str_radix(value.strip_prefix("0x").unwrap_or(value), 16).ok()
+        };
+        Some((read_id("subsystem_vendor")?, read_id("subsystem_device")?))
+    }
+
+    /// Returns the display connectors of this GPU's card node, sorted by name. Empty
+    /// for headless cards (e.g. compute-only GPUs) and when no DRM driver is bound.
+    pub fn connectors(&self) -> Vec<ConnectorInfo> {
+        let Some(card) = Path::new(&self.card)
+            .file_name()
+            .and_then(|name| name.to_str())
+        else {
+            return Vec::new();
+        };
+        let prefix = format!("{}-", card);
+        let Ok(entries) = fs::read_dir("/sys/class/drm") else {
+            return Vec::new();
+        };
+
+        let mut connectors: Vec<ConnectorInfo> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let file_name = entry.file_name();
+                let name = file_name.to_str()?.strip_prefix(&prefix)?.to_string();
+                // The type is everything before the trailing index: HDMI-A-1 -> HDMI-A.
+                let kind = name
+                    .rsplit_once('-')
+                    .map_or(name.as_str(), |(kind, _)| kind);
+                let status = fs::read_to_string(entry.path().join("status"))
+                    .map(|status| status.trim().to_string())
+                    .unwrap_or_else(|_| "unknown".to_string());
+                Some(ConnectorInfo {
+                    kind: kind.to_string(),
+                    name,
+                    status,
+                })
+            })
+            .collect();
+        connectors.sort_by(|a, b| a.name.cmp(&b.name));
+        connectors
+    }
+
+    fn power_profile_path(&self) -> PathBuf {
+        Path::new("/sys/bus/pci/devices")
+            .join(&self.pci)
+            .join("power_dpm_force_performance_level")
+    }
+
+    /// Returns the forced DPM performance level (`auto`, `low`, `high`, ...).
+    /// `ErrorKind::NotFound` means the driver doesn't expose it (only amdgpu does).
+    pub fn power_profile(&self) -> io::Result<String> {
+        Ok(fs::read_to_string(self.power_profile_path())?
+            .trim()
+            .to_string())
+    }
+
+    /// Reads one of `READABLE_ATTRIBUTES`, trimmed. Any other name, including paths,
+    /// is `ErrorKind::InvalidInput`; `ErrorKind::NotFound` means this GPU's driver
+    /// doesn't expose it.
+    pub fn read_attribute(&self, name: &str) -> io::Result<String> {
+        let Some((_, relative)) = READABLE_ATTRIBUTES.iter().find(|(known, _)| *known == name)
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "attribute '{}' isn't readable, expected one of: {}",
+                    name,
+                    READABLE_ATTRIBUTES
+                        .iter()
+                        .map(|(known, _)| *known)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            ));
+        };
+        let path = Path::new("/sys/bus/pci/devices")
+            .join(&self.pci)
+            .join(relative);
+        Ok(fs::read_to_string(path)?.trim().to_string())
+    }
+
+    /// Forces a DPM performance level, one of `POWER_PROFILE_LEVELS`.
+    pub fn set_power_profile(&self, level: &str) -> io::Result<()> {
+        if !POWER_PROFILE_LEVELS.contains(&level) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "invalid level '{}', expected one of: {}",
+                    level,
+                    POWER_PROFILE_LEVELS.join(", ")
+                ),
+            ));
+        }
+        let path = self.power_profile_path();
+        if !path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "power_dpm_force_performance_level not available",
+            ));
+        }
+        fs::write(path, level)
+    }
 }
 
 /// Discover GPUs among PCI devices using the VGA (0x030000) class code.
@@ -72,11 +426,13 @@ pub fn list_gpu(pci_devices: &HashMap<String, Device>) -> io::Result<HashMap<Str
         let gpu = Gpu {
             id,
             name: device.device_name.clone(),
-            pci: device.pci_address.clone(),
+            pci: iommu::normalize_pci(&device.pci_address),
             render: render_node_path(&device.pci_address),
             card: card_node_path(&device.pci_address),
             default: is_default,
+            vendor: Vendor::from_pci_id(&device.vendor_id),
             slot: find_pci_slot(&device.pci_address).unwrap_or(0),
+            hwmon: find_hwmon(&device.pci_address),
         };
 
         gpu_map.insert(id.to_string(), gpu);
@@ -85,12 +441,41 @@ pub fn list_gpu(pci_devices: &HashMap<String, Device>) -> io::Result<HashMap<Str
     Ok(gpu_map)
 }
 
+/// DRM nodes claimed by more than one GPU, with the ids of the GPUs sharing each.
+/// Blocking such a node affects every GPU listed for it.
+pub fn shared_nodes(gpus: &HashMap<String, Gpu>) -> Vec<(String, Vec<usize>)> {
+    let mut owners: HashMap<&str, Vec<usize>> = HashMap::new();
+    for gpu in gpus.values() {
+        for node in [gpu.render_node(), gpu.card_node()] {
+            // Unresolved nodes hold an error message, not a path.
+            if node.starts_with("/dev/dri/") {
+                owners.entry(node).or_default().push(gpu.id());
+            }
+        }
+    }
+
+    let mut shared: Vec<(String, Vec<usize>)> = owners
+        .into_iter()
+        .filter(|(_, ids)| ids.len() > 1)
+        .map(|(node, mut ids)| {
+            ids.sort();
+            (node.to_string(), ids)
+        })
+        .collect();
+    shared.sort();
+    shared
+}
+
 /// Refresh GPU information for a specific PCI address
 fn refresh_gpu(
     pci_address: &str,
     pci_devices: &HashMap<String, Device>,
 ) -> io::Result<Option<Gpu>> {
-    let device = match pci_devices.values().find(|d| d.pci_address == pci_address) {
+    let pci_address = iommu::normalize_pci(pci_address);
+    let device = match pci_devices
+        .values()
+        .find(|d| iommu::normalize_pci(&d.pci_address) == pci_address)
+    {
         Some(d) => d,
         None => return Ok(None),
     };
@@ -108,11 +493,13 @@ fn refresh_gpu(
     let gpu = Gpu {
         id: 0,
         name: device.device_name.clone(),
-        pci: device.pci_address.clone(),
+        pci: iommu::normalize_pci(&device.pci_address),
         render: render_node_path(&device.pci_address),
         card: card_node_path(&device.pci_address),
         default: is_default,
+        vendor: Vendor::from_pci_id(&device.vendor_id),
         slot: find_pci_slot(&device.pci_address).unwrap_or(0),
+        hwmon: find_hwmon(&device.pci_address),
     };
 
     Ok(Some(gpu))
@@ -157,6 +544,49 @@ pub fn bind_gpu(pci_address: &str, slot: usize) -> io::Result<Option<Gpu>> {
     refresh_gpu(pci_address, &pci_devices)
 }
 
+const PLATFORM_PROFILE_PATH: &str = "/sys/firmware/acpi/platform_profile";
+
+/// Set the ACPI platform profile (`low-power`, `balanced`, `performance`, ...).
+/// `NotFound` when the platform has no profile support, `InvalidInput` when it doesn't
+/// offer `profile`.
+pub fn set_platform_profile(profile: &str) -> io::Result<()> {
+    let choices = fs::read_to_string(format!("{}_choices", PLATFORM_PROFILE_PATH))?;
+    if !choices.split_whitespace().any(|choice| choice == profile) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "platform profile {} not offered (choices: {})",
+                profile,
+                choices.trim()
+            ),
+        ));
+    }
+    fs::write(PLATFORM_PROFILE_PATH, profile)
+}
+
+// Firmware framebuffer drivers that can keep the boot GPU busy: (platform driver, device).
+const BOOT_FRAMEBUFFERS: [(&str, &str); 3] = [
+    ("efi-framebuffer", "efi-framebuffer.0"),
+    ("simple-framebuffer", "simple-framebuffer.0"),
+    ("vesa-framebuffer", "vesa-framebuffer.0"),
+];
+
+/// Unbind the firmware framebuffer (efifb, simpledrm, vesafb) from the boot GPU so it
+/// can be fully powered down. This blanks the console until a real driver takes over.
+/// Returns the released framebuffer devices; empty when none was bound.
+pub fn release_boot_framebuffer() -> io::Result<Vec<String>> {
+    let mut released = Vec::new();
+    for (driver, device) in BOOT_FRAMEBUFFERS {
+        let driver_path = Path::new("/sys/bus/platform/drivers").join(driver);
+        if !driver_path.join(device).exists() {
+            continue;
+        }
+        fs::write(driver_path.join("unbind"), device)?;
+        released.push(device.to_string());
+    }
+    Ok(released)
+}
+
 /// Checks the device power_state status, returns 1 if GPU is D3cold
 fn is_sleeping(pci_address: &str) -> io::Result<bool> {
     let power_state_path = Path::new("/sys/bus/pci/devices")
@@ -182,6 +612,23 @@ fn card_node_path(pci_address: &str) -> String {
         .unwrap_or_else(|_| "Error: couldn't read symlink for card node".to_string())
 }
 
+/// Find the hwmon directory of a PCI device, resolved once so sensor reads are a single file access
+fn find_hwmon(pci_address: &str) -> Option<PathBuf> {
+    let hwmon_dir = Path::new("/sys/bus/pci/devices")
+        .join(pci_address)
+        .join("hwmon");
+
+    fs::read_dir(hwmon_dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("hwmon"))
+        })
+}
+
 /// Find the PCI slot number for a given PCI address
 fn find_pci_slot(pci_address: &str) -> io::Result<usize> {
     let slots_dir = Path::new("/sys/bus/pci/slots");
@@ -230,3 +677,19 @@ pub fn set_gpu_power(slot: usize, power_on: bool) -> io::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gpu_with_nodes(render: &str, card: &str) -> Gpu {
+        Gpu::new(1, "Test GPU", "03:00.0", render, card, false, Vendor::Other)
+    }
+
+    #[test]
+    fn nodes_present_needs_both_nodes() {
+        assert!(gpu_with_nodes("/dev/null", "/dev/null").nodes_present());
+        assert!(!gpu_with_nodes("/nonexistent/renderD128", "/dev/null").nodes_present());
+        assert!(!gpu_with_nodes("/dev/null", "/nonexistent/card1").nodes_present());
+    }
+}
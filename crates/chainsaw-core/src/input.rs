@@ -0,0 +1,50 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// `EV_KEY` event type from `linux/input-event-codes.h`.
+const EV_KEY: u16 = 0x01;
+/// `value` of an `EV_KEY` event for a key going down (0 is release, 2 auto-repeat).
+const KEY_PRESSED: i32 = 1;
+
+// `struct input_event`: a `struct timeval` (two C longs), then type, code and value.
+const TIMEVAL_LEN: usize = 2 * std::mem::size_of::<std::ffi::c_long>();
+const EVENT_LEN: usize = TIMEVAL_LEN + 8;
+
+/// Key events read from an evdev node (`/dev/input/event*`).
+///
+/// The nodes are usually `root:input` with mode 0660, so the reader must run as
+/// root or be in the `input` group. The device isn't grabbed: other readers (the
+/// compositor, the console) still see every key.
+pub struct KeyEvents {
+    file: File,
+}
+
+impl KeyEvents {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self {
+            file: File::open(path)?,
+        })
+    }
+
+    /// Block until `code` (e.g. 148 for `KEY_PROG1`) is pressed. Releases and
+    /// auto-repeats are skipped, so holding the key counts as one press.
+    pub fn next_press(&mut self, code: u16) -> io::Result<()> {
+        let mut event = [0u8; EVENT_LEN];
+        loop {
+            self.file.read_exact(&mut event)?;
+            let field = |offset: usize| [event[offset], event[offset + 1]];
+            let event_type = u16::from_ne_bytes(field(TIMEVAL_LEN));
+            let event_code = u16::from_ne_bytes(field(TIMEVAL_LEN + 2));
+            let value = i32::from_ne_bytes([
+                event[TIMEVAL_LEN + 4],
+                event[TIMEVAL_LEN + 5],
+                event[TIMEVAL_LEN + 6],
+                event[TIMEVAL_LEN + 7],
+            ]);
+            if event_type == EV_KEY && event_code == code && value == KEY_PRESSED {
+                return Ok(());
+            }
+        }
+    }
+}
@@ -22,6 +22,299 @@ pub struct Device {
     pub class: String,
 }
 
+/// Normalize a PCI address to the sysfs form `dddd:bb:dd.f`.
+///
+/// Short forms (`01:00.0`) get the `0000` domain, and hex digits are lowercased,
+/// so the same device always maps to the same string.
+pub fn normalize_pci(addr: &str) -> String {
+    let addr = addr.trim().to_ascii_lowercase();
+    let mut parts: Vec<&str> = addr.split(':').collect();
+    if parts.len() == 2 {
+        parts.insert(0, "0");
+    }
+
+    let normalized = match parts.as_slice() {
+        [domain, bus, slot_func] => slot_func
+            .split_once('.')
+            .map(|(slot, func)| format!("{:0>4}:{:0>2}:{:0>2}.{}", domain, bus, slot, func)),
+        _ => None,
+    };
+    normalized.unwrap_or(addr)
+}
+
+/// Validate and normalize a PCI slot pattern: `bb:dd.*` or `dddd:bb:dd.*` (every
+/// function of one slot), or a plain address. Returns it in `dddd:bb:dd.*` form.
+pub fn normalize_pci_pattern(pattern: &str) -> Result<String, String> {
+    let invalid = || {
+        format!(
+            "invalid PCI pattern '{}', expected bb:dd.* or dddd:bb:dd.* (hex), or a full address",
+            pattern
+        )
+    };
+    let pattern = pattern.trim().to_ascii_lowercase();
+    let (slot, func) = pattern.rsplit_once('.').ok_or_else(invalid)?;
+    let mut parts: Vec<&str> = slot.split(':').collect();
+    if parts.len() == 2 {
+        parts.insert(0, "0");
+    }
+    let [domain, bus, device] = parts.as_slice() else {
+        return Err(invalid());
+    };
+    let hex = |field: &str, max_len: usize| {
+        !field.is_empty() && field.len() <= max_len && field.chars().all(|c| c.is_ascii_hexdigit())
+    };
+    if !hex(domain, 8) || !hex(bus, 2) || !hex(device, 2) || !(func == "*" || hex(func, 1)) {
+        return Err(invalid());
+    }
+    Ok(format!(
+        "{:0>4}:{:0>2}:{:0>2}.{}",
+        domain, bus, device, func
+    ))
+}
+
+/// Whether a normalized address matches a pattern from `normalize_pci_pattern`.
+pub fn pci_pattern_matches(pattern: &str, addr: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => addr.starts_with(prefix),
+        None => addr == pattern,
+    }
+}
+
+impl Device {
+    /// Returns the driver currently bound to the device, read live from the `driver` symlink.
+    /// `Ok(None)` means no driver is bound; `Err` means the symlink couldn't be read.
+    pub fn current_driver(&self) -> io::Result<Option<String>> {
+        let driver_path = Path::new("/sys/bus/pci/devices/")
+            .join(&self.pci_address)
+            .join("driver");
+        match fs::read_link(&driver_path) {
+            Ok(target) => Ok(target
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Whether the kernel has the device enabled (sysfs `enable`). `Some(false)` also
+    /// covers a removed device and one no driver ever enabled; `None` means the
+    /// platform doesn't expose `enable`.
+    pub fn is_enabled(&self) -> Option<bool> {
+        let device_path = Path::new("/sys/bus/pci/devices/").join(&self.pci_address);
+        if !device_path.exists() {
+            return Some(false);
+        }
+        let enable = fs::read_to_string(device_path.join("enable")).ok()?;
+        Some(enable.trim() != "0")
+    }
+
+    /// Returns the kernel's reset methods for the device in preference order (sysfs
+    /// `reset_method`, e.g. "flr bus"). `None` when the attribute is missing (kernels
+    /// before 5.15) or empty, meaning the device can't be reset.
+    pub fn reset_method(&self) -> Option<String> {
+        let reset_method = fs::read_to_string(
+            Path::new("/sys/bus/pci/devices/")
+                .join(&self.pci_address)
+                .join("reset_method"),
+        )
+        .ok()?;
+        let reset_method = reset_method.trim();
+        (!reset_method.is_empty()).then(|| reset_method.to_string())
+    }
+
+    /// Returns the current PCIe link speed (e.g. "16.0 GT/s PCIe") and width.
+    /// `None` when the link state isn't known, e.g. while runtime-suspended; the
+    /// device is never woken up to read it.
+    pub fn link_status(&self) -> Option<(String, u8)> {
+        let device_path = Path::new("/sys/bus/pci/devices/").join(&self.pci_address);
+        let speed = fs::read_to_string(device_path.join("current_link_speed")).ok()?;
+        let width = fs::read_to_string(device_path.join("current_link_width")).ok()?;
+
+        let speed = speed.trim();
+        if speed.is_empty() || speed.starts_with("Unknown") {
+            return None;
+        }
+        // An unknown width reads as 0 or a non-number.
+        let width: u8 = width.trim().parse().ok().filter(|width| *width > 0)?;
+        Some((speed.to_string(), width))
+    }
+}
+
+/// Every PCI function in the same slot (`dddd:bb:dd`) as `pci_address`, itself
+/// included, sorted. Scans all of `/sys/bus/pci/devices`, not just IOMMU groups.
+pub fn slot_functions(pci_address: &str) -> io::Result<Vec<String>> {
+    let address = normalize_pci(pci_address);
+    let Some((slot, _)) = address.rsplit_once('.') else {
+        return Ok(Vec::new());
+    };
+    let mut functions = Vec::new();
+    for entry in fs::read_dir("/sys/bus/pci/devices/")? {
+        let name = entry?.file_name();
+        if let Some(name) = name.to_str()
+            && name
+                .rsplit_once('.')
+                .is_some_and(|(other, _)| other == slot)
+        {
+            functions.push(name.to_string());
+        }
+    }
+    functions.sort();
+    Ok(functions)
+}
+
+/// ACS (Access Control Services) state of a PCIe port, from its config space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcsState {
+    /// Every redirect/forwarding control the port supports is on.
+    Enabled,
+    /// The port has ACS but leaves some of it off, so peers below it can talk
+    /// directly and end up sharing an IOMMU group.
+    Disabled,
+    /// No ACS capability (common on client root ports).
+    Absent,
+    /// Extended config space couldn't be read (needs root).
+    Unknown,
+}
+
+impl AcsState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AcsState::Enabled => "on",
+            AcsState::Disabled => "off",
+            AcsState::Absent => "absent",
+            AcsState::Unknown => "unknown",
+        }
+    }
+}
+
+// PCIe extended capability id of ACS, and the control bits that isolate peers:
+// P2P request redirect, P2P completion redirect, upstream forwarding.
+const ACS_CAP_ID: u16 = 0x000d;
+const ACS_ISOLATION_BITS: u16 = (1 << 2) | (1 << 3) | (1 << 4);
+
+/// A bridge (root port or switch port) above a device.
+#[derive(Debug, Clone)]
+pub struct Bridge {
+    pub pci_address: String,
+    pub acs: AcsState,
+}
+
+fn acs_state(pci_address: &str) -> AcsState {
+    let Ok(config) = fs::read(
+        Path::new("/sys/bus/pci/devices/")
+            .join(pci_address)
+            .join("config"),
+    ) else {
+        return AcsState::Unknown;
+    };
+    // Unprivileged reads stop after the 64-byte header.
+    if config.len() < 0x104 {
+        return AcsState::Unknown;
+    }
+    let read_u16 = |offset: usize| u16::from_le_bytes([config[offset], config[offset + 1]]);
+
+    // Walk the extended capability list. Each step must move forward, so a corrupt
+    // list can't loop.
+    let mut offset = 0x100;
+    while offset + 8 <= config.len() {
+        let header = u32::from_le_bytes([
+            config[offset],
+            config[offset + 1],
+            config[offset + 2],
+            config[offset + 3],
+        ]);
+        if header == 0 || header == u32::MAX {
+            break;
+        }
+        if header as u16 == ACS_CAP_ID {
+            let supported = read_u16(offset + 4) & ACS_ISOLATION_BITS;
+            let control = read_u16(offset + 6);
+            return if supported != 0 && control & supported == supported {
+                AcsState::Enabled
+            } else {
+                AcsState::Disabled
+            };
+        }
+        let next = (header >> 20) as usize & !0x3;
+        if next <= offset {
+            break;
+        }
+        offset = next;
+    }
+    AcsState::Absent
+}
+
+/// Bridges between a device and the root complex, root port first, read from the
+/// device's sysfs path (`/sys/devices/pci0000:00/0000:00:01.0/0000:01:00.0/...`).
+/// The path is finite, so however deep the switches nest, the walk ends.
+pub fn upstream_bridges(pci_address: &str) -> io::Result<Vec<Bridge>> {
+    let path = fs::canonicalize(Path::new("/sys/bus/pci/devices/").join(pci_address))?;
+    let mut bridges: Vec<Bridge> = path
+        .ancestors()
+        .skip(1)
+        .map_while(|ancestor| {
+            let name = ancestor.file_name()?.to_str()?;
+            // Stops at the host bridge directory (`pci0000:00`).
+            (normalize_pci_pattern(name).as_deref() == Ok(name)).then(|| Bridge {
+                pci_address: name.to_string(),
+                acs: acs_state(name),
+            })
+        })
+        .collect();
+    bridges.reverse();
+    Ok(bridges)
+}
+
+/// Whether a device could be handed to a VM cleanly, with the reasons it can't.
+/// Diagnostic only: its IOMMU group must hold nothing but its own slot's functions
+/// and bridges, no bridge above it may have ACS switched off, and it needs a reset
+/// method other than a bare bus reset.
+pub fn passthrough_issues(
+    pci_address: &str,
+    devices: &HashMap<String, Device>,
+    bridges: &[Bridge],
+) -> Vec<String> {
+    let mut issues = Vec::new();
+    match devices.get(pci_address) {
+        None => issues.push("not in any IOMMU group (is the IOMMU enabled?)".to_string()),
+        Some(device) => {
+            let slot = pci_address
+                .rsplit_once('.')
+                .map_or(pci_address, |(slot, _)| slot);
+            let mut shared: Vec<&str> = devices
+                .values()
+                .filter(|other| {
+                    other.iommu_group == device.iommu_group
+                        && !other.pci_address.starts_with(slot)
+                        && !other.class.starts_with("0x0604")
+                })
+                .map(|other| other.pci_address.as_str())
+                .collect();
+            if !shared.is_empty() {
+                shared.sort_unstable();
+                issues.push(format!(
+                    "IOMMU group {} is shared with {}",
+                    device.iommu_group,
+                    shared.join(", ")
+                ));
+            }
+            // A secondary bus reset alone often leaves GPUs (notably AMD) in a bad
+            // state after the first VM shutdown.
+            if device.reset_method().as_deref() == Some("bus") {
+                issues.push(
+                    "only a bus reset, no FLR: may fail after repeated VM start/stop".to_string(),
+                );
+            }
+        }
+    }
+    for bridge in bridges {
+        if bridge.acs == AcsState::Disabled {
+            issues.push(format!("ACS is off on bridge {}", bridge.pci_address));
+        }
+    }
+    issues
+}
+
 pub fn read_iommu_groups() -> std::io::Result<HashMap<usize, IommuGroup>> {
     let base_path = Path::new("/sys/kernel/iommu_groups");
 
@@ -214,3 +507,40 @@ pub fn pci_rescan() -> Result<(), std::io::Error> {
         Err(e) => Err(e),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_pci_maps_every_form_to_one_key() {
+        let key = "0000:01:00.0";
+        for addr in ["01:00.0", "0000:01:00.0", "0000:01:00.0\n", "1:0.0"] {
+            assert_eq!(normalize_pci(addr), key, "{}", addr);
+        }
+    }
+
+    #[test]
+    fn normalize_pci_lowercases() {
+        assert_eq!(normalize_pci("0000:0A:1F.3"), "0000:0a:1f.3");
+        assert_eq!(normalize_pci("0A:1F.3"), "0000:0a:1f.3");
+    }
+
+    #[test]
+    fn normalize_pci_keeps_normalized_addresses() {
+        for addr in ["0000:00:02.0", "0001:c1:00.1"] {
+            assert_eq!(normalize_pci(addr), addr);
+            assert_eq!(normalize_pci(&normalize_pci(addr)), addr);
+        }
+    }
+
+    #[test]
+    fn normalize_pci_keeps_extended_domains() {
+        assert_eq!(normalize_pci("10000000:01:00.0"), "10000000:01:00.0");
+        assert_eq!(normalize_pci("ABCDEF01:1:0.0"), "abcdef01:01:00.0");
+        assert_ne!(
+            normalize_pci("10000000:01:00.0"),
+            normalize_pci("0000:01:00.0")
+        );
+    }
+}
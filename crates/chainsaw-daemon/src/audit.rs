@@ -0,0 +1,59 @@
+//! Append-only audit trail of mode changes and block operations (`audit_log`).
+
+use std::fs::{DirBuilder, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Caller recorded for operations the daemon starts itself (timers, the toggle key).
+pub const DAEMON_CALLER: &str = "chainsawd";
+
+pub struct AuditLog {
+    path: PathBuf,
+    file: Mutex<BufWriter<File>>,
+}
+
+impl AuditLog {
+    /// Open `path` for appending. A missing file (and directory) is created readable
+    /// by root only; an existing one keeps its permissions.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            DirBuilder::new().recursive(true).mode(0o750).create(dir)?;
+        }
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append one line, `<unix time> caller=<name> op="..." outcome="..."`, and sync
+    /// it to disk before returning.
+    pub fn record(&self, caller: &str, operation: &str, outcome: &str) -> io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or(0);
+        let mut file = self
+            .file
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        writeln!(
+            file,
+            "{} caller={} op={:?} outcome={:?}",
+            timestamp, caller, operation, outcome
+        )?;
+        file.flush()?;
+        file.get_ref().sync_data()
+    }
+}
@@ -0,0 +1,76 @@
+//! Recent daemon log records, kept in memory for hosts without a journal.
+
+use log::{Level, Log, Metadata, Record};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Records kept for `GetRecentEvents`; older ones are dropped.
+const CAPACITY: usize = 100;
+
+/// (Unix timestamp in seconds, level, message).
+pub type EventRecord = (u64, String, String);
+
+static EVENTS: Mutex<VecDeque<EventRecord>> = Mutex::new(VecDeque::new());
+
+/// Forwards to env_logger, and keeps the daemon's own info/warn/error records (mode
+/// changes, block changes, failures) in a bounded in-memory ring, for hosts without
+/// a journal. Library chatter (zbus, ...) is only forwarded.
+struct RecordingLogger {
+    inner: env_logger::Logger,
+}
+
+impl Log for RecordingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.inner.matches(record)
+            && record.level() <= Level::Info
+            && record.target().starts_with(env!("CARGO_CRATE_NAME"))
+        {
+            let timestamp = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|since| since.as_secs())
+                .unwrap_or(0);
+            let mut events = EVENTS
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if events.len() == CAPACITY {
+                events.pop_front();
+            }
+            events.push_back((
+                timestamp,
+                record.level().to_string(),
+                record.args().to_string(),
+            ));
+        }
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Install `builder`'s logger, recording into the event ring.
+pub fn init(builder: &mut env_logger::Builder) -> Result<(), log::SetLoggerError> {
+    let inner = builder.build();
+    let max_level = inner.filter();
+    log::set_boxed_logger(Box::new(RecordingLogger { inner }))?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+/// The last `limit` recorded events (all of them for 0), oldest first.
+pub fn recent(limit: usize) -> Vec<EventRecord> {
+    let events = EVENTS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let skip = match limit {
+        0 => 0,
+        limit => events.len().saturating_sub(limit),
+    };
+    events.iter().skip(skip).cloned().collect()
+}
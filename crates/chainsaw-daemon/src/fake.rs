@@ -0,0 +1,96 @@
+//! Synthetic GPU topologies (`CHAINSAW_FAKE_GPUS`), for developing and demoing the
+//! daemon and CLI without the hardware.
+
+use chainsaw_core::{gpu, iommu};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// Fixture file the daemon takes its GPUs from instead of scanning PCI.
+pub const FAKE_GPUS_ENV: &str = "CHAINSAW_FAKE_GPUS";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Fixture {
+    gpu: Vec<FakeGpu>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FakeGpu {
+    name: String,
+    pci: String,
+    render: String,
+    card: String,
+    #[serde(default)]
+    default: bool,
+    /// `amd`, `intel`, `nvidia` or a PCI vendor id (`0x1002`); anything else is `Other`.
+    #[serde(default)]
+    vendor: String,
+}
+
+fn vendor(name: &str) -> gpu::Vendor {
+    match name.trim().to_ascii_lowercase().as_str() {
+        "amd" => gpu::Vendor::Amd,
+        "intel" => gpu::Vendor::Intel,
+        "nvidia" => gpu::Vendor::Nvidia,
+        other => gpu::Vendor::from_pci_id(other),
+    }
+}
+
+/// Read a fixture: TOML (`[[gpu]]` tables), or JSON (`{"gpu": [...]}`) for a `.json`
+/// path. GPU ids follow the order of the file.
+pub fn load(path: &Path) -> Result<HashMap<String, gpu::Gpu>, String> {
+    let invalid =
+        |reason: String| format!("Invalid {} {}: {}", FAKE_GPUS_ENV, path.display(), reason);
+    let contents = std::fs::read_to_string(path).map_err(|err| invalid(err.to_string()))?;
+    let fixture: Fixture = if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::from_str(&contents).map_err(|err| invalid(err.to_string()))?
+    } else {
+        toml::from_str(&contents).map_err(|err| invalid(err.to_string()))?
+    };
+
+    if fixture.gpu.is_empty() {
+        return Err(invalid("no [[gpu]] entries".to_string()));
+    }
+    if fixture.gpu.iter().filter(|gpu| gpu.default).count() > 1 {
+        return Err(invalid("more than one GPU has default = true".to_string()));
+    }
+
+    let mut seen = HashSet::new();
+    let mut gpus = HashMap::new();
+    for (id, fake) in fixture.gpu.iter().enumerate() {
+        let pci = iommu::normalize_pci_pattern(&fake.pci)
+            .ok()
+            .filter(|pci| !pci.ends_with('*'))
+            .ok_or_else(|| invalid(format!("GPU {}: '{}' is not a PCI address", id, fake.pci)))?;
+        if !seen.insert(pci.clone()) {
+            return Err(invalid(format!(
+                "GPU {}: PCI address {} is used twice",
+                id, pci
+            )));
+        }
+        for (field, value) in [
+            ("name", &fake.name),
+            ("render", &fake.render),
+            ("card", &fake.card),
+        ] {
+            if value.trim().is_empty() {
+                return Err(invalid(format!("GPU {}: {} is empty", id, field)));
+            }
+        }
+        gpus.insert(
+            id.to_string(),
+            gpu::Gpu::new(
+                id,
+                &fake.name,
+                &pci,
+                &fake.render,
+                &fake.card,
+                fake.default,
+                vendor(&fake.vendor),
+            ),
+        );
+    }
+    Ok(gpus)
+}
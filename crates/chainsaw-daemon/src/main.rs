@@ -1,70 +1,493 @@
 use chainsaw_core::iommu::Device;
-use chainsaw_core::{gpu, iommu};
-use config::Config;
-use log::{info, warn};
-use std::collections::HashMap;
-use std::{error::Error, future::pending};
+use chainsaw_core::{gpu, input, iommu};
+use clap::Parser;
+use futures_util::StreamExt;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::RwLock;
+use zbus::message::Header;
+use zbus::object_server::SignalEmitter;
 use zbus::{connection, fdo, interface};
 
-use chainsaw_ebpf_loader::EbpfBlocker;
+use chainsaw_ebpf_loader::{
+    AttachStatus, BlockSnapshot, Blocker, Capabilities, DenialEvent, EbpfBlocker, HitKey,
+    MemoryBlocker, SENTINEL_NODE_ID,
+};
+
+mod audit;
+mod events;
+mod fake;
+mod settings;
 
-const CONFIG_PATH: &str = "/etc/chainsaw.toml";
 const MODE_INTEGRATED: &str = "integrated";
 const MODE_HYBRID: &str = "hybrid";
+const MODE_GAMING: &str = "gaming";
+const SUPPORTED_MODES: [&str; 3] = [MODE_INTEGRATED, MODE_HYBRID, MODE_GAMING];
 const RENDER_NODE_PREFIX: &str = "/dev/dri/renderD";
 const CARD_NODE_PREFIX: &str = "/dev/dri/card";
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+/// How often the hook's denial events are drained and logged.
+const DENIAL_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How often GPUs are checked for DRM nodes appearing or going away.
+const GPU_RESCAN_INTERVAL: Duration = Duration::from_secs(2);
+/// How often node id entries past their TTL are removed from the map.
+const EXPIRED_PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+type NodeAccessor = fn(&gpu::Gpu) -> &str;
+
+/// One step of a mode preset, on top of its base mode's block decisions.
+#[derive(Debug, Clone, Copy)]
+enum PresetAction {
+    /// Block the integrated GPU, unless it drives the display.
+    BlockIntegrated,
+    /// Force the DPM performance level of every managed discrete GPU.
+    DiscretePowerProfile(&'static str),
+    /// Set the ACPI platform profile.
+    PlatformProfile(&'static str),
+}
+
+/// A composite mode: a base mode plus extra actions. Actions that aren't supported
+/// here are skipped and reported, the rest still applies.
+struct ModePreset {
+    name: &'static str,
+    base: &'static str,
+    actions: &'static [PresetAction],
+}
+
+const MODE_PRESETS: [ModePreset; 1] = [ModePreset {
+    name: MODE_GAMING,
+    base: MODE_HYBRID,
+    actions: &[
+        PresetAction::BlockIntegrated,
+        PresetAction::DiscretePowerProfile("high"),
+        PresetAction::PlatformProfile("performance"),
+    ],
+}];
+
+// Built-in GPU nodes: (kind, accessor, prefix the node id is parsed from).
+const BUILTIN_NODES: [(&str, NodeAccessor, &str); 2] = [
+    ("render", gpu::Gpu::render_node, RENDER_NODE_PREFIX),
+    ("card", gpu::Gpu::card_node, CARD_NODE_PREFIX),
+];
 
+#[derive(Clone)]
 struct Daemon {
-    current_mode: RwLock<String>,
-    gpu_list: HashMap<String, gpu::Gpu>,
+    current_mode: Arc<RwLock<String>>,
+    gpu_list: Arc<HashMap<String, gpu::Gpu>>,
+    // Normalized PCI addresses chainsaw may block; empty manages every GPU.
+    managed_pci: Arc<HashSet<String>>,
+    // Extra device node prefixes (e.g. /dev/accel/accel) blocked by device number.
+    extra_block_prefixes: Arc<Vec<String>>,
+    // DRM nodes shared by several GPUs, with the ids of the sharers.
+    shared_nodes: Arc<HashMap<String, Vec<usize>>>,
     // Cached PCI devices.
-    _pci_devices: HashMap<String, Device>,
-    ebpf_blocker: tokio::sync::Mutex<EbpfBlocker>,
+    pci_devices: Arc<HashMap<String, Device>>,
+    ebpf_blocker: Arc<tokio::sync::Mutex<Box<dyn Blocker>>>,
+    // Kernel features the blocker probed at load; they don't change while it runs.
+    capabilities: Capabilities,
+    // Latest queued set_gpu_block request per GPU: (generation, blocked).
+    pending_blocks: Arc<std::sync::Mutex<HashMap<u32, (u64, bool)>>>,
+    block_debounce: Duration,
+    blocker_timeout: Duration,
+    // Pending timed-mode revert: (generation, (task, deadline)).
+    mode_revert: Arc<std::sync::Mutex<(u64, Option<PendingRevert>)>>,
+    // Pending resume after a timed pause: (generation, (task, deadline)).
+    pause_resume: Arc<std::sync::Mutex<(u64, Option<PendingRevert>)>>,
+    // Why startup reconciliation failed, if it did.
+    reconcile_error: Arc<std::sync::Mutex<Option<String>>>,
+    // Whether `release_boot_framebuffer` may unbind efifb/simpledrm.
+    allow_framebuffer_release: bool,
+    // Settings the daemon was started with.
+    settings: Arc<settings::Settings>,
+    // Normalized `blocked_pci_patterns`, kept in sync with the config file.
+    pci_patterns: Arc<std::sync::Mutex<Vec<String>>>,
+    // Validated `gpu_aliases`: normalized PCI address -> alias.
+    gpu_aliases: Arc<HashMap<String, String>>,
+    // `blocked_drivers`, kept in sync with the config file.
+    blocked_drivers: Arc<std::sync::Mutex<Vec<String>>>,
+    // What the mode toggle key watches ("<device> key <code>"), while it's armed.
+    toggle_key: Arc<std::sync::Mutex<Option<String>>>,
+    // Normalized PCI address integrated mode keeps instead of the boot GPU.
+    integrated_keep: Arc<std::sync::Mutex<Option<String>>>,
+    // Set at startup when deferring to switcheroo-control: every change is refused.
+    read_only: Arc<std::sync::atomic::AtomicBool>,
+    // When each GPU's block state last changed, and to what. Runtime only.
+    block_changes: Arc<std::sync::Mutex<HashMap<u32, (SystemTime, bool)>>>,
+    // `audit_log`, when set: the open file, or why it couldn't be opened.
+    audit_log: Arc<Option<Result<audit::AuditLog, String>>>,
+    started_at: SystemTime,
+    // Bumped around every block write the daemon makes, so the block watcher can tell
+    // its own changes from external ones.
+    block_epoch: Arc<AtomicU64>,
+}
+
+// (id, name, pci, render, default, blocked, driver, managed, block_state)
+type GpuRow = (
+    u32,
+    String,
+    String,
+    String,
+    bool,
+    bool,
+    String,
+    bool,
+    String,
+);
+// Ordered (field, value) pairs describing one GPU.
+type GpuInfo = Vec<(String, String)>;
+// Timed-mode revert task and when it fires.
+type PendingRevert = (tokio::task::JoinHandle<()>, SystemTime);
+// Per-PCI and per-node-id block flags.
+type BlockSet = (Vec<(String, bool)>, Vec<(u32, bool)>);
+
+/// Block set snapshot used by `export_state`/`import_state`.
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportedState {
+    mode: String,
+    #[serde(default)]
+    blocked_pci: Vec<String>,
+    #[serde(default)]
+    blocked_ids: Vec<u32>,
+}
+
+/// fbdev nodes, matched to a GPU through `/sys/dev/char/<dev>/device`.
+const FB_NODE_PREFIX: &str = "/dev/fb";
+
+// (kind, node path, prefix its id is parsed from; `None` blocks by device number)
+type BlockNode = (&'static str, String, Option<&'static str>);
+
+/// Owned copy of what blocking one GPU needs, so it can move to a blocking worker.
+struct BlockTarget {
+    pci: String,
+    nodes: Vec<BlockNode>,
+    // Other GPUs sharing each node: node -> [(pci, is_default)].
+    sharers: HashMap<String, Vec<(String, bool)>>,
+}
+
+/// How many of a GPU's block entries (PCI access, and each node it owns alone) are set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockState {
+    FullyBlocked,
+    /// Some entries are set and others aren't, e.g. after a failed block; the nodes
+    /// that aren't blocked are still usable.
+    PartiallyBlocked,
+    Unblocked,
+}
+
+impl BlockState {
+    fn from_entries(states: &[bool]) -> Self {
+        if states.iter().all(|blocked| *blocked) && !states.is_empty() {
+            BlockState::FullyBlocked
+        } else if states.iter().any(|blocked| *blocked) {
+            BlockState::PartiallyBlocked
+        } else {
+            BlockState::Unblocked
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            BlockState::FullyBlocked => "blocked",
+            BlockState::PartiallyBlocked => "partial",
+            BlockState::Unblocked => "unblocked",
+        }
+    }
+}
+
+/// Failure of a daemon-side blocker operation.
+#[derive(Debug)]
+enum BlockerError {
+    /// The operation didn't finish within `blocker_timeout_ms`.
+    Timeout(Duration),
+    /// The worker running the operation panicked.
+    Task(String),
+    /// The daemon defers to switcheroo-control and makes no block changes.
+    ReadOnly,
+    /// A map read or update failed; the first failure's message.
+    Map(String),
+}
+
+impl std::fmt::Display for BlockerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlockerError::Timeout(limit) => {
+                write!(f, "eBPF blocker timed out after {}ms", limit.as_millis())
+            }
+            BlockerError::Task(err) => write!(f, "eBPF blocker task failed: {}", err),
+            BlockerError::ReadOnly => f.write_str(READ_ONLY_MESSAGE),
+            BlockerError::Map(err) => f.write_str(err),
+        }
+    }
 }
 
-type GpuRow = (u32, String, String, String, bool, bool);
+impl Error for BlockerError {}
+
+impl From<BlockerError> for fdo::Error {
+    fn from(err: BlockerError) -> Self {
+        match err {
+            BlockerError::ReadOnly => fdo::Error::NotSupported(err.to_string()),
+            _ => fdo::Error::Failed(err.to_string()),
+        }
+    }
+}
+
+const READ_ONLY_MESSAGE: &str =
+    "chainsaw defers to switcheroo-control (switcheroo_policy = \"defer\") and is read-only";
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// Serve on the session bus instead of the system bus (development only)
+    #[arg(long)]
+    session: bool,
+    /// Take the bus name over from a running (e.g. stuck) instance, which then exits
+    #[arg(long)]
+    replace: bool,
+}
+
+const BUS_NAME: &str = "com.chainsaw.daemon";
+const SWITCHEROO_BUS_NAME: &str = "net.hadess.SwitcherooControl";
+/// Exit status when another instance owns `BUS_NAME` and `--replace` wasn't given.
+const EXIT_ALREADY_RUNNING: i32 = 3;
+/// Exit status when `require_enforcement` is set and the blocker isn't fully attached.
+const EXIT_NOT_ENFORCING: i32 = 4;
+
+/// PID of the current owner of `BUS_NAME`, if the bus will tell.
+async fn bus_name_owner_pid(session: bool) -> Option<u32> {
+    let conn = if session {
+        connection::Connection::session().await
+    } else {
+        connection::Connection::system().await
+    }
+    .ok()?;
+    let dbus = fdo::DBusProxy::new(&conn).await.ok()?;
+    let name = zbus::names::BusName::try_from(BUS_NAME).ok()?;
+    dbus.get_connection_unix_process_id(name).await.ok()
+}
+
+/// Whether switcheroo-control currently owns its name on `conn`'s bus.
+async fn switcheroo_running(conn: &zbus::Connection) -> bool {
+    let Ok(dbus) = fdo::DBusProxy::new(conn).await else {
+        return false;
+    };
+    let Ok(name) = zbus::names::BusName::try_from(SWITCHEROO_BUS_NAME) else {
+        return false;
+    };
+    dbus.name_has_owner(name).await.unwrap_or(false)
+}
 
 impl Daemon {
-    pub fn new(initial_mode: String) -> Result<Self, Box<dyn std::error::Error>> {
-        let pci_devices = iommu::read_pci_devices()?;
-        let gpu_list = gpu::list_gpu(&pci_devices)?;
-        let ebpf_blocker = EbpfBlocker::new()?;
+    pub fn new(
+        initial_mode: String,
+        settings: settings::Settings,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let fake_gpus = std::env::var_os(fake::FAKE_GPUS_ENV).filter(|path| !path.is_empty());
+        let (pci_devices, gpu_list) = match &fake_gpus {
+            Some(path) => {
+                let gpu_list = fake::load(std::path::Path::new(path))?;
+                warn!(
+                    "!!! {} is set: serving {} FAKE GPUs from {} over an in-memory blocker, nothing is really blocked (development only) !!!",
+                    fake::FAKE_GPUS_ENV,
+                    gpu_list.len(),
+                    std::path::Path::new(path).display()
+                );
+                (HashMap::new(), gpu_list)
+            }
+            None => {
+                let pci_devices = iommu::read_pci_devices()?;
+                let gpu_list = gpu::list_gpu(&pci_devices)?;
+                (pci_devices, gpu_list)
+            }
+        };
+        let managed_pci: HashSet<String> = settings
+            .managed_pci
+            .iter()
+            .map(|address| iommu::normalize_pci(address))
+            .collect();
+        for address in &managed_pci {
+            if !gpu_list.values().any(|gpu| gpu.pci_address() == address) {
+                warn!(
+                    "managed_pci entry {} matches no detected GPU, ignoring it",
+                    address
+                );
+            }
+        }
+        let pci_patterns: Vec<String> = settings
+            .blocked_pci_patterns
+            .iter()
+            .filter_map(|pattern| match iommu::normalize_pci_pattern(pattern) {
+                Ok(pattern) => Some(pattern),
+                Err(err) => {
+                    warn!("blocked_pci_patterns: {}, ignoring it", err);
+                    None
+                }
+            })
+            .collect();
+        let gpu_aliases = Self::validate_aliases(&settings.gpu_aliases, &gpu_list);
+        let integrated_keep = Some(settings.integrated_keep_pci.trim())
+            .filter(|address| !address.is_empty())
+            .map(iommu::normalize_pci)
+            .filter(|address| {
+                let detected = gpu_list.values().any(|gpu| gpu.pci_address() == address);
+                if !detected {
+                    warn!(
+                        "integrated_keep_pci {} matches no detected GPU, integrated mode keeps the boot GPU",
+                        address
+                    );
+                }
+                detected
+            });
+        let mut blocker: Box<dyn Blocker> = if fake_gpus.is_some() {
+            Box::new(MemoryBlocker::new())
+        } else {
+            Box::new(EbpfBlocker::new().map_err(|err| {
+                format!(
+                    "Failed to load and attach the eBPF blocker (needs root, BTF and the bpf LSM): {}",
+                    err
+                )
+            })?)
+        };
+        // Never lock the daemon out of its own device probing.
+        blocker
+            .exempt_pid(std::process::id())
+            .map_err(|err| format!("Failed to exempt the daemon from blocking: {}", err))?;
+
+        let audit_log = (!settings.audit_log.is_empty()).then(|| {
+            let path = std::path::Path::new(&settings.audit_log);
+            audit::AuditLog::open(path)
+                .inspect(|_| info!("Recording operations in audit log {}", path.display()))
+                .map_err(|err| {
+                    error!("Failed to open audit log {}: {}", path.display(), err);
+                    err.to_string()
+                })
+        });
 
         Ok(Self {
-            current_mode: RwLock::new(initial_mode),
-            _pci_devices: pci_devices,
-            gpu_list,
-            ebpf_blocker: tokio::sync::Mutex::new(ebpf_blocker),
+            audit_log: Arc::new(audit_log),
+            pci_devices: Arc::new(pci_devices),
+            managed_pci: Arc::new(managed_pci),
+            extra_block_prefixes: Arc::new(settings.extra_block_prefixes.clone()),
+            block_debounce: Duration::from_millis(settings.block_debounce_ms),
+            blocker_timeout: Duration::from_millis(settings.blocker_timeout_ms),
+            allow_framebuffer_release: settings.allow_framebuffer_release,
+            blocked_drivers: Arc::new(std::sync::Mutex::new(settings.blocked_drivers.clone())),
+            gpu_aliases: Arc::new(gpu_aliases),
+            settings: Arc::new(settings),
+            pci_patterns: Arc::new(std::sync::Mutex::new(pci_patterns)),
+            integrated_keep: Arc::new(std::sync::Mutex::new(integrated_keep)),
+            ..Self::with_parts(initial_mode, gpu_list, blocker)
         })
     }
 
-    fn get_current_hardware_mode(&self) -> Result<String, Box<dyn std::error::Error>> {
-        // eBPF starts with an empty block list, so default mode is hybrid.
-        Ok(MODE_HYBRID.to_string())
+    /// Daemon over an injected GPU list and blocker, without touching sysfs or loading
+    /// the eBPF program. Every GPU is managed and the timings are the config defaults.
+    pub fn with_parts(
+        initial_mode: String,
+        gpu_list: HashMap<String, gpu::Gpu>,
+        blocker: Box<dyn Blocker>,
+    ) -> Self {
+        let defaults = settings::Settings::default();
+        let shared_nodes: HashMap<String, Vec<usize>> =
+            gpu::shared_nodes(&gpu_list).into_iter().collect();
+        for (node, ids) in &shared_nodes {
+            warn!(
+                "DRM node {} is shared by GPUs {:?}, it won't be blocked while one of them stays available",
+                node, ids
+            );
+        }
+
+        Self {
+            current_mode: Arc::new(RwLock::new(initial_mode)),
+            pci_devices: Arc::new(HashMap::new()),
+            gpu_list: Arc::new(gpu_list),
+            managed_pci: Arc::new(HashSet::new()),
+            extra_block_prefixes: Arc::new(Vec::new()),
+            shared_nodes: Arc::new(shared_nodes),
+            capabilities: blocker.capabilities(),
+            ebpf_blocker: Arc::new(tokio::sync::Mutex::new(blocker)),
+            pending_blocks: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            block_debounce: Duration::from_millis(defaults.block_debounce_ms),
+            blocker_timeout: Duration::from_millis(defaults.blocker_timeout_ms),
+            mode_revert: Arc::new(std::sync::Mutex::new((0, None))),
+            pause_resume: Arc::new(std::sync::Mutex::new((0, None))),
+            reconcile_error: Arc::new(std::sync::Mutex::new(None)),
+            allow_framebuffer_release: false,
+            settings: Arc::new(defaults),
+            pci_patterns: Arc::new(std::sync::Mutex::new(Vec::new())),
+            blocked_drivers: Arc::new(std::sync::Mutex::new(Vec::new())),
+            gpu_aliases: Arc::new(HashMap::new()),
+            toggle_key: Arc::new(std::sync::Mutex::new(None)),
+            integrated_keep: Arc::new(std::sync::Mutex::new(None)),
+            read_only: Arc::new(std::sync::atomic::AtomicBool::new(false)),
+            block_changes: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            audit_log: Arc::new(None),
+            started_at: SystemTime::now(),
+            block_epoch: Arc::new(AtomicU64::new(0)),
+        }
     }
 
-    fn config_contents(mode: &str) -> String {
-        format!(
-            r#"# Chainsaw Daemon Configuration
-# This file was automatically generated
+    /// Keep the usable `gpu_aliases` entries, normalized. Aliases must look nothing
+    /// like an id or a PCI address, be unique, and point at a detected GPU.
+    fn validate_aliases(
+        aliases: &HashMap<String, String>,
+        gpu_list: &HashMap<String, gpu::Gpu>,
+    ) -> HashMap<String, String> {
+        let mut valid: HashMap<String, String> = HashMap::new();
+        let mut entries: Vec<(&String, &String)> = aliases.iter().collect();
+        entries.sort();
+        for (address, alias) in entries {
+            let address = iommu::normalize_pci(address);
+            let alias = alias.trim();
+            if alias.is_empty() || alias.contains(':') || alias.parse::<u32>().is_ok() {
+                warn!(
+                    "gpu_aliases: '{}' for {} can't be told apart from an id or PCI address, ignoring it",
+                    alias, address
+                );
+            } else if !gpu_list.values().any(|gpu| gpu.pci_address() == address) {
+                warn!(
+                    "gpu_aliases: {} ('{}') matches no detected GPU, ignoring it",
+                    address, alias
+                );
+            } else if valid.values().any(|existing| existing == alias) {
+                warn!(
+                    "gpu_aliases: '{}' is used twice, ignoring it for {}",
+                    alias, address
+                );
+            } else {
+                valid.insert(address, alias.to_string());
+            }
+        }
+        valid
+    }
 
-# GPU Mode: "integrated", "hybrid"
-mode = "{}"
-"#,
-            mode
-        )
+    /// The alias of a GPU, else its detected name.
+    fn display_name<'a>(&'a self, gpu: &'a gpu::Gpu) -> &'a str {
+        self.gpu_aliases
+            .get(gpu.pci_address())
+            .map_or(gpu.name(), String::as_str)
     }
 
-    fn ensure_config_exists() -> Result<(), Box<dyn std::error::Error>> {
-        if !std::path::Path::new(CONFIG_PATH).exists() {
-            info!(
-                "Config file not found, creating default config at {}",
-                CONFIG_PATH
-            );
-            std::fs::write(CONFIG_PATH, Self::config_contents(MODE_HYBRID))?;
+    /// The mode the block maps currently implement, read from the maps (pinned ones
+    /// may still hold an earlier run's blocks): `preferred` when it fits, else the first
+    /// supported mode that does, `None` when no mode does.
+    async fn get_current_hardware_mode(
+        &self,
+        preferred: &str,
+    ) -> Result<Option<String>, BlockerError> {
+        let mut effective = HashMap::new();
+        for gpu in self.gpu_list.values().filter(|gpu| self.is_managed(gpu)) {
+            effective.insert(gpu.id() as u32, self.is_gpu_blocked(gpu).await?);
         }
-        Ok(())
+        Ok(std::iter::once(preferred)
+            .chain(SUPPORTED_MODES.iter().copied())
+            .find(|mode| self.mode_matches(mode, &effective))
+            .map(str::to_string))
     }
 
     fn parse_node_id(node_path: &str, prefix: &str) -> Option<u32> {
@@ -73,234 +496,4225 @@ mode = "{}"
             .and_then(|id_str| id_str.parse::<u32>().ok())
     }
 
-    fn gpu_by_id(&self, id: u32) -> Option<&gpu::Gpu> {
-        self.gpu_list.values().find(|gpu| gpu.id() as u32 == id)
+    fn check_mode(mode: &str) -> fdo::Result<()> {
+        if SUPPORTED_MODES.contains(&mode) {
+            Ok(())
+        } else {
+            Err(fdo::Error::InvalidArgs(format!("Unknown mode={}", mode)))
+        }
     }
 
-    async fn is_gpu_blocked(&self, gpu: &gpu::Gpu) -> bool {
-        let mut blocker = self.ebpf_blocker.lock().await;
+    /// Whether chainsaw may block/unblock this GPU (`managed_pci`, empty = all).
+    fn is_managed(&self, gpu: &gpu::Gpu) -> bool {
+        self.managed_pci.is_empty() || self.managed_pci.contains(gpu.pci_address())
+    }
 
-        if let Ok(true) = blocker.is_pci_blocked(gpu.pci_address()) {
-            return true;
+    /// Block decision for a GPU under `mode`: `Some(block)` for GPUs the mode manages,
+    /// `None` for GPUs it leaves untouched (the boot GPU and unmanaged GPUs). With
+    /// `integrated_keep_pci` set, integrated mode keeps that GPU and blocks the boot
+    /// GPU like any other.
+    fn mode_block_decision(&self, mode: &str, gpu: &gpu::Gpu) -> Option<bool> {
+        if !self.is_managed(gpu) {
+            return None;
+        }
+        if mode == MODE_INTEGRATED
+            && let Some(keep) = self.integrated_keep_pci()
+        {
+            return Some(gpu.pci_address() != keep);
+        }
+        if gpu.is_default() {
+            return None;
+        }
+        if let Some(preset) = Self::preset(mode) {
+            let blocks_integrated = preset
+                .actions
+                .iter()
+                .any(|action| matches!(action, PresetAction::BlockIntegrated));
+            if blocks_integrated && gpu.is_integrated() {
+                return Some(true);
+            }
+            return self.mode_block_decision(preset.base, gpu);
+        }
+        match mode {
+            MODE_INTEGRATED => Some(true),
+            MODE_HYBRID => Some(false),
+            _ => None,
         }
+    }
 
-        if let Some(render_id) = Self::parse_node_id(gpu.render_node(), RENDER_NODE_PREFIX) {
-            match blocker.is_id_blocked(render_id) {
-                Ok(true) => return true,
-                Ok(false) => {}
-                Err(err) => warn!(
-                    "Failed to read render block state for {}: {}",
-                    gpu.pci_address(),
-                    err
-                ),
+    fn integrated_keep_pci(&self) -> Option<String> {
+        self.integrated_keep
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Make integrated mode keep `keep` (a normalized PCI address, `None` for the boot
+    /// GPU) and save it as `integrated_keep_pci`.
+    fn set_integrated_keep(&self, keep: Option<String>) {
+        if let Err(err) =
+            settings::save_string("integrated_keep_pci", keep.as_deref().unwrap_or_default())
+        {
+            warn!("Failed to save integrated_keep_pci to config: {}", err);
+        }
+        *self
+            .integrated_keep
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = keep;
+    }
+
+    fn preset(mode: &str) -> Option<&'static ModePreset> {
+        MODE_PRESETS.iter().find(|preset| preset.name == mode)
+    }
+
+    /// One-line description of what a mode does.
+    fn describe_mode(mode: &str) -> String {
+        match (mode, Self::preset(mode)) {
+            (_, Some(preset)) => {
+                let mut steps = vec![format!("{} blocks", preset.base)];
+                steps.extend(preset.actions.iter().map(|action| match action {
+                    PresetAction::BlockIntegrated => {
+                        "block the integrated GPU if it doesn't drive the display".to_string()
+                    }
+                    PresetAction::DiscretePowerProfile(level) => {
+                        format!("discrete GPU power profile {}", level)
+                    }
+                    PresetAction::PlatformProfile(profile) => {
+                        format!("platform profile {}", profile)
+                    }
+                }));
+                steps.join(", ")
             }
+            (MODE_INTEGRATED, None) => "block every managed GPU except the boot GPU".to_string(),
+            (MODE_HYBRID, None) => "allow every GPU".to_string(),
+            _ => "unknown".to_string(),
         }
+    }
 
-        if let Some(card_id) = Self::parse_node_id(gpu.card_node(), CARD_NODE_PREFIX) {
-            match blocker.is_id_blocked(card_id) {
-                Ok(true) => return true,
-                Ok(false) => {}
-                Err(err) => warn!(
-                    "Failed to read card block state for {}: {}",
-                    gpu.pci_address(),
-                    err
-                ),
+    /// Run the extra actions of a preset mode. Returns what was skipped or failed.
+    fn apply_preset_actions(&self, preset: &ModePreset) -> Vec<String> {
+        let mut skipped = Vec::new();
+        for action in preset.actions {
+            match action {
+                PresetAction::BlockIntegrated => {
+                    // Already part of the block decisions; only report why it didn't happen.
+                    match self.gpu_list.values().find(|gpu| gpu.is_integrated()) {
+                        None => skipped.push("no integrated GPU found".to_string()),
+                        Some(gpu) if gpu.is_default() => skipped.push(format!(
+                            "integrated GPU {} drives the display, left unblocked",
+                            gpu.id()
+                        )),
+                        Some(gpu) if !self.is_managed(gpu) => skipped
+                            .push(format!("integrated GPU {} isn't in managed_pci", gpu.id())),
+                        Some(_) => {}
+                    }
+                }
+                PresetAction::DiscretePowerProfile(level) => {
+                    for gpu in self.gpu_list.values().filter(|gpu| {
+                        !gpu.is_integrated() && !gpu.is_default() && self.is_managed(gpu)
+                    }) {
+                        if let Err(err) = gpu.set_power_profile(level) {
+                            skipped.push(format!(
+                                "power profile {} on GPU {}: {}",
+                                level,
+                                gpu.id(),
+                                err
+                            ));
+                        }
+                    }
+                }
+                PresetAction::PlatformProfile(profile) => {
+                    if let Err(err) = gpu::set_platform_profile(profile) {
+                        skipped.push(format!("platform profile {}: {}", profile, err));
+                    }
+                }
             }
         }
+        skipped
+    }
 
-        false
+    /// Modes that change something here: both need a managed GPU besides the boot GPU,
+    /// otherwise `integrated` would have nothing to block (or block the only GPU).
+    fn available_modes(&self) -> Vec<String> {
+        let has_secondary = self
+            .gpu_list
+            .values()
+            .any(|gpu| !gpu.is_default() && self.is_managed(gpu));
+        SUPPORTED_MODES
+            .iter()
+            .filter(|mode| match **mode {
+                MODE_INTEGRATED | MODE_HYBRID | MODE_GAMING => has_secondary,
+                _ => true,
+            })
+            .map(|mode| mode.to_string())
+            .collect()
     }
 
-    async fn list_gpu_rows(&self) -> Vec<GpuRow> {
-        let mut rows = Vec::with_capacity(self.gpu_list.len());
-        for gpu in self.gpu_list.values() {
-            let blocked = self.is_gpu_blocked(gpu).await;
-            rows.push((
-                gpu.id() as u32,
-                gpu.name().to_string(),
-                gpu.pci_address().to_string(),
-                gpu.render_node().to_string(),
-                gpu.is_default(),
-                blocked,
+    /// Per-GPU block decisions for `mode`, sorted by GPU id.
+    fn preview_mode_blocks(&self, mode: &str) -> Vec<(u32, bool)> {
+        let mut preview: Vec<(u32, bool)> = self
+            .gpu_list
+            .values()
+            .map(|gpu| {
+                (
+                    gpu.id() as u32,
+                    self.mode_block_decision(mode, gpu).unwrap_or(false),
+                )
+            })
+            .collect();
+        preview.sort_by_key(|(id, _)| *id);
+        preview
+    }
+
+    /// Why switching to `mode` can't fully apply until the session restarts: a GPU it
+    /// would block or unblock is driving a display (the boot GPU, or one with a
+    /// connected connector), and the compositor only picks GPUs up or lets go of them
+    /// when it starts. Conservative: a GPU whose current state can't be read counts as
+    /// changing, and a transition touching no such GPU never needs a restart.
+    async fn session_restart_reasons(&self, mode: &str) -> Vec<String> {
+        let mut gpus: Vec<&gpu::Gpu> = self.gpu_list.values().collect();
+        gpus.sort_by_key(|gpu| gpu.id());
+        let mut reasons = Vec::new();
+        for gpu in gpus {
+            let Some(block) = self.mode_block_decision(mode, gpu) else {
+                continue;
+            };
+            if self.is_gpu_blocked(gpu).await.ok() == Some(block) {
+                continue;
+            }
+            let connected: Vec<String> = gpu
+                .connectors()
+                .into_iter()
+                .filter(|connector| connector.status == "connected")
+                .map(|connector| connector.name)
+                .collect();
+            let driving = if !connected.is_empty() {
+                format!("drives display {}", connected.join(", "))
+            } else if gpu.is_default() {
+                "is the boot GPU".to_string()
+            } else {
+                continue;
+            };
+            reasons.push(format!(
+                "GPU {} ({}) {} and would be {}: log out and back in for this to fully apply",
+                gpu.id(),
+                gpu.pci_address(),
+                driving,
+                if block { "blocked" } else { "unblocked" }
             ));
         }
-        rows.sort_by_key(|row| row.0);
-        rows
+        reasons
     }
 
-    async fn apply_gpu_block_policy(&self, gpu: &gpu::Gpu, block: bool) {
-        let mut blocker = self.ebpf_blocker.lock().await;
+    /// Whether a GPU is driving a display and must never be blocked: the boot GPU, or
+    /// one with a connected connector.
+    fn drives_display(gpu: &gpu::Gpu) -> bool {
+        gpu.is_default()
+            || gpu
+                .connectors()
+                .iter()
+                .any(|connector| connector.status == "connected")
+    }
 
-        if let Some(id) = Self::parse_node_id(gpu.render_node(), RENDER_NODE_PREFIX) {
-            let result = if block {
-                blocker.block_id(id)
-            } else {
-                blocker.unblock_id(id)
-            };
-            if let Err(err) = result {
-                warn!(
-                    "Failed to {} render node id {} for {}: {}",
-                    if block { "block" } else { "unblock" },
-                    id,
-                    gpu.pci_address(),
-                    err
-                );
+    /// Unblock `target` and block every other managed non-default GPU, as one block set
+    /// swap. Returns the ids of the GPUs blocked.
+    async fn make_exclusive(&self, target: &gpu::Gpu) -> fdo::Result<Vec<usize>> {
+        let decisions = self.exclusive_block_decisions(target)?;
+
+        // One block set swap, so a failure can't leave some GPUs switched over.
+        let mut targets = Vec::new();
+        let mut before = Vec::new();
+        let mut blocked = Vec::new();
+        for (gpu, block) in decisions {
+            if block {
+                blocked.push(gpu.id());
+            }
+            if !self.check_block_needed(gpu, block) {
+                continue;
+            }
+            targets.push((self.block_target(gpu), block));
+            before.push((gpu, self.is_gpu_blocked(gpu).await.ok()));
+        }
+        blocked.sort();
+        let applied = self
+            .with_blocker_write("set exclusive GPU", move |blocker| {
+                Self::apply_mode_blocks_locked(blocker, &targets)
+            })
+            .await?;
+        for (gpu, before) in before {
+            self.record_block_change(gpu, before).await;
+        }
+        applied.map_err(|err| {
+            fdo::Error::Failed(format!(
+                "Failed to make GPU {} exclusive: {}",
+                target.id(),
+                err
+            ))
+        })?;
+        Ok(blocked)
+    }
+
+    /// Block decisions for running on `target` only: it is unblocked and every other
+    /// non-boot GPU is blocked.
+    fn exclusive_block_decisions<'a>(
+        &'a self,
+        target: &gpu::Gpu,
+    ) -> fdo::Result<Vec<(&'a gpu::Gpu, bool)>> {
+        let mut decisions = Vec::with_capacity(self.gpu_list.len());
+        for gpu in self.gpu_list.values() {
+            if gpu.id() == target.id() {
+                decisions.push((gpu, false));
+            } else if !gpu.is_default() && self.is_managed(gpu) {
+                if Self::drives_display(gpu) {
+                    return Err(fdo::Error::InvalidArgs(format!(
+                        "GPU {} ({}) drives the display and can't be blocked",
+                        gpu.id(),
+                        gpu.pci_address()
+                    )));
+                }
+                decisions.push((gpu, true));
             }
         }
+        Ok(decisions)
+    }
 
-        if let Some(id) = Self::parse_node_id(gpu.card_node(), CARD_NODE_PREFIX) {
-            let result = if block {
-                blocker.block_id(id)
-            } else {
-                blocker.unblock_id(id)
+    fn gpu_by_id(&self, id: u32) -> Option<&gpu::Gpu> {
+        self.gpu_list.values().find(|gpu| gpu.id() as u32 == id)
+    }
+
+    /// DRM nodes of a GPU paired with the prefix their id is parsed from.
+    fn gpu_nodes(gpu: &gpu::Gpu) -> [(&'static str, &str, &'static str); 2] {
+        BUILTIN_NODES.map(|(kind, node, prefix)| (kind, node(gpu), prefix))
+    }
+
+    /// Every node blocked for a GPU: the built-in DRM nodes, then device nodes under
+    /// `extra_block_prefixes` that belong to it, then its `/dev/fb*` framebuffers with
+    /// `block_fb_nodes` (the latter two blocked by device number).
+    fn block_nodes(&self, gpu: &gpu::Gpu) -> Vec<BlockNode> {
+        let mut nodes: Vec<BlockNode> = Self::gpu_nodes(gpu)
+            .into_iter()
+            .map(|(kind, node, prefix)| (kind, node.to_string(), Some(prefix)))
+            .collect();
+        for prefix in self.extra_block_prefixes.iter() {
+            for node in Self::nodes_under_prefix(prefix, gpu.pci_address()) {
+                if !nodes.iter().any(|(_, known, _)| *known == node) {
+                    nodes.push(("extra", node, None));
+                }
+            }
+        }
+        // The boot GPU's framebuffer is the console's, so it's never blocked.
+        if self.settings.block_fb_nodes && !gpu.is_default() {
+            for node in Self::nodes_under_prefix(FB_NODE_PREFIX, gpu.pci_address()) {
+                if !nodes.iter().any(|(_, known, _)| *known == node) {
+                    nodes.push(("fb", node, None));
+                }
+            }
+        }
+        nodes
+    }
+
+    /// Character devices whose path starts with `prefix` and whose sysfs device is (or
+    /// sits below) the PCI device `pci`.
+    fn nodes_under_prefix(prefix: &str, pci: &str) -> Vec<String> {
+        Self::nodes_matching(prefix, |devno| {
+            Self::char_device_owner(devno).is_some_and(|owner| {
+                owner
+                    .components()
+                    .any(|part| part.as_os_str() == std::ffi::OsStr::new(pci))
+            })
+        })
+    }
+
+    /// Character devices whose path starts with `prefix` and whose device number
+    /// `owned` accepts, sorted.
+    fn nodes_matching(prefix: &str, owned: impl Fn(u64) -> bool) -> Vec<String> {
+        let prefix_path = std::path::Path::new(prefix);
+        let (Some(dir), Some(stem)) = (
+            prefix_path.parent(),
+            prefix_path.file_name().and_then(|name| name.to_str()),
+        ) else {
+            return Vec::new();
+        };
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return Vec::new();
+        };
+
+        let mut nodes: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(stem))
+            .filter_map(|entry| {
+                let node = entry.path().to_string_lossy().into_owned();
+                let devno = Self::node_devno(&node)?;
+                owned(devno).then_some(node)
+            })
+            .collect();
+        nodes.sort();
+        nodes
+    }
+
+    /// Resolved sysfs device path of a character device (`/sys/dev/char/M:m/device`).
+    fn char_device_owner(devno: u64) -> Option<std::path::PathBuf> {
+        let major = ((devno >> 8) & 0xfff) | ((devno >> 32) & !0xfff);
+        let minor = (devno & 0xff) | ((devno >> 12) & !0xff);
+        std::fs::canonicalize(format!("/sys/dev/char/{}:{}/device", major, minor)).ok()
+    }
+
+    /// Other GPUs sharing each of `gpu`'s nodes: node -> [(pci, is_default)].
+    fn node_sharers(&self, gpu: &gpu::Gpu) -> HashMap<String, Vec<(String, bool)>> {
+        let mut sharers = HashMap::new();
+        for (_, node, _) in Self::gpu_nodes(gpu) {
+            let Some(ids) = self.shared_nodes.get(node) else {
+                continue;
             };
-            if let Err(err) = result {
+            let others: Vec<(String, bool)> = ids
+                .iter()
+                .filter(|id| **id != gpu.id())
+                .filter_map(|id| self.gpu_by_id(*id as u32))
+                .map(|other| (other.pci_address().to_string(), other.is_default()))
+                .collect();
+            sharers.insert(node.to_string(), others);
+        }
+        sharers
+    }
+
+    /// Run `op` on the blocker from a blocking worker, giving up after `blocker_timeout`.
+    /// On timeout the worker keeps the lock until the operation returns, so later calls
+    /// time out too instead of stalling the interface.
+    async fn with_blocker<T, F>(&self, what: &str, op: F) -> Result<T, BlockerError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut dyn Blocker) -> T + Send + 'static,
+    {
+        let blocker = self.ebpf_blocker.clone();
+        let task = async move {
+            let mut guard = blocker.lock_owned().await;
+            tokio::task::spawn_blocking(move || op(&mut **guard)).await
+        };
+        match tokio::time::timeout(self.blocker_timeout, task).await {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(err)) => {
+                warn!("Blocker operation '{}' failed: {}", what, err);
+                Err(BlockerError::Task(err.to_string()))
+            }
+            Err(_) => {
                 warn!(
-                    "Failed to {} card node id {} for {}: {}",
-                    if block { "block" } else { "unblock" },
-                    id,
-                    gpu.pci_address(),
-                    err
+                    "Blocker operation '{}' timed out after {}ms",
+                    what,
+                    self.blocker_timeout.as_millis()
                 );
+                Err(BlockerError::Timeout(self.blocker_timeout))
             }
         }
+    }
 
-        let pci_result = if block {
-            blocker.block_pci(gpu.pci_address())
-        } else {
-            blocker.unblock_pci(gpu.pci_address())
+    /// `with_blocker` for operations that change GPU block state.
+    async fn with_blocker_write<T, F>(&self, what: &str, op: F) -> Result<T, BlockerError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut dyn Blocker) -> T + Send + 'static,
+    {
+        if self.read_only.load(Ordering::SeqCst) {
+            return Err(BlockerError::ReadOnly);
+        }
+        self.block_epoch.fetch_add(1, Ordering::SeqCst);
+        let result = self.with_blocker(what, op).await;
+        self.block_epoch.fetch_add(1, Ordering::SeqCst);
+        result
+    }
+
+    /// Loaded module parameters that work against blocking the detected GPUs.
+    fn module_param_conflicts(&self) -> Vec<String> {
+        let vendors: Vec<gpu::Vendor> = self.gpu_list.values().map(|gpu| gpu.vendor()).collect();
+        gpu::module_param_conflicts(&vendors)
+    }
+
+    /// Record an operation and its outcome in the audit log, when one is configured.
+    fn audit<T>(&self, caller: &str, operation: &str, result: &fdo::Result<T>) {
+        let Some(Ok(audit_log)) = self.audit_log.as_ref() else {
+            return;
         };
-        if let Err(err) = pci_result {
+        let outcome = match result {
+            Ok(_) => "ok".to_string(),
+            Err(err) => format!("error: {}", err),
+        };
+        if let Err(err) = audit_log.record(caller, operation, &outcome) {
             warn!(
-                "Failed to {} PCI access for {}: {}",
-                if block { "block" } else { "unblock" },
-                gpu.pci_address(),
+                "Failed to write audit log {}: {}",
+                audit_log.path().display(),
                 err
             );
         }
     }
 
-    fn save_mode_to_config(mode: &str) -> Result<(), Box<dyn std::error::Error>> {
-        std::fs::write(CONFIG_PATH, Self::config_contents(mode))?;
+    /// Run a D-Bus operation and audit it under the caller's unique bus name.
+    async fn audited<T>(
+        &self,
+        header: &Header<'_>,
+        operation: String,
+        run: impl Future<Output = fdo::Result<T>>,
+    ) -> fdo::Result<T> {
+        let result = run.await;
+        let caller = header
+            .sender()
+            .map_or_else(|| "unknown".to_string(), |sender| sender.to_string());
+        self.audit(&caller, &operation, &result);
+        result
+    }
+
+    /// Refuse changes the blocker doesn't see (power profiles, saved settings...) while
+    /// deferring to switcheroo-control.
+    fn check_writable(&self) -> fdo::Result<()> {
+        if self.read_only.load(Ordering::SeqCst) {
+            return Err(BlockerError::ReadOnly.into());
+        }
         Ok(())
     }
-}
-#[interface(name = "com.chainsaw.daemon")]
-impl Daemon {
-    /// Set the GPU mode.
-    ///
-    /// "integrated", "hybrid".
-    async fn set_mode(&self, mode: String) -> fdo::Result<String> {
-        let mut current_mode_lock = self.current_mode.write().await;
-        let block_non_boot_gpu = match mode.as_str() {
-            MODE_INTEGRATED => true,
-            MODE_HYBRID => false,
-            _ => return Err(fdo::Error::InvalidArgs(format!("Unknown mode={}", mode))),
-        };
 
-        for gpu in self.gpu_list.values() {
-            if !gpu.is_default() {
-                self.apply_gpu_block_policy(gpu, block_non_boot_gpu).await;
-            }
+    /// Device number of a character device node, used when the node name doesn't parse.
+    fn node_devno(node_path: &str) -> Option<u64> {
+        std::fs::metadata(node_path)
+            .ok()
+            .filter(|meta| meta.file_type().is_char_device())
+            .map(|meta| meta.rdev())
+    }
+
+    /// cgroup v2 id of a cgroup directory, which is its inode number. Paths outside the
+    /// cgroup root (as in /proc/<pid>/cgroup) are taken relative to it.
+    fn cgroup_id(path: &str) -> Result<u64, String> {
+        let root = std::path::Path::new(CGROUP_ROOT);
+        if !root.join("cgroup.controllers").exists() {
+            return Err(format!("{} is not a cgroup v2 hierarchy", CGROUP_ROOT));
+        }
+        let path = std::path::Path::new(path);
+        let path = if path.starts_with(root) {
+            path.to_path_buf()
+        } else {
+            root.join(path.strip_prefix("/").unwrap_or(path))
+        };
+        let meta = std::fs::metadata(&path)
+            .map_err(|err| format!("can't stat {}: {}", path.display(), err))?;
+        if !meta.is_dir() || !path.join("cgroup.procs").exists() {
+            return Err(format!("{} is not a cgroup", path.display()));
         }
+        Ok(meta.ino())
+    }
 
-        *current_mode_lock = mode.clone();
-        if let Err(err) = Self::save_mode_to_config(&mode) {
-            warn!("Failed to save mode to config: {}", err);
+    async fn is_gpu_blocked(&self, gpu: &gpu::Gpu) -> Result<bool, BlockerError> {
+        // Nothing can use a disabled card, whatever the maps say.
+        if self.gpu_enabled(gpu) == Some(false) {
+            return Ok(true);
         }
+        let pci = gpu.pci_address().to_string();
+        let nodes = self.block_nodes(gpu);
 
-        info!("Set mode to {}", mode);
-        Ok(format!("Set mode to {}", mode))
+        self.with_blocker("read GPU block state", move |blocker| {
+            if let Ok(true) = blocker.is_pci_blocked(&pci) {
+                return true;
+            }
+
+            for (kind, node, prefix) in nodes {
+                let result = match prefix.and_then(|prefix| Self::parse_node_id(&node, prefix)) {
+                    Some(id) => blocker.is_id_blocked(id),
+                    None => match Self::node_devno(&node) {
+                        Some(devno) => blocker.is_dev_blocked(devno),
+                        None => continue,
+                    },
+                };
+                match result {
+                    Ok(true) => return true,
+                    Ok(false) => {}
+                    Err(err) => warn!("Failed to read {} block state for {}: {}", kind, pci, err),
+                }
+            }
+
+            false
+        })
+        .await
     }
-    /// Get the current GPU mode value.
-    async fn get_mode(&self) -> String {
-        self.current_mode.read().await.clone()
+
+    /// Whether all, some or none of a GPU's block entries are set. Shared nodes (which
+    /// blocking may legitimately skip) and nodes that don't exist aren't counted.
+    async fn block_consistency(&self, gpu: &gpu::Gpu) -> Result<BlockState, BlockerError> {
+        if self.gpu_enabled(gpu) == Some(false) {
+            return Ok(BlockState::FullyBlocked);
+        }
+        let states: Vec<bool> = self
+            .block_entries(gpu)
+            .await?
+            .into_iter()
+            .map(|(_, blocked)| blocked)
+            .collect();
+        Ok(BlockState::from_entries(&states))
     }
-    /// List human-readable supported modes.
-    async fn list_mode(&self) -> Vec<String> {
-        vec![MODE_INTEGRATED.to_string(), MODE_HYBRID.to_string()]
+
+    /// `is_gpu_blocked`, answered from a snapshot of the maps.
+    fn snapshot_gpu_blocked(&self, snapshot: &BlockSnapshot, gpu: &gpu::Gpu) -> bool {
+        if self.gpu_enabled(gpu) == Some(false) {
+            return true;
+        }
+        snapshot.is_pci_blocked(gpu.pci_address())
+            || self.block_nodes(gpu).iter().any(|(_, node, prefix)| {
+                match prefix.and_then(|prefix| Self::parse_node_id(node, prefix)) {
+                    Some(id) => snapshot.is_id_blocked(id),
+                    None => {
+                        Self::node_devno(node).is_some_and(|devno| snapshot.is_dev_blocked(devno))
+                    }
+                }
+            })
     }
 
-    /// List discovered GPUs with block state.
-    async fn list_gpus(&self) -> Vec<GpuRow> {
-        self.list_gpu_rows().await
+    /// `block_consistency`, answered from a snapshot of the maps.
+    fn snapshot_block_state(&self, snapshot: &BlockSnapshot, gpu: &gpu::Gpu) -> BlockState {
+        if self.gpu_enabled(gpu) == Some(false) {
+            return BlockState::FullyBlocked;
+        }
+        let target = self.block_target(gpu);
+        let mut states = vec![snapshot.is_pci_blocked(gpu.pci_address())];
+        for (_, node, prefix) in &target.nodes {
+            if target.sharers.contains_key(node) {
+                continue;
+            }
+            let Some(devno) = Self::node_devno(node) else {
+                continue;
+            };
+            states.push(
+                match prefix.and_then(|prefix| Self::parse_node_id(node, prefix)) {
+                    Some(id) => snapshot.is_id_blocked(id),
+                    None => snapshot.is_dev_blocked(devno),
+                },
+            );
+        }
+        BlockState::from_entries(&states)
     }
 
-    /// Block or unblock one GPU by ID.
-    async fn set_gpu_block(&self, gpu_id: u32, blocked: bool) -> fdo::Result<String> {
-        let gpu = self
-            .gpu_by_id(gpu_id)
-            .ok_or_else(|| fdo::Error::InvalidArgs(format!("Unknown gpu id={}", gpu_id)))?;
+    /// The GPU's own block entries as read from the maps: its PCI address, then each
+    /// existing node not shared with another GPU, with whether it's blocked.
+    async fn block_entries(&self, gpu: &gpu::Gpu) -> Result<Vec<(String, bool)>, BlockerError> {
+        let pci = gpu.pci_address().to_string();
+        let target = self.block_target(gpu);
 
-        self.apply_gpu_block_policy(gpu, blocked).await;
-        let now_blocked = self.is_gpu_blocked(gpu).await;
-        info!(
-            "Set GPU {} ({}) block={} (effective={})",
-            gpu_id,
-            gpu.pci_address(),
-            blocked,
-            now_blocked
-        );
+        self.with_blocker("read GPU block entries", move |blocker| {
+            let mut entries = Vec::with_capacity(target.nodes.len() + 1);
+            match blocker.is_pci_blocked(&pci) {
+                Ok(blocked) => entries.push(("pci".to_string(), blocked)),
+                Err(err) => warn!("Failed to read PCI block state for {}: {}", pci, err),
+            }
+            for (kind, node, prefix) in &target.nodes {
+                if target.sharers.contains_key(node) {
+                    continue;
+                }
+                let Some(devno) = Self::node_devno(node) else {
+                    continue;
+                };
+                let result = match prefix.and_then(|prefix| Self::parse_node_id(node, prefix)) {
+                    Some(id) => blocker.is_id_blocked(id),
+                    None => blocker.is_dev_blocked(devno),
+                };
+                match result {
+                    Ok(blocked) => entries.push((node.clone(), blocked)),
+                    Err(err) => warn!("Failed to read {} block state for {}: {}", kind, pci, err),
+                }
+            }
+            entries
+        })
+        .await
+    }
 
-        Ok(format!(
-            "GPU {} block {} (effective={})",
-            gpu_id,
-            if blocked { "on" } else { "off" },
-            now_blocked
-        ))
+    /// The GPU's sysfs `enable` state, `None` when unknown.
+    fn gpu_enabled(&self, gpu: &gpu::Gpu) -> Option<bool> {
+        self.pci_devices
+            .get(gpu.pci_address())
+            .and_then(Device::is_enabled)
     }
-}
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let _ = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
-        .format_timestamp_millis()
-        .try_init();
+    /// Opens denied by this GPU's blocks since they were set, summed over its PCI
+    /// address and nodes.
+    async fn gpu_block_hits(&self, gpu: &gpu::Gpu) -> Result<Result<u64, String>, BlockerError> {
+        let mut keys = vec![HitKey::Pci(gpu.pci_address().to_string())];
+        for (_, node, prefix) in self.block_nodes(gpu) {
+            match prefix.and_then(|prefix| Self::parse_node_id(&node, prefix)) {
+                Some(id) => keys.push(HitKey::Id(id)),
+                None => keys.extend(Self::node_devno(&node).map(HitKey::Dev)),
+            }
+        }
 
-    Daemon::ensure_config_exists()?;
-    let settings = Config::builder()
-        .add_source(config::File::with_name(CONFIG_PATH).required(false))
-        .build()?;
+        self.with_blocker("read block hits", move |blocker| {
+            let hits = blocker.block_hits().map_err(|err| err.to_string())?;
+            Ok(keys.iter().filter_map(|key| hits.get(key)).sum())
+        })
+        .await
+    }
 
-    let configured_mode = settings
-        .get_string("mode")
-        .unwrap_or_else(|_| MODE_HYBRID.to_string());
-    let daemon = Daemon::new(configured_mode.clone())?;
+    fn gpu_by_id_or_err(&self, gpu_id: u32) -> fdo::Result<&gpu::Gpu> {
+        self.gpu_by_id(gpu_id)
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("Unknown gpu id={}", gpu_id)))
+    }
 
-    info!("Detected GPUs:");
-    for gpu in daemon.gpu_list.values() {
-        info!(
-            "- [#{}] {} | pci={} | render={} | default={}",
-            gpu.id(),
-            gpu.name(),
-            gpu.pci_address(),
-            gpu.render_node(),
-            gpu.is_default()
+    fn managed_gpu_by_id_or_err(&self, gpu_id: u32) -> fdo::Result<&gpu::Gpu> {
+        let gpu = self.gpu_by_id_or_err(gpu_id)?;
+        if !self.is_managed(gpu) {
+            return Err(fdo::Error::InvalidArgs(format!(
+                "GPU {} ({}) isn't in managed_pci",
+                gpu_id,
+                gpu.pci_address()
+            )));
+        }
+        Ok(gpu)
+    }
+
+    async fn gpu_info(&self, gpu: &gpu::Gpu) -> Result<GpuInfo, BlockerError> {
+        let blocked = self.is_gpu_blocked(gpu).await?;
+        let block_state = self.block_consistency(gpu).await?;
+        let power = gpu
+            .power_draw_watts()
+            .map(|watts| format!("{:.2}", watts))
+            .unwrap_or_else(|| "n/a".to_string());
+        let temp = gpu
+            .temperature_celsius()
+            .map(|celsius| format!("{:.1}", celsius))
+            .unwrap_or_else(|| "n/a".to_string());
+        let (vram_used, vram_total) = match gpu.vram_usage() {
+            Some((used, total)) => (used.to_string(), total.to_string()),
+            None => ("n/a".to_string(), "n/a".to_string()),
+        };
+        let link = self
+            .pci_devices
+            .get(gpu.pci_address())
+            .and_then(Device::link_status)
+            .map(|(speed, width)| format!("{} x{}", speed, width))
+            .unwrap_or_else(|| "unknown".to_string());
+        let bridges = iommu::upstream_bridges(gpu.pci_address()).unwrap_or_default();
+        let bridge_chain = if bridges.is_empty() {
+            "none".to_string()
+        } else {
+            bridges
+                .iter()
+                .map(|bridge| format!("{} (acs {})", bridge.pci_address, bridge.acs.as_str()))
+                .collect::<Vec<_>>()
+                .join(" > ")
+        };
+        let passthrough_issues =
+            iommu::passthrough_issues(gpu.pci_address(), &self.pci_devices, &bridges);
+        let reset_method = self
+            .pci_devices
+            .get(gpu.pci_address())
+            .and_then(Device::reset_method)
+            .unwrap_or_else(|| "none".to_string());
+        let hits = self
+            .gpu_block_hits(gpu)
+            .await?
+            .map(|hits| hits.to_string())
+            .unwrap_or_else(|_| "n/a".to_string());
+        let enabled = self
+            .gpu_enabled(gpu)
+            .map(|enabled| enabled.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        let subsystem = gpu
+            .subsystem_ids()
+            .map(|(vendor, device)| format!("{:04x}:{:04x}", vendor, device))
+            .unwrap_or_else(|| "unknown".to_string());
+        let connectors = gpu.connectors();
+        let connectors = if connectors.is_empty() {
+            "none".to_string()
+        } else {
+            connectors
+                .iter()
+                .map(|connector| format!("{} ({})", connector.name, connector.status))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let last_change = self
+            .block_changes
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(&(gpu.id() as u32))
+            .copied();
+        let (last_change, last_change_at) = match last_change {
+            Some((time, blocked)) => (
+                String::from(if blocked { "blocked" } else { "unblocked" }),
+                time.duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|since| since.as_secs())
+                    .unwrap_or(0)
+                    .to_string(),
+            ),
+            None => ("none".to_string(), "never".to_string()),
+        };
+
+        Ok(vec![
+            ("id".to_string(), gpu.id().to_string()),
+            ("name".to_string(), self.display_name(gpu).to_string()),
+            ("detected_name".to_string(), gpu.name().to_string()),
+            ("pci".to_string(), gpu.pci_address().to_string()),
+            ("render".to_string(), gpu.render_node().to_string()),
+            ("card".to_string(), gpu.card_node().to_string()),
+            ("default".to_string(), gpu.is_default().to_string()),
+            ("slot".to_string(), gpu.slot().to_string()),
+            ("subsystem".to_string(), subsystem),
+            ("blocked".to_string(), blocked.to_string()),
+            ("block_state".to_string(), block_state.as_str().to_string()),
+            // Since daemon start; `last_block_change_at` is a Unix timestamp.
+            ("last_block_change".to_string(), last_change),
+            ("last_block_change_at".to_string(), last_change_at),
+            ("driver".to_string(), self.driver_label(gpu)),
+            ("managed".to_string(), self.is_managed(gpu).to_string()),
+            ("enabled".to_string(), enabled),
+            ("nodes_present".to_string(), gpu.nodes_present().to_string()),
+            ("connectors".to_string(), connectors),
+            ("power_draw_w".to_string(), power),
+            ("temp_c".to_string(), temp),
+            ("vram_used_bytes".to_string(), vram_used),
+            ("vram_total_bytes".to_string(), vram_total),
+            ("pcie_link".to_string(), link),
+            ("bridge_chain".to_string(), bridge_chain),
+            ("reset_method".to_string(), reset_method),
+            (
+                "passthrough_ready".to_string(),
+                passthrough_issues.is_empty().to_string(),
+            ),
+            (
+                "passthrough_issues".to_string(),
+                if passthrough_issues.is_empty() {
+                    "none".to_string()
+                } else {
+                    passthrough_issues.join("; ")
+                },
+            ),
+            ("block_hits".to_string(), hits),
+        ])
+    }
+
+    /// Compare the effective block state with what the current mode expects.
+    /// Returns the mismatching GPUs as human-readable descriptions.
+    async fn mode_mismatches(&self) -> Vec<String> {
+        let mode = self.current_mode.read().await.clone();
+        let mut mismatches = Vec::new();
+        for gpu in self.gpu_list.values() {
+            let Some(expected) = self.mode_block_decision(&mode, gpu) else {
+                continue;
+            };
+            let blocked = match self.is_gpu_blocked(gpu).await {
+                Ok(blocked) => blocked,
+                Err(err) => {
+                    mismatches.push(format!(
+                        "GPU {} ({}) block state unknown: {}",
+                        gpu.id(),
+                        gpu.pci_address(),
+                        err
+                    ));
+                    continue;
+                }
+            };
+            if blocked != expected {
+                mismatches.push(format!(
+                    "GPU {} ({}) blocked={} but mode {} expects blocked={}",
+                    gpu.id(),
+                    gpu.pci_address(),
+                    blocked,
+                    mode,
+                    expected
+                ));
+            }
+        }
+        mismatches.sort();
+        mismatches
+    }
+
+    /// Bound driver name, "none" when no driver is bound.
+    fn driver_label(&self, gpu: &gpu::Gpu) -> String {
+        let Some(device) = self.pci_devices.get(gpu.pci_address()) else {
+            return "unknown".to_string();
+        };
+        match device.current_driver() {
+            Ok(Some(driver)) => driver,
+            Ok(None) => "none".to_string(),
+            Err(err) => {
+                warn!("Failed to read driver of {}: {}", gpu.pci_address(), err);
+                "error".to_string()
+            }
+        }
+    }
+
+    async fn list_gpu_rows(&self) -> Result<Vec<GpuRow>, BlockerError> {
+        // One read of each map for the whole list, instead of lookups per GPU node.
+        let snapshot = self
+            .with_blocker("snapshot block maps", |blocker| {
+                blocker.snapshot().map_err(|err| err.to_string())
+            })
+            .await?
+            .inspect_err(|err| {
+                warn!(
+                    "Failed to snapshot the block maps, reading them per GPU: {}",
+                    err
+                )
+            })
+            .ok();
+        let mut rows = Vec::with_capacity(self.gpu_list.len());
+        for gpu in self.gpu_list.values() {
+            let (blocked, state) = match &snapshot {
+                Some(snapshot) => (
+                    self.snapshot_gpu_blocked(snapshot, gpu),
+                    self.snapshot_block_state(snapshot, gpu),
+                ),
+                None => (
+                    self.is_gpu_blocked(gpu).await?,
+                    self.block_consistency(gpu).await?,
+                ),
+            };
+            rows.push((
+                gpu.id() as u32,
+                self.display_name(gpu).to_string(),
+                gpu.pci_address().to_string(),
+                gpu.render_node().to_string(),
+                gpu.is_default(),
+                blocked,
+                self.driver_label(gpu),
+                self.is_managed(gpu),
+                state.as_str().to_string(),
+            ));
+        }
+        rows.sort_by_key(|row| row.0);
+        Ok(rows)
+    }
+
+    async fn apply_gpu_block_policy(
+        &self,
+        gpu: &gpu::Gpu,
+        block: bool,
+    ) -> Result<(), BlockerError> {
+        if !self.is_managed(gpu) {
+            info!(
+                "Leaving unmanaged GPU {} ({}) untouched",
+                gpu.id(),
+                gpu.pci_address()
+            );
+            return Ok(());
+        }
+
+        if !self.check_block_needed(gpu, block) {
+            return Ok(());
+        }
+
+        let before = self.is_gpu_blocked(gpu).await.ok();
+        let target = self.block_target(gpu);
+        let action = if block { "block" } else { "unblock" };
+        let applied = self
+            .with_blocker_write(action, move |blocker| {
+                Self::apply_block_locked(blocker, &target, block)
+            })
+            .await?;
+        // Some entries may have changed before the failure.
+        self.record_block_change(gpu, before).await;
+        applied.map_err(BlockerError::Map)
+    }
+
+    /// Apply `mode`'s decision to one GPU alone, e.g. one that just appeared, without
+    /// re-applying the whole mode. A GPU the mode leaves untouched stays as it is.
+    async fn apply_policy_to(&self, gpu: &gpu::Gpu, mode: &str) -> Result<(), BlockerError> {
+        match self.mode_block_decision(mode, gpu) {
+            Some(block) => self.apply_gpu_block_policy(gpu, block).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Unblock a removed GPU's PCI address and node ids, so an unplugged eGPU leaves
+    /// no entries behind.
+    async fn clear_gpu_entries(&self, gpu: &gpu::Gpu) -> Result<(), BlockerError> {
+        let target = self.block_target(gpu);
+        self.with_blocker_write("clear removed GPU", move |blocker| {
+            Self::apply_block_locked(blocker, &target, false)
+        })
+        .await?
+        .map_err(BlockerError::Map)
+    }
+
+    /// Stamp the GPU's block change time if its state now differs from `before`.
+    /// Re-applying the same state, or a state unknown before, leaves it alone.
+    async fn record_block_change(&self, gpu: &gpu::Gpu, before: Option<bool>) {
+        let Some(before) = before else {
+            return;
+        };
+        let Ok(after) = self.is_gpu_blocked(gpu).await else {
+            return;
+        };
+        if after != before {
+            self.block_changes
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .insert(gpu.id() as u32, (SystemTime::now(), after));
+        }
+    }
+
+    /// Whether a block state change is worth applying: false when blocking a GPU the
+    /// kernel already disabled. Warns when blocking will only cover PCI access.
+    fn check_block_needed(&self, gpu: &gpu::Gpu, block: bool) -> bool {
+        if !block {
+            return true;
+        }
+        if self.gpu_enabled(gpu) == Some(false) {
+            info!(
+                "GPU {} ({}) is already disabled or removed, not blocking it",
+                gpu.id(),
+                gpu.pci_address()
+            );
+            return false;
+        }
+        if !gpu.nodes_present() {
+            warn!(
+                "GPU {} ({}) has no render/card node ({}, {}); is its driver loaded? Only PCI config access will be blocked",
+                gpu.id(),
+                gpu.pci_address(),
+                gpu.render_node(),
+                gpu.card_node()
+            );
+        }
+        true
+    }
+
+    fn block_target(&self, gpu: &gpu::Gpu) -> BlockTarget {
+        BlockTarget {
+            pci: gpu.pci_address().to_string(),
+            nodes: self.block_nodes(gpu),
+            sharers: self.node_sharers(gpu),
+        }
+    }
+
+    /// Split a GPU's nodes into the node ids and device numbers that block/unblock
+    /// them, logging the nodes left out. When blocking, a node `keeper` says stays
+    /// available through another GPU (given the node's sharers) is skipped.
+    fn node_entries<K>(
+        target: &BlockTarget,
+        block: bool,
+        mut keeper: K,
+    ) -> (Vec<u32>, Vec<(&'static str, String, u64)>)
+    where
+        K: FnMut(&[(String, bool)]) -> Option<String>,
+    {
+        let pci = &target.pci;
+        let action = if block { "block" } else { "unblock" };
+        let mut ids = Vec::with_capacity(target.nodes.len());
+        let mut devs = Vec::new();
+        for (kind, node, prefix) in &target.nodes {
+            // Blocking a shared node would also cut off the other GPUs using it.
+            if block
+                && let Some(other_pci) = target.sharers.get(node).and_then(|others| keeper(others))
+            {
+                warn!(
+                    "Not blocking {} node {} for {}: shared with {}, which stays available",
+                    kind, node, pci, other_pci
+                );
+                continue;
+            }
+
+            if let Some(id) = prefix.and_then(|prefix| Self::parse_node_id(node, prefix)) {
+                // Keep phantom ids out of the map: the node must be a real device.
+                if block && Self::node_devno(node).is_none() {
+                    warn!(
+                        "{} node {} for {} isn't a device node, not blocking id {}",
+                        kind, node, pci, id
+                    );
+                    continue;
+                }
+                ids.push(id);
+            } else if let Some(devno) = Self::node_devno(node) {
+                if let Some(prefix) = prefix {
+                    warn!(
+                        "{} node {} for {} doesn't match {}<id>, falling back to device number {:#x}",
+                        kind, node, pci, prefix, devno
+                    );
+                }
+                devs.push((*kind, node.clone(), devno));
+            } else {
+                warn!(
+                    "No usable {} node for {} ({}), it can't be {}ed by node",
+                    kind, pci, node, action
+                );
+            }
+        }
+        (ids, devs)
+    }
+
+    /// Block or unblock nodes matched by device number, recording failures.
+    fn apply_dev_entries(
+        blocker: &mut dyn Blocker,
+        pci: &str,
+        devs: &[(&'static str, String, u64)],
+        block: bool,
+        record: &mut impl FnMut(String),
+    ) {
+        let action = if block { "block" } else { "unblock" };
+        for (kind, node, devno) in devs {
+            let result = if block {
+                blocker.block_dev(*devno)
+            } else {
+                blocker.unblock_dev(*devno)
+            };
+            if let Err(err) = result {
+                record(format!(
+                    "Failed to {} {} node {} for {}: {}",
+                    action, kind, node, pci, err
+                ));
+            }
+        }
+    }
+
+    /// Apply one GPU's block state with the blocker already held. Node ids go through
+    /// the bulk map calls. Failures are logged; the first one is returned.
+    fn apply_block_locked(
+        blocker: &mut dyn Blocker,
+        target: &BlockTarget,
+        block: bool,
+    ) -> Result<(), String> {
+        let pci = &target.pci;
+        let action = if block { "block" } else { "unblock" };
+        let mut first_err: Option<String> = None;
+        let mut record = |message: String| {
+            warn!("{}", message);
+            first_err.get_or_insert(message);
+        };
+
+        let (ids, devs) = Self::node_entries(target, block, |others| {
+            others
+                .iter()
+                .find(|(other_pci, is_default)| {
+                    *is_default || !matches!(blocker.is_pci_blocked(other_pci), Ok(true))
+                })
+                .map(|(other_pci, _)| other_pci.clone())
+        });
+        Self::apply_dev_entries(blocker, pci, &devs, block, &mut record);
+
+        let ids_result = if block {
+            blocker.block_ids(&ids)
+        } else {
+            blocker.unblock_ids(&ids)
+        };
+        if let Err(err) = ids_result {
+            record(format!(
+                "Failed to {} node ids {:?} for {}: {}",
+                action, ids, pci, err
+            ));
+        }
+
+        let pci_result = if block {
+            blocker.block_pci(pci)
+        } else {
+            blocker.unblock_pci(pci)
+        };
+        if let Err(err) = pci_result {
+            record(format!(
+                "Failed to {} PCI access for {}: {}",
+                action, pci, err
+            ));
+        }
+
+        first_err.map_or(Ok(()), Err)
+    }
+
+    /// Apply a whole mode's GPU decisions as one block set swap, so no intermediate
+    /// combination of GPUs is ever blocked. Entries of GPUs without a decision (and
+    /// anything else in the maps) are kept. Failures are logged; the first is returned.
+    fn apply_mode_blocks_locked(
+        blocker: &mut dyn Blocker,
+        targets: &[(BlockTarget, bool)],
+    ) -> Result<(), String> {
+        let mut first_err: Option<String> = None;
+        let mut record = |message: String| {
+            warn!("{}", message);
+            first_err.get_or_insert(message);
+        };
+
+        let current_ids = blocker
+            .blocked_ids()
+            .map_err(|err| format!("Failed to read blocked node ids: {}", err))?;
+        let current_pci: HashSet<String> = blocker
+            .blocked_pci()
+            .map_err(|err| format!("Failed to read blocked PCI addresses: {}", err))?
+            .into_iter()
+            .collect();
+        let decided: HashMap<&str, bool> = targets
+            .iter()
+            .map(|(target, block)| (target.pci.as_str(), *block))
+            .collect();
+        let stays_available = |pci: &str, is_default: bool| {
+            is_default
+                || !decided
+                    .get(pci)
+                    .copied()
+                    .unwrap_or_else(|| current_pci.contains(pci))
+        };
+
+        // Start from what's blocked, minus everything the decided GPUs cover.
+        let mut ids: HashSet<u32> = current_ids.into_iter().collect();
+        let mut pci = current_pci.clone();
+        for (target, _) in targets {
+            let (target_ids, _) = Self::node_entries(target, false, |_| None);
+            for id in target_ids {
+                ids.remove(&id);
+            }
+            pci.remove(&target.pci);
+        }
+
+        let mut devs = Vec::new();
+        for (target, block) in targets {
+            let (target_ids, target_devs) = Self::node_entries(target, *block, |others| {
+                others
+                    .iter()
+                    .find(|(other_pci, is_default)| stays_available(other_pci, *is_default))
+                    .map(|(other_pci, _)| other_pci.clone())
+            });
+            if *block {
+                ids.extend(target_ids);
+                pci.insert(target.pci.clone());
+            }
+            devs.push((target, target_devs, *block));
+        }
+
+        let ids: Vec<u32> = ids.into_iter().collect();
+        let pci: Vec<String> = pci.into_iter().collect();
+        if let Err(err) = blocker.apply_block_set(&ids, &pci) {
+            record(format!("Failed to apply the mode's block set: {}", err));
+        }
+        for (target, target_devs, block) in devs {
+            Self::apply_dev_entries(blocker, &target.pci, &target_devs, block, &mut record);
+        }
+
+        first_err.map_or(Ok(()), Err)
+    }
+
+    /// Block/unblock every managed GPU for `mode` and make it current. `persist` also
+    /// saves it as the startup mode. Returns the preset actions that were skipped.
+    async fn apply_mode(&self, mode: &str, persist: bool) -> fdo::Result<Vec<String>> {
+        let mut current_mode_lock = self.current_mode.write().await;
+        Self::check_mode(mode)?;
+
+        let mut targets = Vec::new();
+        let mut before = Vec::new();
+        for gpu in self.gpu_list.values() {
+            let Some(block) = self.mode_block_decision(mode, gpu) else {
+                continue;
+            };
+            if !self.check_block_needed(gpu, block) {
+                continue;
+            }
+            targets.push((self.block_target(gpu), block));
+            before.push((gpu, self.is_gpu_blocked(gpu).await.ok()));
+        }
+        let applied = self
+            .with_blocker_write("apply mode", move |blocker| {
+                Self::apply_mode_blocks_locked(blocker, &targets)
+            })
+            .await?;
+        for (gpu, before) in before {
+            self.record_block_change(gpu, before).await;
+        }
+        // Neither the current mode nor the config changes on failure.
+        applied.map_err(|err| {
+            warn!("Failed to apply mode {}: {}", mode, err);
+            fdo::Error::Failed(format!("Failed to apply mode {}: {}", mode, err))
+        })?;
+
+        if persist {
+            Self::mark_mode_pending(mode, &current_mode_lock);
+        }
+        let previous = std::mem::replace(&mut *current_mode_lock, mode.to_string());
+        if persist && let Err(err) = settings::save_mode(mode) {
+            warn!("Failed to save mode to config: {}", err);
+        }
+
+        let skipped = Self::preset(mode)
+            .map(|preset| self.apply_preset_actions(preset))
+            .unwrap_or_default();
+        for reason in &skipped {
+            warn!("Mode {}: skipped {}", mode, reason);
+        }
+
+        info!("Set mode to {}", mode);
+        if previous != mode {
+            self.run_mode_hook(mode, &previous);
+        }
+        Ok(skipped)
+    }
+
+    /// Start `on_mode_change` for a change to `mode`, in the background so a slow
+    /// script doesn't hold up the caller. Its status and output go to the log.
+    fn run_mode_hook(&self, mode: &str, previous: &str) {
+        let program = self.settings.on_mode_change.trim();
+        if program.is_empty() {
+            return;
+        }
+        let mut command = tokio::process::Command::new(program);
+        command
+            .arg(mode)
+            .env("CHAINSAW_MODE", mode)
+            .env("CHAINSAW_PREVIOUS_MODE", previous)
+            .stdin(std::process::Stdio::null());
+        let program = program.to_string();
+        tokio::spawn(async move {
+            let output = match command.output().await {
+                Ok(output) => output,
+                Err(err) => {
+                    warn!("Failed to run on_mode_change {}: {}", program, err);
+                    return;
+                }
+            };
+            if output.status.success() {
+                info!("on_mode_change {} exited with {}", program, output.status);
+            } else {
+                warn!("on_mode_change {} exited with {}", program, output.status);
+            }
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            for line in stdout.lines().chain(stderr.lines()) {
+                info!("on_mode_change: {}", line);
+            }
+        });
+    }
+
+    fn power_profile_error(gpu_id: u32, err: std::io::Error) -> fdo::Error {
+        match err.kind() {
+            std::io::ErrorKind::NotFound => {
+                fdo::Error::NotSupported(format!("GPU {} doesn't support power profiles", gpu_id))
+            }
+            std::io::ErrorKind::InvalidInput => fdo::Error::InvalidArgs(err.to_string()),
+            _ => fdo::Error::Failed(format!(
+                "Failed to access power profile of GPU {}: {}",
+                gpu_id, err
+            )),
+        }
+    }
+
+    /// Record `mode` as unconfirmed so a reboot before `confirm_mode_ok` falls back to
+    /// the last confirmed-good mode. Going back to that mode clears the marker.
+    fn mark_mode_pending(mode: &str, previous: &str) {
+        let last_good = match settings::read_pending() {
+            Some(marker) => marker.last_good,
+            None if mode == previous => return,
+            None => previous.to_string(),
+        };
+        let result = if mode == last_good {
+            settings::clear_pending()
+        } else {
+            settings::write_pending(&settings::PendingMode {
+                pending: mode.to_string(),
+                last_good,
+            })
+        };
+        if let Err(err) = result {
+            warn!(
+                "Failed to update {}: {}",
+                settings::PENDING_MARKER_PATH,
+                err
+            );
+        }
+    }
+
+    /// Cancel the pending timed-mode revert, if any.
+    fn cancel_mode_revert(&self) {
+        let mut revert = self
+            .mode_revert
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some((task, _)) = revert.1.take() {
+            task.abort();
+            info!("Cancelled pending mode revert");
+        }
+    }
+
+    /// Cancel the pending resume of a timed pause, if any.
+    fn cancel_pause_resume(&self) {
+        let mut resume = self
+            .pause_resume
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some((task, _)) = resume.1.take() {
+            task.abort();
+            info!("Cancelled pending resume");
+        }
+    }
+
+    async fn resume_after(
+        &self,
+        emitter: SignalEmitter<'static>,
+        generation: u64,
+        delay: Duration,
+    ) {
+        tokio::time::sleep(delay).await;
+
+        {
+            let mut resume = self
+                .pause_resume
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if resume.0 != generation {
+                return;
+            }
+            resume.1 = None;
+        }
+
+        info!("Timed pause expired, resuming blocking");
+        let result = self.switch_enforcement(true).await;
+        self.audit(audit::DAEMON_CALLER, "pause expired, resume", &result);
+        if let Err(err) = result {
+            warn!("Failed to resume blocking: {}", err);
+            return;
+        }
+        if let Err(err) = Self::blocking_enabled_changed(&emitter, true).await {
+            warn!("Failed to emit BlockingEnabledChanged: {}", err);
+        }
+    }
+
+    async fn switch_enforcement(&self, enabled: bool) -> fdo::Result<()> {
+        self.with_blocker("set blocking enabled", move |blocker| {
+            blocker
+                .set_blocking_enabled(enabled)
+                .map_err(|err| err.to_string())
+        })
+        .await?
+        .map_err(|err| fdo::Error::Failed(format!("Failed to switch enforcement: {}", err)))
+    }
+
+    async fn revert_mode_after(
+        &self,
+        emitter: SignalEmitter<'static>,
+        generation: u64,
+        target: String,
+        delay: Duration,
+    ) {
+        tokio::time::sleep(delay).await;
+
+        {
+            let mut revert = self
+                .mode_revert
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if revert.0 != generation {
+                return;
+            }
+            revert.1 = None;
+        }
+
+        info!("Timed mode expired, reverting to {}", target);
+        let result = self.apply_mode(&target, false).await;
+        self.audit(
+            audit::DAEMON_CALLER,
+            &format!("timed mode expired, revert to {}", target),
+            &result,
+        );
+        if let Err(err) = result {
+            warn!("Failed to revert to mode {}: {}", target, err);
+            return;
+        }
+        if let Err(err) = Self::mode_changed(&emitter, &target).await {
+            warn!("Failed to emit ModeChanged: {}", err);
+        }
+    }
+
+    /// Block or unblock every known PCI function matching a normalized pattern. GPUs
+    /// get their full block policy; other functions (audio, USB-C controllers...) only
+    /// lose config space access, and are left alone when their slot holds an unmanaged
+    /// GPU. Returns the (address, gpu id) of each function changed.
+    async fn apply_pci_pattern(
+        &self,
+        pattern: &str,
+        block: bool,
+    ) -> Result<Vec<(String, Option<u32>)>, BlockerError> {
+        let mut addresses: Vec<String> = self
+            .pci_devices
+            .keys()
+            .map(|address| iommu::normalize_pci(address))
+            .chain(
+                self.gpu_list
+                    .values()
+                    .map(|gpu| gpu.pci_address().to_string()),
+            )
+            .filter(|address| iommu::pci_pattern_matches(pattern, address))
+            .collect();
+        addresses.sort();
+        addresses.dedup();
+
+        let mut changed = Vec::new();
+        let mut functions = Vec::new();
+        for address in addresses {
+            let slot = address
+                .rsplit_once('.')
+                .map_or(address.as_str(), |(slot, _)| slot);
+            let slot_gpus: Vec<&gpu::Gpu> = self
+                .gpu_list
+                .values()
+                .filter(|gpu| gpu.pci_address().starts_with(slot))
+                .collect();
+            if let Some(gpu) = slot_gpus.iter().find(|gpu| gpu.pci_address() == address) {
+                if self.is_managed(gpu) {
+                    self.apply_gpu_block_policy(gpu, block).await?;
+                    changed.push((address, Some(gpu.id() as u32)));
+                } else {
+                    info!("Pattern {} skips unmanaged GPU {}", pattern, address);
+                }
+            } else if slot_gpus.iter().all(|gpu| self.is_managed(gpu)) {
+                functions.push(address);
+            } else {
+                info!(
+                    "Pattern {} skips {}, its slot holds an unmanaged GPU",
+                    pattern, address
+                );
+            }
+        }
+
+        let action = if block {
+            "pattern block"
+        } else {
+            "pattern unblock"
+        };
+        let applied = self.set_functions_blocked(action, functions, block).await?;
+        changed.extend(applied.into_iter().map(|address| (address, None)));
+        changed.sort();
+        Ok(changed)
+    }
+
+    /// Block or unblock PCI config access of non-GPU functions. Returns the ones
+    /// changed; per-function failures are logged and skipped.
+    async fn set_functions_blocked(
+        &self,
+        action: &'static str,
+        functions: Vec<String>,
+        block: bool,
+    ) -> Result<Vec<String>, BlockerError> {
+        self.with_blocker_write(action, move |blocker| {
+            functions
+                .into_iter()
+                .filter(|address| {
+                    let result = if block {
+                        blocker.block_pci(address)
+                    } else {
+                        blocker.unblock_pci(address)
+                    };
+                    result
+                        .map_err(|err| warn!("Failed to {} {}: {}", action, address, err))
+                        .is_ok()
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+    }
+
+    /// The other functions in a GPU's slot (HDMI audio, USB-C, ...), excluding any
+    /// that is itself a GPU: those keep their own policy.
+    fn companion_functions(&self, gpu: &gpu::Gpu) -> fdo::Result<Vec<String>> {
+        let slot = gpu
+            .pci_address()
+            .rsplit_once('.')
+            .map_or(gpu.pci_address(), |(slot, _)| slot);
+        let functions = iommu::slot_functions(gpu.pci_address()).map_err(|err| {
+            fdo::Error::Failed(format!(
+                "Failed to list the PCI functions of slot {}: {}",
+                slot, err
+            ))
+        })?;
+        Ok(functions
+            .into_iter()
+            .filter(|address| {
+                address.rsplit_once('.').map(|(other, _)| other) == Some(slot)
+                    && !self
+                        .gpu_list
+                        .values()
+                        .any(|other| other.pci_address() == address)
+            })
+            .collect())
+    }
+
+    /// Block the functions matching each saved `blocked_pci_patterns` entry.
+    async fn apply_saved_pci_patterns(&self) {
+        let patterns = self
+            .pci_patterns
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone();
+        for pattern in patterns {
+            match self.apply_pci_pattern(&pattern, true).await {
+                Ok(changed) => info!(
+                    "PCI pattern {} blocked {:?}",
+                    pattern,
+                    changed
+                        .iter()
+                        .map(|(address, _)| address)
+                        .collect::<Vec<_>>()
+                ),
+                Err(err) => warn!("Failed to apply PCI pattern {}: {}", pattern, err),
+            }
+        }
+    }
+
+    /// Add or remove `value` in a saved list setting, writing the config first.
+    fn update_saved_list(
+        list: &std::sync::Mutex<Vec<String>>,
+        key: &str,
+        value: &str,
+        add: bool,
+    ) -> fdo::Result<()> {
+        let mut saved = list.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let present = saved.iter().any(|entry| entry == value);
+        if add == present {
+            return Ok(());
+        }
+        let mut updated = saved.clone();
+        if add {
+            updated.push(value.to_string());
+        } else {
+            updated.retain(|entry| entry != value);
+        }
+        settings::save_list(key, &updated)
+            .map_err(|err| fdo::Error::Failed(format!("Failed to save {}: {}", key, err)))?;
+        *saved = updated;
+        Ok(())
+    }
+
+    /// Shared body of `block_pci_pattern`/`unblock_pci_pattern`.
+    async fn set_pci_pattern_block(
+        &self,
+        emitter: &SignalEmitter<'_>,
+        pattern: &str,
+        block: bool,
+    ) -> fdo::Result<Vec<String>> {
+        self.check_writable()?;
+        let pattern = iommu::normalize_pci_pattern(pattern).map_err(fdo::Error::InvalidArgs)?;
+        let changed = self.apply_pci_pattern(&pattern, block).await?;
+        Self::update_saved_list(&self.pci_patterns, "blocked_pci_patterns", &pattern, block)?;
+
+        for gpu_id in changed.iter().filter_map(|(_, gpu_id)| *gpu_id) {
+            if let Err(err) = Self::gpu_block_changed(emitter, gpu_id, block).await {
+                warn!("Failed to emit GpuBlockChanged for GPU {}: {}", gpu_id, err);
+            }
+        }
+        let addresses: Vec<String> = changed.into_iter().map(|(address, _)| address).collect();
+        info!(
+            "Set PCI pattern {} block={}: {:?}",
+            pattern, block, addresses
+        );
+        Ok(addresses)
+    }
+
+    /// Managed GPUs currently bound to `driver`, by id.
+    fn gpus_with_driver(&self, driver: &str) -> Vec<&gpu::Gpu> {
+        let mut gpus: Vec<&gpu::Gpu> = self
+            .gpu_list
+            .values()
+            .filter(|gpu| self.is_managed(gpu))
+            .filter(|gpu| {
+                self.pci_devices
+                    .get(gpu.pci_address())
+                    .and_then(|device| device.current_driver().ok().flatten())
+                    .is_some_and(|bound| bound == driver)
+            })
+            .collect();
+        gpus.sort_by_key(|gpu| gpu.id());
+        gpus
+    }
+
+    /// Block the GPUs of each saved `blocked_drivers` entry.
+    async fn apply_saved_driver_blocks(&self) {
+        let drivers = self
+            .blocked_drivers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone();
+        for driver in drivers {
+            let gpus = self.gpus_with_driver(&driver);
+            if gpus.is_empty() {
+                info!("No GPU uses blocked driver {} right now", driver);
+            }
+            for gpu in gpus {
+                if let Err(err) = self.apply_gpu_block_policy(gpu, true).await {
+                    warn!(
+                        "Failed to block GPU {} (driver {}): {}",
+                        gpu.id(),
+                        driver,
+                        err
+                    );
+                }
+            }
+        }
+    }
+
+    /// Bring the hardware in line with the configured mode. Runs after the bus name is
+    /// claimed; a failure is recorded for `get_mode_consistency` instead of aborting.
+    async fn reconcile(&self, configured_mode: String) {
+        let reused = self
+            .with_blocker("read pinned map state", |blocker| {
+                blocker.pinned_maps_reused()
+            })
+            .await
+            .unwrap_or(false);
+        if reused {
+            match self.clear_stale_entries().await {
+                Ok(0) => {}
+                Ok(cleared) => info!(
+                    "Cleared {} block entries of GPUs no longer present",
+                    cleared
+                ),
+                Err(err) => warn!("Failed to clear block entries of removed GPUs: {}", err),
+            }
+        }
+
+        let outcome = match self.get_current_hardware_mode(&configured_mode).await {
+            // Reused maps can match while the mode's preset actions were never run, so
+            // those re-apply the mode anyway.
+            Ok(Some(hardware_mode)) if hardware_mode == configured_mode && !reused => {
+                info!("Hardware mode matches configured mode: {}", configured_mode);
+                Ok(())
+            }
+            Ok(hardware_mode) => {
+                info!(
+                    "Hardware mode {} ({}), applying configured mode {}...",
+                    hardware_mode.as_deref().unwrap_or("none"),
+                    if reused {
+                        "from reused pinned maps"
+                    } else {
+                        "doesn't match"
+                    },
+                    configured_mode
+                );
+                // Skipped preset actions are already logged.
+                self.apply_mode(&configured_mode, true)
+                    .await
+                    .map(|_| ())
+                    .map_err(|err| err.to_string())
+            }
+            Err(err) => Err(format!("can't read hardware mode: {}", err)),
+        };
+
+        match &outcome {
+            Ok(()) => info!(
+                "Startup reconciliation succeeded (mode={})",
+                configured_mode
+            ),
+            Err(err) => warn!(
+                "STARTUP RECONCILIATION FAILED for mode {}: {} (daemon keeps serving, see GetModeConsistency)",
+                configured_mode, err
+            ),
+        }
+        *self
+            .reconcile_error
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = outcome.err();
+    }
+
+    /// Unblock the node ids and PCI addresses no detected GPU owns, such as those of an
+    /// eGPU unplugged while reused pinned maps kept its entries. Returns how many went.
+    async fn clear_stale_entries(&self) -> Result<usize, String> {
+        let owned_pci: HashSet<String> = self
+            .gpu_list
+            .values()
+            .map(|gpu| iommu::normalize_pci(gpu.pci_address()))
+            .collect();
+        let owned_ids: HashSet<u32> = self.gpu_list.values().flat_map(Self::node_ids).collect();
+        self.with_blocker_write("clear stale entries", move |blocker| {
+            let stale_ids: Vec<u32> = blocker
+                .blocked_ids()
+                .map_err(|err| err.to_string())?
+                .into_iter()
+                .filter(|id| !owned_ids.contains(id))
+                .collect();
+            let stale_pci: Vec<String> = blocker
+                .blocked_pci()
+                .map_err(|err| err.to_string())?
+                .into_iter()
+                .filter(|address| !owned_pci.contains(&iommu::normalize_pci(address)))
+                .collect();
+            blocker
+                .unblock_ids(&stale_ids)
+                .map_err(|err| err.to_string())?;
+            for address in &stale_pci {
+                blocker
+                    .unblock_pci(address)
+                    .map_err(|err| err.to_string())?;
+            }
+            Ok(stale_ids.len() + stale_pci.len())
+        })
+        .await
+        .map_err(|err| err.to_string())?
+    }
+
+    /// Whether the effective block state matches what `mode` expects.
+    fn mode_matches(&self, mode: &str, effective: &HashMap<u32, bool>) -> bool {
+        self.gpu_list
+            .values()
+            .all(|gpu| match self.mode_block_decision(mode, gpu) {
+                Some(block) => effective.get(&(gpu.id() as u32)) == Some(&block),
+                None => true,
+            })
+    }
+
+    /// Start the mode toggle key from `toggle_key_device`, if configured. Each press
+    /// switches between integrated and hybrid, like `set_mode`.
+    fn arm_toggle_key(&self, emitter: SignalEmitter<'static>) {
+        let device = self.settings.toggle_key_device.clone();
+        if device.is_empty() {
+            return;
+        }
+        let code = self.settings.toggle_key_code;
+        let mut events = match input::KeyEvents::open(&device) {
+            Ok(events) => events,
+            Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied => {
+                warn!(
+                    "Toggle key disabled: no read access to {} ({}); the daemon must run as root or in the input group",
+                    device, err
+                );
+                return;
+            }
+            Err(err) => {
+                warn!("Toggle key disabled: can't open {}: {}", device, err);
+                return;
+            }
+        };
+
+        // evdev reads block, so they get their own thread.
+        let (presses, mut pressed) = tokio::sync::mpsc::channel(1);
+        let armed = self.toggle_key.clone();
+        let reader_device = device.clone();
+        std::thread::spawn(move || {
+            loop {
+                if let Err(err) = events.next_press(code) {
+                    warn!(
+                        "Toggle key disabled: reading {} failed: {}",
+                        reader_device, err
+                    );
+                    break;
+                }
+                // A full channel means a toggle is still running; drop the press.
+                if let Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) = presses.try_send(())
+                {
+                    break;
+                }
+            }
+            *armed
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()) = None;
+        });
+
+        *self
+            .toggle_key
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) =
+            Some(format!("{} key {}", device, code));
+        info!("Toggle key armed: key {} on {}", code, device);
+
+        let debounce = Duration::from_millis(self.settings.toggle_key_debounce_ms);
+        let daemon = self.clone();
+        tokio::spawn(async move {
+            let mut last_toggle: Option<Instant> = None;
+            while pressed.recv().await.is_some() {
+                if last_toggle.is_some_and(|last| last.elapsed() < debounce) {
+                    continue;
+                }
+                let target = if *daemon.current_mode.read().await == MODE_INTEGRATED {
+                    MODE_HYBRID
+                } else {
+                    MODE_INTEGRATED
+                };
+                info!("Toggle key pressed, switching to {}", target);
+                daemon.cancel_mode_revert();
+                let result = daemon.apply_mode(target, true).await;
+                daemon.audit(
+                    audit::DAEMON_CALLER,
+                    &format!("toggle key, set_mode {}", target),
+                    &result,
+                );
+                match result {
+                    Ok(_) => {
+                        if let Err(err) = Self::mode_changed(&emitter, target).await {
+                            warn!("Failed to emit ModeChanged: {}", err);
+                        }
+                    }
+                    Err(err) => warn!("Toggle key: failed to switch to {}: {}", target, err),
+                }
+                // Count from the end of the switch, so presses made while it ran are
+                // debounced too.
+                last_toggle = Some(Instant::now());
+            }
+        });
+    }
+
+    /// Follow GPUs whose DRM nodes come and go at runtime: a driver that binds late or
+    /// an eGPU plugged back in gets the current mode's decision, and an unplugged GPU's
+    /// entries are cleared.
+    async fn watch_gpu_nodes(&self) {
+        let mut ticker = tokio::time::interval(GPU_RESCAN_INTERVAL);
+        let mut present: HashMap<u32, bool> = self
+            .gpu_list
+            .values()
+            .map(|gpu| (gpu.id() as u32, gpu.nodes_present()))
+            .collect();
+        loop {
+            ticker.tick().await;
+            self.rescan_gpus(
+                &mut present,
+                gpu::Gpu::nodes_present,
+                gpu::Gpu::device_present,
+            )
+            .await;
+        }
+    }
+
+    /// One `watch_gpu_nodes` pass against the node presence seen last time.
+    async fn rescan_gpus(
+        &self,
+        present: &mut HashMap<u32, bool>,
+        nodes_present: impl Fn(&gpu::Gpu) -> bool,
+        device_present: impl Fn(&gpu::Gpu) -> bool,
+    ) {
+        let mut gpus: Vec<&gpu::Gpu> = self.gpu_list.values().collect();
+        gpus.sort_by_key(|gpu| gpu.id());
+        for gpu in gpus {
+            let now = nodes_present(gpu);
+            if present.insert(gpu.id() as u32, now) == Some(now) {
+                continue;
+            }
+            if now {
+                let mode = self.current_mode.read().await.clone();
+                info!(
+                    "GPU {} ({}) nodes appeared, applying mode {} to it",
+                    gpu.id(),
+                    gpu.pci_address(),
+                    mode
+                );
+                if let Err(err) = self.apply_policy_to(gpu, &mode).await {
+                    warn!("Failed to apply mode {} to GPU {}: {}", mode, gpu.id(), err);
+                }
+            } else if !device_present(gpu) {
+                info!(
+                    "GPU {} ({}) was removed, clearing its block entries",
+                    gpu.id(),
+                    gpu.pci_address()
+                );
+                if let Err(err) = self.clear_gpu_entries(gpu).await {
+                    warn!(
+                        "Failed to clear entries of removed GPU {}: {}",
+                        gpu.id(),
+                        err
+                    );
+                }
+            }
+        }
+    }
+
+    /// Log each access the hook denies: "process X (pid N) was denied access to GPU Y".
+    /// Also removes node id entries whose TTL has passed, every `EXPIRED_PURGE_INTERVAL`.
+    async fn watch_denials(&self) {
+        let mut ticker = tokio::time::interval(DENIAL_POLL_INTERVAL);
+        let mut last_purge = Instant::now();
+        loop {
+            ticker.tick().await;
+            if last_purge.elapsed() >= EXPIRED_PURGE_INTERVAL {
+                last_purge = Instant::now();
+                self.purge_expired_blocks().await;
+            }
+            let Ok(events) = self
+                .with_blocker("poll denial events", |blocker| blocker.poll_events())
+                .await
+            else {
+                continue;
+            };
+            for event in events {
+                info!(
+                    "Process {} (pid {}, uid {}) was denied access to {}",
+                    event.command(),
+                    event.pid,
+                    event.uid,
+                    self.denial_target(&event)
+                );
+            }
+        }
+    }
+
+    /// Remove expired TTL entries. They already stopped denying; this only keeps them
+    /// from piling up in the map.
+    async fn purge_expired_blocks(&self) {
+        if self.read_only.load(Ordering::SeqCst) {
+            return;
+        }
+        match self
+            .with_blocker_write("purge expired blocks", |blocker| {
+                blocker.purge_expired().map_err(|err| err.to_string())
+            })
+            .await
+        {
+            Ok(Ok(0)) => {}
+            Ok(Ok(purged)) => info!("Removed {} expired node id blocks", purged),
+            Ok(Err(err)) => warn!("Failed to purge expired blocks: {}", err),
+            Err(err) => warn!("Failed to purge expired blocks: {}", err),
+        }
+    }
+
+    /// The GPU (and node) a denial event hit, or the raw entry if no GPU owns it.
+    fn denial_target(&self, event: &DenialEvent) -> String {
+        let target = event.target();
+        for gpu in self.gpu_list.values() {
+            if let HitKey::Pci(pci) = &target
+                && iommu::normalize_pci(pci) == gpu.pci_address()
+            {
+                return format!("GPU {} ({} config space)", gpu.id(), pci);
+            }
+            for (_, node, prefix) in self.block_nodes(gpu) {
+                let hit = match &target {
+                    HitKey::Id(id) => prefix
+                        .and_then(|prefix| Self::parse_node_id(&node, prefix))
+                        .is_some_and(|node_id| node_id == *id),
+                    HitKey::Dev(rdev) => Self::node_devno(&node) == Some(*rdev),
+                    HitKey::Pci(_) => false,
+                };
+                if hit {
+                    return format!("GPU {} ({})", gpu.id(), node);
+                }
+            }
+        }
+        match target {
+            HitKey::Id(id) => format!("node id {}", id),
+            HitKey::Dev(rdev) => format!("device {:#x}", rdev),
+            HitKey::Pci(pci) => format!("PCI {} config space", pci),
+        }
+    }
+
+    /// Poll the effective block state and report changes the daemon didn't make: each
+    /// changed GPU gets a `GpuBlockChanged`, and if the new state matches another mode,
+    /// that mode becomes current (not saved) and `ModeChanged` is emitted.
+    async fn watch_block_state(&self, emitter: SignalEmitter<'static>, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        let mut baseline: Option<(u64, HashMap<u32, bool>)> = None;
+        loop {
+            ticker.tick().await;
+
+            let epoch = self.block_epoch.load(Ordering::SeqCst);
+            let mut effective = HashMap::new();
+            for gpu in self.gpu_list.values().filter(|gpu| self.is_managed(gpu)) {
+                match self.is_gpu_blocked(gpu).await {
+                    Ok(blocked) => {
+                        effective.insert(gpu.id() as u32, blocked);
+                    }
+                    Err(err) => warn!("Block watcher can't read GPU {}: {}", gpu.id(), err),
+                }
+            }
+            // The daemon wrote while we were reading; start over from the next sample.
+            if self.block_epoch.load(Ordering::SeqCst) != epoch {
+                baseline = None;
+                continue;
+            }
+
+            let Some((previous_epoch, previous)) = baseline.replace((epoch, effective.clone()))
+            else {
+                continue;
+            };
+            if previous_epoch != epoch {
+                continue;
+            }
+
+            let mut changes: Vec<(u32, bool)> = effective
+                .iter()
+                .filter(|(gpu_id, blocked)| previous.get(gpu_id).is_some_and(|was| was != *blocked))
+                .map(|(gpu_id, blocked)| (*gpu_id, *blocked))
+                .collect();
+            if changes.is_empty() {
+                continue;
+            }
+            changes.sort();
+            for (gpu_id, blocked) in changes {
+                warn!(
+                    "GPU {} block state changed outside the daemon (blocked={})",
+                    gpu_id, blocked
+                );
+                self.block_changes
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .insert(gpu_id, (SystemTime::now(), blocked));
+                if let Err(err) = Self::gpu_block_changed(&emitter, gpu_id, blocked).await {
+                    warn!("Failed to emit GpuBlockChanged for GPU {}: {}", gpu_id, err);
+                }
+            }
+
+            let mut current_mode = self.current_mode.write().await;
+            if self.mode_matches(&current_mode, &effective) {
+                continue;
+            }
+            let Some(mode) = SUPPORTED_MODES
+                .iter()
+                .find(|mode| self.mode_matches(mode, &effective))
+            else {
+                continue;
+            };
+            warn!(
+                "Block state now matches mode {} instead of {}, updating current mode",
+                mode, current_mode
+            );
+            *current_mode = mode.to_string();
+            drop(current_mode);
+            if let Err(err) = Self::mode_changed(&emitter, mode).await {
+                warn!("Failed to emit ModeChanged: {}", err);
+            }
+        }
+    }
+
+    /// Queue a block change, superseding any earlier request for the same GPU.
+    fn queue_gpu_block(&self, gpu_id: u32, blocked: bool) -> u64 {
+        let mut pending = self
+            .pending_blocks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let entry = pending.entry(gpu_id).or_insert((0, blocked));
+        entry.0 += 1;
+        entry.1 = blocked;
+        entry.0
+    }
+
+    /// Returns the queued state if `generation` is still the latest request for the GPU.
+    fn latest_queued_block(&self, gpu_id: u32, generation: u64) -> Option<bool> {
+        let pending = self
+            .pending_blocks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        pending
+            .get(&gpu_id)
+            .and_then(|(latest, blocked)| (*latest == generation).then_some(*blocked))
+    }
+
+    /// Apply a queued block once the debounce window passes without a newer request.
+    async fn apply_debounced_block(
+        &self,
+        emitter: SignalEmitter<'static>,
+        gpu_id: u32,
+        generation: u64,
+    ) {
+        tokio::time::sleep(self.block_debounce).await;
+
+        let Some(blocked) = self.latest_queued_block(gpu_id, generation) else {
+            return;
+        };
+        let Some(gpu) = self.gpu_by_id(gpu_id) else {
+            return;
+        };
+
+        let applied = match self.apply_gpu_block_policy(gpu, blocked).await {
+            Ok(()) => self.is_gpu_blocked(gpu).await,
+            Err(err) => Err(err),
+        };
+        let now_blocked = match applied {
+            Ok(now_blocked) => now_blocked,
+            Err(err) => {
+                warn!("Failed to set GPU {} block={}: {}", gpu_id, blocked, err);
+                return;
+            }
+        };
+        info!(
+            "Set GPU {} ({}) block={} (effective={})",
+            gpu_id,
+            gpu.pci_address(),
+            blocked,
+            now_blocked
+        );
+
+        if let Err(err) = Self::gpu_block_changed(&emitter, gpu_id, now_blocked).await {
+            warn!("Failed to emit GpuBlockChanged for GPU {}: {}", gpu_id, err);
+        }
+    }
+
+    /// Node ids (render and card) exposed by a GPU.
+    fn node_ids(gpu: &gpu::Gpu) -> Vec<u32> {
+        [
+            Self::parse_node_id(gpu.render_node(), RENDER_NODE_PREFIX),
+            Self::parse_node_id(gpu.card_node(), CARD_NODE_PREFIX),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    /// PCI addresses of managed GPUs, the ones export/import cover.
+    fn known_pci(&self) -> HashSet<String> {
+        self.gpu_list
+            .values()
+            .filter(|gpu| self.is_managed(gpu))
+            .map(|gpu| gpu.pci_address().to_string())
+            .collect()
+    }
+
+    fn known_ids(&self) -> HashSet<u32> {
+        self.gpu_list
+            .values()
+            .filter(|gpu| self.is_managed(gpu))
+            .flat_map(Self::node_ids)
+            .collect()
+    }
+
+    /// Read the current block state of every known PCI address and node id.
+    fn read_block_set(
+        blocker: &mut dyn Blocker,
+        pci: &HashSet<String>,
+        ids: &HashSet<u32>,
+    ) -> Result<BlockSet, Box<dyn std::error::Error>> {
+        let mut pci_state = Vec::with_capacity(pci.len());
+        for address in pci {
+            pci_state.push((address.clone(), blocker.is_pci_blocked(address)?));
+        }
+        let mut id_state = Vec::with_capacity(ids.len());
+        for id in ids {
+            id_state.push((*id, blocker.is_id_blocked(*id)?));
+        }
+        Ok((pci_state, id_state))
+    }
+
+    /// Write a block set, stopping at the first failure.
+    fn write_block_set(
+        blocker: &mut dyn Blocker,
+        pci: &[(String, bool)],
+        ids: &[(u32, bool)],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for (address, blocked) in pci {
+            if *blocked {
+                blocker.block_pci(address)?;
+            } else {
+                blocker.unblock_pci(address)?;
+            }
+        }
+        for (id, blocked) in ids {
+            if *blocked {
+                blocker.block_id(*id)?;
+            } else {
+                blocker.unblock_id(*id)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn export_block_state(&self) -> fdo::Result<ExportedState> {
+        let mode = self.current_mode.read().await.clone();
+        let (known_pci, known_ids) = (self.known_pci(), self.known_ids());
+        let (pci_state, id_state) = self
+            .with_blocker("read block state", move |blocker| {
+                Self::read_block_set(blocker, &known_pci, &known_ids).map_err(|err| err.to_string())
+            })
+            .await?
+            .map_err(|err| fdo::Error::Failed(format!("Failed to read block state: {}", err)))?;
+
+        let mut blocked_pci: Vec<String> = pci_state
+            .into_iter()
+            .filter_map(|(address, blocked)| blocked.then_some(address))
+            .collect();
+        let mut blocked_ids: Vec<u32> = id_state
+            .into_iter()
+            .filter_map(|(id, blocked)| blocked.then_some(id))
+            .collect();
+        blocked_pci.sort();
+        blocked_ids.sort();
+
+        Ok(ExportedState {
+            mode,
+            blocked_pci,
+            blocked_ids,
+        })
+    }
+
+    async fn import_block_state(&self, state: ExportedState) -> fdo::Result<String> {
+        self.check_writable()?;
+        Self::check_mode(&state.mode)?;
+
+        let known_pci = self.known_pci();
+        let known_ids = self.known_ids();
+        let wanted_pci: HashSet<String> = state
+            .blocked_pci
+            .iter()
+            .map(|address| iommu::normalize_pci(address))
+            .collect();
+        let wanted_ids: HashSet<u32> = state.blocked_ids.iter().copied().collect();
+
+        let mut unknown_pci: Vec<&String> = wanted_pci.difference(&known_pci).collect();
+        if !unknown_pci.is_empty() {
+            unknown_pci.sort();
+            warn!(
+                "Rejecting import: PCI addresses not present on this machine: {:?}",
+                unknown_pci
+            );
+            return Err(fdo::Error::InvalidArgs(format!(
+                "PCI addresses not present on this machine: {:?}",
+                unknown_pci
+            )));
+        }
+        let mut unknown_ids: Vec<&u32> = wanted_ids.difference(&known_ids).collect();
+        if !unknown_ids.is_empty() {
+            unknown_ids.sort();
+            warn!(
+                "Rejecting import: node ids not present on this machine: {:?}",
+                unknown_ids
+            );
+            return Err(fdo::Error::InvalidArgs(format!(
+                "Node ids not present on this machine: {:?}",
+                unknown_ids
+            )));
+        }
+
+        let target_pci: Vec<(String, bool)> = known_pci
+            .iter()
+            .map(|address| (address.clone(), wanted_pci.contains(address)))
+            .collect();
+        let target_ids: Vec<(u32, bool)> = known_ids
+            .iter()
+            .map(|id| (*id, wanted_ids.contains(id)))
+            .collect();
+
+        self.cancel_mode_revert();
+        let previous_mode = self.current_mode.read().await.clone();
+        let (previous_pci, previous_ids) = self
+            .with_blocker("read block state", move |blocker| {
+                Self::read_block_set(blocker, &known_pci, &known_ids).map_err(|err| err.to_string())
+            })
+            .await?
+            .map_err(|err| fdo::Error::Failed(format!("Failed to read block state: {}", err)))?;
+
+        // The mode goes through `apply_mode` for its preset actions and hook; the
+        // imported block set then replaces whatever the mode blocked.
+        let skipped = self.apply_mode(&state.mode, true).await?;
+        let written = self
+            .with_blocker_write("import block set", move |blocker| {
+                Self::write_block_set(blocker, &target_pci, &target_ids)
+                    .map_err(|err| err.to_string())
+            })
+            .await;
+        if let Err(err) = written
+            .map_err(|err| err.to_string())
+            .and_then(|result| result)
+        {
+            let message = format!("Import failed, rolled back: {}", err);
+            warn!("{}", message);
+            if let Err(mode_err) = self.apply_mode(&previous_mode, true).await {
+                warn!("Failed to restore mode {}: {}", previous_mode, mode_err);
+            }
+            let restored = self
+                .with_blocker_write("restore block set", move |blocker| {
+                    Self::write_block_set(blocker, &previous_pci, &previous_ids)
+                        .map_err(|err| err.to_string())
+                })
+                .await;
+            if let Err(rollback_err) = restored
+                .map_err(|err| err.to_string())
+                .and_then(|result| result)
+            {
+                warn!("Failed to roll back block state: {}", rollback_err);
+            }
+            return Err(fdo::Error::Failed(message));
+        }
+
+        info!(
+            "Imported state: mode={} blocked_pci={:?} blocked_ids={:?}",
+            state.mode, state.blocked_pci, state.blocked_ids
+        );
+        let summary = format!(
+            "Imported mode {} ({} PCI, {} ids blocked)",
+            state.mode,
+            wanted_pci.len(),
+            wanted_ids.len()
+        );
+        if skipped.is_empty() {
+            Ok(summary)
+        } else {
+            Ok(format!("{} (skipped: {})", summary, skipped.join("; ")))
+        }
+    }
+}
+#[interface(name = "com.chainsaw.daemon")]
+impl Daemon {
+    /// Set the GPU mode.
+    ///
+    /// "integrated", "hybrid", "gaming".
+    async fn set_mode(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        mode: String,
+    ) -> fdo::Result<String> {
+        let operation = format!("set_mode {}", mode);
+        self.audited(&header, operation, async {
+            self.cancel_mode_revert();
+            let skipped = self.apply_mode(&mode, true).await?;
+            if let Err(err) = Self::mode_changed(&emitter, &mode).await {
+                warn!("Failed to emit ModeChanged: {}", err);
+            }
+            if skipped.is_empty() {
+                Ok(format!("Set mode to {}", mode))
+            } else {
+                Ok(format!(
+                    "Set mode to {} (skipped: {})",
+                    mode,
+                    skipped.join("; ")
+                ))
+            }
+        })
+        .await
+    }
+
+    /// Set integrated mode keeping `keep_gpu_id` instead of the boot GPU, blocking
+    /// every other managed GPU. The choice is saved, so later `set_mode("integrated")`
+    /// calls and restarts keep the same GPU until `clear_mode_keep`.
+    ///
+    /// The kept GPU must be managed, enabled and have display connectors.
+    async fn set_mode_keeping(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        mode: String,
+        keep_gpu_id: u32,
+    ) -> fdo::Result<String> {
+        let operation = format!("set_mode_keeping {} keep_gpu={}", mode, keep_gpu_id);
+        self.audited(&header, operation, async {
+            self.check_writable()?;
+            if mode != MODE_INTEGRATED {
+                return Err(fdo::Error::InvalidArgs(format!(
+                    "Only {} mode keeps a chosen GPU, not {}",
+                    MODE_INTEGRATED, mode
+                )));
+            }
+            let gpu = self.managed_gpu_by_id_or_err(keep_gpu_id)?;
+            if self.gpu_enabled(gpu) == Some(false) {
+                return Err(fdo::Error::InvalidArgs(format!(
+                    "GPU {} ({}) is disabled or removed, it can't be kept",
+                    keep_gpu_id,
+                    gpu.pci_address()
+                )));
+            }
+            if gpu.connectors().is_empty() {
+                return Err(fdo::Error::InvalidArgs(format!(
+                    "GPU {} ({}) has no display connectors, so it can't drive a screen",
+                    keep_gpu_id,
+                    gpu.pci_address()
+                )));
+            }
+
+            let previous = self.integrated_keep_pci();
+            self.set_integrated_keep(Some(gpu.pci_address().to_string()));
+            self.cancel_mode_revert();
+            if let Err(err) = self.apply_mode(&mode, true).await {
+                self.set_integrated_keep(previous);
+                return Err(err);
+            }
+            if let Err(err) = Self::mode_changed(&emitter, &mode).await {
+                warn!("Failed to emit ModeChanged: {}", err);
+            }
+            info!(
+                "Integrated mode now keeps GPU {} ({})",
+                keep_gpu_id,
+                gpu.pci_address()
+            );
+            Ok(format!(
+                "Set mode to {}, keeping GPU {} ({})",
+                mode,
+                keep_gpu_id,
+                gpu.pci_address()
+            ))
+        })
+        .await
+    }
+
+    /// Make integrated mode keep the boot GPU again, re-applying it if it's current.
+    async fn clear_mode_keep(&self, #[zbus(header)] header: Header<'_>) -> fdo::Result<String> {
+        let operation = "clear_mode_keep".to_string();
+        self.audited(&header, operation, async {
+            self.check_writable()?;
+            self.set_integrated_keep(None);
+            info!("Integrated mode keeps the boot GPU again");
+            if *self.current_mode.read().await == MODE_INTEGRATED {
+                self.apply_mode(MODE_INTEGRATED, false).await?;
+            }
+            Ok("Integrated mode keeps the boot GPU again".to_string())
+        })
+        .await
+    }
+
+    /// Apply a mode for `duration_secs`, then revert to the current one.
+    ///
+    /// The timed mode isn't saved to the config, so a restart also reverts it.
+    async fn set_mode_timed(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        mode: String,
+        duration_secs: u64,
+    ) -> fdo::Result<String> {
+        let operation = format!("set_mode_timed {} {}s", mode, duration_secs);
+        self.audited(&header, operation, async {
+            Self::check_mode(&mode)?;
+            if duration_secs == 0 {
+                return Err(fdo::Error::InvalidArgs(
+                    "duration_secs must be greater than 0".to_string(),
+                ));
+            }
+
+            self.cancel_mode_revert();
+            let previous = self.current_mode.read().await.clone();
+            self.apply_mode(&mode, false).await?;
+            if let Err(err) = Self::mode_changed(&emitter, &mode).await {
+                warn!("Failed to emit ModeChanged: {}", err);
+            }
+
+            let mut revert = self
+                .mode_revert
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            revert.0 += 1;
+            let generation = revert.0;
+            let daemon = self.clone();
+            let emitter = emitter.to_owned();
+            let target = previous.clone();
+            let task = tokio::spawn(async move {
+                daemon
+                    .revert_mode_after(
+                        emitter,
+                        generation,
+                        target,
+                        Duration::from_secs(duration_secs),
+                    )
+                    .await;
+            });
+            revert.1 = Some((task, SystemTime::now() + Duration::from_secs(duration_secs)));
+
+            info!(
+                "Set mode to {} for {}s, reverting to {} afterwards",
+                mode, duration_secs, previous
+            );
+            Ok(format!(
+                "Set mode to {} for {}s (reverts to {})",
+                mode, duration_secs, previous
+            ))
+        })
+        .await
+    }
+    /// Confirm the current mode works, so it becomes the last known good mode.
+    ///
+    /// Meant to be called by the session after a successful login.
+    async fn confirm_mode_ok(&self, #[zbus(header)] header: Header<'_>) -> fdo::Result<String> {
+        let operation = "confirm_mode_ok".to_string();
+        self.audited(&header, operation, async {
+            let mode = self.current_mode.read().await.clone();
+            settings::clear_pending().map_err(|err| {
+                fdo::Error::Failed(format!("Failed to clear pending marker: {}", err))
+            })?;
+            info!("Mode {} confirmed good", mode);
+            Ok(format!("Confirmed mode {}", mode))
+        })
+        .await
+    }
+    /// Get the current GPU mode value.
+    async fn get_mode(&self) -> String {
+        self.current_mode.read().await.clone()
+    }
+    /// List the modes that make sense on this hardware.
+    async fn list_mode(&self) -> Vec<String> {
+        self.available_modes()
+    }
+    /// List every mode the daemon knows, whatever the hardware.
+    async fn list_all_modes(&self) -> Vec<String> {
+        SUPPORTED_MODES
+            .iter()
+            .map(|mode| mode.to_string())
+            .collect()
+    }
+
+    /// Every known mode with a description of what it does.
+    async fn list_modes_detailed(&self) -> Vec<(String, String)> {
+        SUPPORTED_MODES
+            .iter()
+            .map(|mode| (mode.to_string(), Self::describe_mode(mode)))
+            .collect()
+    }
+
+    /// Block decision of every GPU under each supported mode, without applying anything.
+    async fn preview_all_modes(&self) -> Vec<(String, Vec<(u32, bool)>)> {
+        SUPPORTED_MODES
+            .iter()
+            .map(|mode| (mode.to_string(), self.preview_mode_blocks(mode)))
+            .collect()
+    }
+
+    /// Block decision of every GPU under `mode`, whether the change needs a session
+    /// restart to fully apply, and why, without applying anything.
+    async fn preview_mode(&self, mode: &str) -> fdo::Result<(Vec<(u32, bool)>, bool, Vec<String>)> {
+        Self::check_mode(mode)?;
+        let reasons = self.session_restart_reasons(mode).await;
+        Ok((self.preview_mode_blocks(mode), !reasons.is_empty(), reasons))
+    }
+
+    /// List discovered GPUs with block state.
+    async fn list_gpus(&self) -> fdo::Result<Vec<GpuRow>> {
+        Ok(self.list_gpu_rows().await?)
+    }
+
+    /// Block or unblock one GPU by ID. With `include_functions`, the other functions
+    /// in its PCI slot (HDMI audio, USB-C, ...) lose or regain config space access
+    /// too, right away.
+    ///
+    /// Rapid requests for the same GPU are coalesced; only the last one is applied
+    /// after the debounce window, followed by `GpuBlockChanged`.
+    async fn set_gpu_block(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        gpu_id: u32,
+        blocked: bool,
+        include_functions: bool,
+    ) -> fdo::Result<String> {
+        let operation = format!(
+            "set_gpu_block gpu={} blocked={} include_functions={}",
+            gpu_id, blocked, include_functions
+        );
+        self.audited(&header, operation, async {
+            self.check_writable()?;
+            let gpu = self.managed_gpu_by_id_or_err(gpu_id)?;
+            let functions = if include_functions {
+                let functions = self.companion_functions(gpu)?;
+                let action = if blocked {
+                    "block companion function"
+                } else {
+                    "unblock companion function"
+                };
+                let changed = self
+                    .set_functions_blocked(action, functions, blocked)
+                    .await?;
+                if !changed.is_empty() {
+                    info!(
+                        "GPU {} companion functions {} {:?}",
+                        gpu_id,
+                        if blocked { "blocked" } else { "unblocked" },
+                        changed
+                    );
+                }
+                Some(changed)
+            } else {
+                None
+            };
+
+            let generation = self.queue_gpu_block(gpu_id, blocked);
+            let daemon = self.clone();
+            let emitter = emitter.to_owned();
+            tokio::spawn(async move {
+                daemon
+                    .apply_debounced_block(emitter, gpu_id, generation)
+                    .await;
+            });
+
+            let state = if blocked { "on" } else { "off" };
+            Ok(match functions {
+                None => format!("GPU {} block {} queued", gpu_id, state),
+                Some(functions) if functions.is_empty() => format!(
+                    "GPU {} block {} queued; no other functions in its slot",
+                    gpu_id, state
+                ),
+                Some(functions) => format!(
+                    "GPU {} block {} queued; functions {} block {}",
+                    gpu_id,
+                    state,
+                    functions.join(", "),
+                    state
+                ),
+            })
+        })
+        .await
+    }
+
+    /// Block or unblock several GPUs at once, under a single blocker lock.
+    ///
+    /// Applied immediately (no debounce). Returns (gpu_id, ok, message) per requested
+    /// id; unknown or unmanaged ids are reported without aborting the others.
+    async fn set_gpu_blocks(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        gpu_ids: Vec<u32>,
+        blocked: bool,
+    ) -> fdo::Result<Vec<(u32, bool, String)>> {
+        let operation = format!("set_gpu_blocks gpus={:?} blocked={}", gpu_ids, blocked);
+        self.audited(&header, operation, async {
+            let mut results = Vec::with_capacity(gpu_ids.len());
+            let mut targets = Vec::with_capacity(gpu_ids.len());
+            for gpu_id in gpu_ids {
+                match self.gpu_by_id(gpu_id) {
+                    Some(gpu) if self.is_managed(gpu) => {
+                        targets.push((gpu_id, self.block_target(gpu)))
+                    }
+                    Some(gpu) => results.push((
+                        gpu_id,
+                        false,
+                        format!("{} isn't in managed_pci", gpu.pci_address()),
+                    )),
+                    None => results.push((gpu_id, false, "unknown gpu id".to_string())),
+                }
+            }
+
+            let applied = self
+                .with_blocker_write("batch block", move |blocker| {
+                    targets
+                        .into_iter()
+                        .map(|(gpu_id, target)| {
+                            (gpu_id, Self::apply_block_locked(blocker, &target, blocked))
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .await?;
+
+            for (gpu_id, result) in applied {
+                match result {
+                    Ok(()) => {
+                        if let Err(err) = Self::gpu_block_changed(&emitter, gpu_id, blocked).await {
+                            warn!("Failed to emit GpuBlockChanged for GPU {}: {}", gpu_id, err);
+                        }
+                        let state = if blocked { "blocked" } else { "unblocked" };
+                        results.push((gpu_id, true, state.to_string()));
+                    }
+                    Err(err) => results.push((gpu_id, false, err)),
+                }
+            }
+            results.sort_by_key(|(gpu_id, _, _)| *gpu_id);
+
+            info!("Set GPUs block={}: {:?}", blocked, results);
+            Ok(results)
+        })
+        .await
+    }
+
+    /// Use only one GPU: unblock it and block every other non-boot GPU.
+    async fn set_exclusive_gpu(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        gpu_id: u32,
+    ) -> fdo::Result<String> {
+        let operation = format!("set_exclusive_gpu gpu={}", gpu_id);
+        self.audited(&header, operation, async {
+            let target = self.managed_gpu_by_id_or_err(gpu_id)?;
+            let blocked = self.make_exclusive(target).await?;
+
+            info!(
+                "Set GPU {} ({}) exclusive, blocked GPUs {:?}",
+                gpu_id,
+                target.pci_address(),
+                blocked
+            );
+            Ok(format!(
+                "GPU {} exclusive (blocked GPUs: {:?})",
+                gpu_id, blocked
+            ))
+        })
+        .await
+    }
+
+    /// Block or unblock every GPU node for the tasks of one cgroup (e.g. a container).
+    async fn set_cgroup_block(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        cgroup_path: String,
+        blocked: bool,
+    ) -> fdo::Result<String> {
+        let operation = format!("set_cgroup_block {} blocked={}", cgroup_path, blocked);
+        self.audited(&header, operation, async {
+            let id = Self::cgroup_id(&cgroup_path).map_err(fdo::Error::InvalidArgs)?;
+            let action = if blocked {
+                "block cgroup"
+            } else {
+                "unblock cgroup"
+            };
+            self.with_blocker_write(action, move |blocker| {
+                let result = if blocked {
+                    blocker.block_cgroup(id)
+                } else {
+                    blocker.unblock_cgroup(id)
+                };
+                result.map_err(|err| err.to_string())
+            })
+            .await?
+            .map_err(|err| {
+                fdo::Error::Failed(format!("Failed to update cgroup {}: {}", id, err))
+            })?;
+
+            info!(
+                "Set cgroup {} (id {}) block state to {}",
+                cgroup_path, id, blocked
+            );
+            Ok(format!(
+                "cgroup {} (id {}) {}",
+                cgroup_path,
+                id,
+                if blocked { "blocked" } else { "unblocked" }
+            ))
+        })
+        .await
+    }
+
+    /// Block every PCI function matching `pattern` (`bb:dd.*`, `dddd:bb:dd.*` or a full
+    /// address) and save it to `blocked_pci_patterns`. Saved patterns are re-expanded
+    /// at startup, so functions that appear later are blocked on the next start.
+    /// Returns the addresses blocked now (possibly none).
+    async fn block_pci_pattern(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        pattern: String,
+    ) -> fdo::Result<Vec<String>> {
+        let operation = format!("block_pci_pattern {}", pattern);
+        self.audited(&header, operation, async {
+            self.set_pci_pattern_block(&emitter, &pattern, true).await
+        })
+        .await
+    }
+
+    /// Block or unblock every managed GPU bound to a kernel driver (e.g. `nouveau`).
+    /// Blocking saves the driver to `blocked_drivers`, so GPUs using it are blocked
+    /// again at startup; unblocking removes it. Returns the ids of the GPUs changed.
+    async fn block_by_driver(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        name: String,
+        blocked: bool,
+    ) -> fdo::Result<Vec<u32>> {
+        let operation = format!("block_by_driver {} blocked={}", name, blocked);
+        self.audited(&header, operation, async {
+            self.check_writable()?;
+            let name = name.trim().to_string();
+            if name.is_empty() || name.contains('/') {
+                return Err(fdo::Error::InvalidArgs(format!(
+                    "Invalid driver name '{}'",
+                    name
+                )));
+            }
+            let gpus = self.gpus_with_driver(&name);
+            if blocked && gpus.is_empty() {
+                return Err(fdo::Error::InvalidArgs(format!(
+                    "No managed GPU uses driver {}",
+                    name
+                )));
+            }
+
+            let mut changed = Vec::with_capacity(gpus.len());
+            for gpu in gpus {
+                self.apply_gpu_block_policy(gpu, blocked).await?;
+                let gpu_id = gpu.id() as u32;
+                if let Err(err) = Self::gpu_block_changed(&emitter, gpu_id, blocked).await {
+                    warn!("Failed to emit GpuBlockChanged for GPU {}: {}", gpu_id, err);
+                }
+                changed.push(gpu_id);
+            }
+            Self::update_saved_list(&self.blocked_drivers, "blocked_drivers", &name, blocked)?;
+
+            info!("Set driver {} block={}: GPUs {:?}", name, blocked, changed);
+            Ok(changed)
+        })
+        .await
+    }
+
+    /// Unblock every PCI function matching `pattern` and drop it from
+    /// `blocked_pci_patterns`. Returns the addresses unblocked.
+    async fn unblock_pci_pattern(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        pattern: String,
+    ) -> fdo::Result<Vec<String>> {
+        let operation = format!("unblock_pci_pattern {}", pattern);
+        self.audited(&header, operation, async {
+            self.set_pci_pattern_block(&emitter, &pattern, false).await
+        })
+        .await
+    }
+
+    /// Unbind the firmware framebuffer (efifb/simpledrm) from the boot GPU so it can be
+    /// powered down. Needs `allow_framebuffer_release`, another GPU to take over the
+    /// display, and a boot GPU from a known vendor (not e.g. a BMC's VGA).
+    async fn release_boot_framebuffer(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+    ) -> fdo::Result<String> {
+        let operation = "release_boot_framebuffer".to_string();
+        self.audited(&header, operation, async {
+            self.check_writable()?;
+            if !self.allow_framebuffer_release {
+                return Err(fdo::Error::NotSupported(
+                    "Framebuffer release is disabled, set allow_framebuffer_release = true in the config"
+                        .to_string(),
+                ));
+            }
+            let boot_gpu = self
+                .gpu_list
+                .values()
+                .find(|gpu| gpu.is_default())
+                .ok_or_else(|| fdo::Error::Failed("No boot GPU detected".to_string()))?;
+            if self.gpu_list.len() < 2 {
+                return Err(fdo::Error::Failed(
+                    "The boot GPU is the only GPU, releasing its framebuffer would leave no display"
+                        .to_string(),
+                ));
+            }
+            if boot_gpu.vendor() == gpu::Vendor::Other {
+                return Err(fdo::Error::NotSupported(format!(
+                    "Boot GPU {} ({}) is from an unknown vendor, not releasing its framebuffer",
+                    boot_gpu.id(),
+                    boot_gpu.name()
+                )));
+            }
+
+            let released = gpu::release_boot_framebuffer().map_err(|err| {
+                fdo::Error::Failed(format!("Failed to release boot framebuffer: {}", err))
+            })?;
+            if released.is_empty() {
+                return Ok("No firmware framebuffer bound".to_string());
+            }
+            warn!(
+                "Released boot framebuffer {:?} of GPU {} ({})",
+                released,
+                boot_gpu.id(),
+                boot_gpu.pci_address()
+            );
+            Ok(format!("Released {}", released.join(", ")))
+        })
+        .await
+    }
+
+    /// Pause (`false`) or resume (`true`) all blocking. The block set is kept, so
+    /// resuming restores exactly the previous policy. Cancels a timed pause.
+    async fn set_blocking_enabled(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        enabled: bool,
+    ) -> fdo::Result<String> {
+        let operation = format!("set_blocking_enabled {}", enabled);
+        self.audited(&header, operation, async {
+            self.cancel_pause_resume();
+            self.switch_enforcement(enabled).await?;
+            if let Err(err) = Self::blocking_enabled_changed(&emitter, enabled).await {
+                warn!("Failed to emit BlockingEnabledChanged: {}", err);
+            }
+
+            let state = if enabled { "resumed" } else { "paused" };
+            info!("Blocking {}", state);
+            Ok(format!("Blocking {}", state))
+        })
+        .await
+    }
+
+    /// Pause all blocking for `duration_secs`, then resume on its own. A later
+    /// `set_blocking_enabled` or `pause_for` replaces the timer.
+    ///
+    /// Enforcement state isn't saved, so a restart during the pause also resumes.
+    async fn pause_for(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        duration_secs: u64,
+    ) -> fdo::Result<String> {
+        let operation = format!("pause_for {}s", duration_secs);
+        self.audited(&header, operation, async {
+            if duration_secs == 0 {
+                return Err(fdo::Error::InvalidArgs(
+                    "duration_secs must be greater than 0".to_string(),
+                ));
+            }
+
+            self.cancel_pause_resume();
+            self.switch_enforcement(false).await?;
+            if let Err(err) = Self::blocking_enabled_changed(&emitter, false).await {
+                warn!("Failed to emit BlockingEnabledChanged: {}", err);
+            }
+
+            let mut resume = self
+                .pause_resume
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            resume.0 += 1;
+            let generation = resume.0;
+            let daemon = self.clone();
+            let emitter = emitter.to_owned();
+            let task = tokio::spawn(async move {
+                daemon
+                    .resume_after(emitter, generation, Duration::from_secs(duration_secs))
+                    .await;
+            });
+            resume.1 = Some((task, SystemTime::now() + Duration::from_secs(duration_secs)));
+
+            info!("Blocking paused for {}s", duration_secs);
+            Ok(format!("Blocking paused for {}s", duration_secs))
+        })
+        .await
+    }
+
+    /// Daemon status as (key, value) pairs.
+    async fn get_status(&self) -> Vec<(String, String)> {
+        let mode = self.current_mode.read().await.clone();
+        let consistent = self.mode_mismatches().await.is_empty();
+        let (ebpf, enforcing) = self
+            .with_blocker("read blocker status", |blocker| {
+                let enforcing = match blocker.is_blocking_enabled() {
+                    Ok(enabled) => enabled.to_string(),
+                    Err(err) => format!("unknown ({})", err),
+                };
+                (blocker.attach_status().to_string(), enforcing)
+            })
+            .await
+            .unwrap_or_else(|err| (format!("unknown ({})", err), format!("unknown ({})", err)));
+        let config = match settings::check() {
+            Ok(()) => "ok".to_string(),
+            Err(err) => format!("error: {}", err),
+        };
+        let epoch_secs = |time: SystemTime| {
+            time.duration_since(SystemTime::UNIX_EPOCH)
+                .map(|since| since.as_secs())
+                .unwrap_or(0)
+                .to_string()
+        };
+        let revert_at = self
+            .mode_revert
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .1
+            .as_ref()
+            .map(|(_, deadline)| *deadline);
+        let resume_at = self
+            .pause_resume
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .1
+            .as_ref()
+            .map(|(_, deadline)| *deadline);
+
+        let toggle_key = self
+            .toggle_key
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .as_ref()
+            .map_or_else(|| "off".to_string(), |armed| format!("armed ({})", armed));
+
+        // `*_at` values are Unix timestamps in seconds.
+        let mut status = vec![
+            ("mode".to_string(), mode),
+            ("gpu_count".to_string(), self.gpu_list.len().to_string()),
+            ("ebpf".to_string(), ebpf),
+            ("enforcing".to_string(), enforcing),
+            ("config".to_string(), config),
+            ("consistent".to_string(), consistent.to_string()),
+            ("started_at".to_string(), epoch_secs(self.started_at)),
+            ("toggle_key".to_string(), toggle_key),
+            (
+                "read_only".to_string(),
+                self.read_only.load(Ordering::SeqCst).to_string(),
+            ),
+            (
+                "integrated_keeps".to_string(),
+                self.integrated_keep_pci()
+                    .unwrap_or_else(|| "boot gpu".to_string()),
+            ),
+            (
+                "audit_log".to_string(),
+                match self.audit_log.as_ref() {
+                    None => "off".to_string(),
+                    Some(Ok(audit_log)) => audit_log.path().display().to_string(),
+                    Some(Err(err)) => format!("error: {}", err),
+                },
+            ),
+        ];
+        if let Some(deadline) = revert_at {
+            status.push(("revert_at".to_string(), epoch_secs(deadline)));
+        }
+        if let Some(deadline) = resume_at {
+            status.push(("resume_at".to_string(), epoch_secs(deadline)));
+        }
+        status
+    }
+
+    /// The settings the daemon is running with, as TOML. `mode` is the current mode,
+    /// which can differ from the file after a timed or unsaved change, and
+    /// `blocked_pci_patterns`/`blocked_drivers` include entries added since startup.
+    async fn get_effective_config(&self) -> fdo::Result<String> {
+        let mut effective = (*self.settings).clone();
+        effective.mode = self.current_mode.read().await.clone();
+        effective.blocked_pci_patterns = self
+            .pci_patterns
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone();
+        effective.blocked_drivers = self
+            .blocked_drivers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone();
+        toml::to_string(&effective)
+            .map_err(|err| fdo::Error::Failed(format!("Failed to serialize config: {}", err)))
+    }
+
+    /// Config file the daemon reads its settings from and saves them to.
+    async fn get_config_path(&self) -> String {
+        settings::config_path().to_string()
+    }
+
+    /// `RUST_LOG` the daemon was started with; empty when unset (logs at `info`).
+    async fn get_log_filter(&self) -> String {
+        std::env::var("RUST_LOG").unwrap_or_default()
+    }
+
+    /// Kernel module parameters (`/sys/module/*/parameters`) known to conflict with
+    /// blocking the detected GPUs, as `module.parameter=value: consequence`.
+    async fn get_module_param_conflicts(&self) -> Vec<String> {
+        self.module_param_conflicts()
+    }
+
+    /// Load/attach state of the eBPF blocker, with map entry counts.
+    async fn get_blocker_status(&self) -> fdo::Result<GpuInfo> {
+        let (attach_status, hook_name, extra_hooks, pinned_maps_reused, counts) = self
+            .with_blocker("read blocker status", |blocker| {
+                (
+                    blocker.attach_status(),
+                    blocker.hook_name(),
+                    blocker.extra_hooks(),
+                    blocker.pinned_maps_reused(),
+                    blocker.map_counts().map_err(|err| err.to_string()),
+                )
+            })
+            .await?;
+        let kernel =
+            EbpfBlocker::kernel_version().unwrap_or_else(|err| format!("unknown ({})", err));
+        let extra_hooks = extra_hooks
+            .into_iter()
+            .map(|(name, error)| match error {
+                None => name.to_string(),
+                Some(_) => format!("{} (not attached)", name),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let extra_hooks = if extra_hooks.is_empty() {
+            "none".to_string()
+        } else {
+            extra_hooks
+        };
+        let mut status = vec![
+            ("status".to_string(), attach_status.to_string()),
+            ("hook".to_string(), format!("{} LSM", hook_name)),
+            ("extra_hooks".to_string(), extra_hooks),
+            ("kernel".to_string(), kernel),
+            (
+                "bpf_sha256".to_string(),
+                EbpfBlocker::object_hash().to_string(),
+            ),
+            (
+                "pinned_maps_reused".to_string(),
+                pinned_maps_reused.to_string(),
+            ),
+        ];
+        let counts = counts
+            .map_err(|err| fdo::Error::Failed(format!("Failed to read blocker maps: {}", err)))?;
+        for (name, count) in counts {
+            status.push((name.to_string(), count.to_string()));
+        }
+        Ok(status)
+    }
+
+    /// SHA-256 of the eBPF object built into this daemon, to check a deployment runs
+    /// the expected build of bpf.o.
+    async fn get_bpf_hash(&self) -> String {
+        EbpfBlocker::object_hash().to_string()
+    }
+
+    /// The daemon's latest log records (mode and block changes, warnings, errors) as
+    /// (Unix timestamp, level, message), oldest first. At most 100 are kept; `limit`
+    /// 0 returns all of them.
+    async fn get_recent_events(&self, limit: u32) -> Vec<events::EventRecord> {
+        events::recent(limit as usize)
+    }
+
+    /// Check that the blocker map round-trip works, using a sentinel node id that no
+    /// GPU can have.
+    async fn run_self_test(&self) -> fdo::Result<String> {
+        // The sentinel isn't a GPU, so this is allowed even while read-only.
+        self.with_blocker("self-test", |blocker| {
+            blocker.self_test().map_err(|err| err.to_string())
+        })
+        .await?
+        .map_err(fdo::Error::Failed)?;
+        info!("Blocker self-test passed");
+        Ok("insert, read back, remove and re-check of BLOCKED_IDS ok".to_string())
+    }
+
+    /// Access routes the blocker currently denies for a blocked GPU: `render` and
+    /// `card` (DRM nodes), `pci` (sysfs config space), `cgroup` when the kernel can
+    /// tell cgroups apart, `extra-nodes` and `fbdev` when configured, and `fd-io` and
+    /// `mmap` when those hooks attached. Empty while the hook isn't attached.
+    async fn list_enforcement_coverage(&self) -> fdo::Result<Vec<String>> {
+        let (attach_status, extra_hooks) = self
+            .with_blocker("read enforcement coverage", |blocker| {
+                (blocker.attach_status(), blocker.extra_hooks())
+            })
+            .await?;
+        if attach_status == AttachStatus::Detached {
+            return Ok(Vec::new());
+        }
+        let mut routes = vec!["render", "card", "pci"];
+        if self.capabilities.cgroup_id_helper_available {
+            routes.push("cgroup");
+        }
+        if !self.extra_block_prefixes.is_empty() {
+            routes.push("extra-nodes");
+        }
+        if self.settings.block_fb_nodes {
+            routes.push("fbdev");
+        }
+        for (hook, error) in extra_hooks {
+            if error.is_none() {
+                routes.push(match hook {
+                    "file_permission" => "fd-io",
+                    "mmap_file" => "mmap",
+                    other => other,
+                });
+            }
+        }
+        Ok(routes.into_iter().map(str::to_string).collect())
+    }
+
+    /// Kernel features available to the blocker, as probed at startup.
+    async fn get_capabilities(&self) -> Vec<(String, bool)> {
+        self.capabilities
+            .as_pairs()
+            .into_iter()
+            .map(|(name, available)| (name.to_string(), available))
+            .collect()
+    }
+
+    /// Whether the effective block state matches the current mode, with the mismatches
+    /// and any startup reconciliation failure.
+    async fn get_mode_consistency(&self) -> (bool, Vec<String>) {
+        let mut mismatches = self.mode_mismatches().await;
+        let reconcile_error = self
+            .reconcile_error
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone();
+        if let Some(err) = reconcile_error {
+            mismatches.insert(0, format!("startup reconciliation failed: {}", err));
+        }
+        (mismatches.is_empty(), mismatches)
+    }
+
+    /// Detailed information about one GPU.
+    async fn get_gpu_detail(&self, gpu_id: u32) -> fdo::Result<GpuInfo> {
+        let gpu = self.gpu_by_id_or_err(gpu_id)?;
+        Ok(self.gpu_info(gpu).await?)
+    }
+
+    /// Display connectors of one GPU as (name, type, status); empty for headless GPUs.
+    async fn get_gpu_connectors(&self, gpu_id: u32) -> fdo::Result<Vec<(String, String, String)>> {
+        let gpu = self.gpu_by_id_or_err(gpu_id)?;
+        Ok(gpu
+            .connectors()
+            .into_iter()
+            .map(|connector| (connector.name, connector.kind, connector.status))
+            .collect())
+    }
+
+    /// GPUs in switcheroo-control's `GPUs` shape, for desktops that speak it: `Name`,
+    /// `Environment` (flat [name, value, ...] list), `Default`, `Discrete`.
+    async fn get_switcheroo_gpus(&self) -> Vec<HashMap<String, zbus::zvariant::Value<'static>>> {
+        let mut gpus: Vec<&gpu::Gpu> = self.gpu_list.values().collect();
+        gpus.sort_by_key(|gpu| (!gpu.is_default(), gpu.id()));
+        gpus.into_iter()
+            .map(|gpu| {
+                let environment: Vec<String> = gpu
+                    .offload_env()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .flat_map(|(name, value)| [name.to_string(), value])
+                    .collect();
+                HashMap::from([
+                    (
+                        "Name".to_string(),
+                        zbus::zvariant::Value::from(self.display_name(gpu).to_string()),
+                    ),
+                    (
+                        "Environment".to_string(),
+                        zbus::zvariant::Value::from(environment),
+                    ),
+                    (
+                        "Default".to_string(),
+                        zbus::zvariant::Value::from(gpu.is_default()),
+                    ),
+                    (
+                        "Discrete".to_string(),
+                        zbus::zvariant::Value::from(!gpu.is_integrated()),
+                    ),
+                ])
+            })
+            .collect()
+    }
+
+    /// Per GPU, what the current mode expects against what the maps hold, as (id, pci,
+    /// expected, actual, matches, entries). `expected` is `blocked`, `unblocked` or
+    /// `any` for GPUs the mode leaves alone; `actual` is a `block_state`; `entries`
+    /// lists each map entry, e.g. `pci=on /dev/dri/renderD129=off`.
+    async fn get_block_diff(
+        &self,
+    ) -> fdo::Result<Vec<(u32, String, String, String, bool, String)>> {
+        let mode = self.current_mode.read().await.clone();
+        let mut gpus: Vec<&gpu::Gpu> = self.gpu_list.values().collect();
+        gpus.sort_by_key(|gpu| gpu.id());
+
+        let mut rows = Vec::with_capacity(gpus.len());
+        for gpu in gpus {
+            let expected = self.mode_block_decision(&mode, gpu);
+            let actual = self.block_consistency(gpu).await?;
+            let matches = match expected {
+                None => true,
+                Some(true) => actual == BlockState::FullyBlocked,
+                Some(false) => actual == BlockState::Unblocked,
+            };
+            let entries = self
+                .block_entries(gpu)
+                .await?
+                .into_iter()
+                .map(|(entry, blocked)| format!("{}={}", entry, if blocked { "on" } else { "off" }))
+                .collect::<Vec<_>>()
+                .join(" ");
+            rows.push((
+                gpu.id() as u32,
+                gpu.pci_address().to_string(),
+                match expected {
+                    Some(true) => "blocked",
+                    Some(false) => "unblocked",
+                    None => "any",
+                }
+                .to_string(),
+                actual.as_str().to_string(),
+                matches,
+                entries,
+            ));
+        }
+        Ok(rows)
+    }
+
+    /// `GetGpuDetail` for every GPU, ordered by id.
+    async fn list_gpus_detailed(&self) -> fdo::Result<Vec<GpuInfo>> {
+        let mut gpus: Vec<&gpu::Gpu> = self.gpu_list.values().collect();
+        gpus.sort_by_key(|gpu| gpu.id());
+        let mut details = Vec::with_capacity(gpus.len());
+        for gpu in gpus {
+            details.push(self.gpu_info(gpu).await?);
+        }
+        Ok(details)
+    }
+
+    /// Effective block state of one GPU, as in `ListGpus` (`blocked`, `partial` or
+    /// `unblocked`), without looking at the others. Cheap enough to poll.
+    async fn get_gpu_block_state(&self, gpu_id: u32) -> fdo::Result<String> {
+        let gpu = self.gpu_by_id_or_err(gpu_id)?;
+        Ok(self.block_consistency(gpu).await?.as_str().to_string())
+    }
+
+    /// Opens denied by one GPU's blocks since they were set.
+    async fn get_block_stats(&self, gpu_id: u32) -> fdo::Result<u64> {
+        let gpu = self.gpu_by_id_or_err(gpu_id)?;
+        self.gpu_block_hits(gpu)
+            .await?
+            .map_err(|err| fdo::Error::Failed(format!("Failed to read block hits: {}", err)))
+    }
+
+    /// Detailed information about the GPU at a PCI address (short forms accepted).
+    async fn get_gpu_by_pci(&self, address: String) -> fdo::Result<GpuInfo> {
+        let pci = iommu::normalize_pci(&address);
+        let gpu = self
+            .gpu_list
+            .values()
+            .find(|gpu| gpu.pci_address() == pci)
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("No GPU at PCI address {}", pci)))?;
+        Ok(self.gpu_info(gpu).await?)
+    }
+
+    /// Detailed information about the GPU with a `gpu_aliases` label.
+    async fn get_gpu_by_alias(&self, alias: String) -> fdo::Result<GpuInfo> {
+        let gpu = self
+            .gpu_aliases
+            .iter()
+            .find(|(_, label)| **label == alias)
+            .and_then(|(address, _)| {
+                self.gpu_list
+                    .values()
+                    .find(|gpu| gpu.pci_address() == address)
+            })
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("No GPU is aliased '{}'", alias)))?;
+        Ok(self.gpu_info(gpu).await?)
+    }
+
+    /// Instantaneous power draw of one GPU in watts.
+    async fn get_gpu_power(&self, gpu_id: u32) -> fdo::Result<f64> {
+        let gpu = self.gpu_by_id_or_err(gpu_id)?;
+        gpu.power_draw_watts().map(f64::from).ok_or_else(|| {
+            fdo::Error::NotSupported(format!("GPU {} doesn't report power draw", gpu_id))
+        })
+    }
+
+    /// Read an allow-listed sysfs attribute (`gpu::READABLE_ATTRIBUTES`) of a GPU's
+    /// PCI device, e.g. `runtime_status`. Other names are rejected, so this can't
+    /// read arbitrary files.
+    async fn read_gpu_attribute(&self, gpu_id: u32, attr_name: String) -> fdo::Result<String> {
+        let gpu = self.gpu_by_id_or_err(gpu_id)?;
+        gpu.read_attribute(&attr_name)
+            .map_err(|err| match err.kind() {
+                std::io::ErrorKind::InvalidInput => fdo::Error::InvalidArgs(err.to_string()),
+                std::io::ErrorKind::NotFound => {
+                    fdo::Error::NotSupported(format!("GPU {} doesn't expose {}", gpu_id, attr_name))
+                }
+                _ => fdo::Error::Failed(format!(
+                    "Failed to read {} of GPU {}: {}",
+                    attr_name, gpu_id, err
+                )),
+            })
+    }
+
+    /// A GPU's (used, total) VRAM in bytes; only amdgpu exposes it.
+    async fn get_gpu_vram(&self, gpu_id: u32) -> fdo::Result<(u64, u64)> {
+        let gpu = self.gpu_by_id_or_err(gpu_id)?;
+        gpu.vram_usage().ok_or_else(|| {
+            fdo::Error::NotSupported(format!("GPU {} doesn't report VRAM usage", gpu_id))
+        })
+    }
+
+    /// Environment variables to run an app on one GPU with PRIME render offload.
+    async fn get_offload_env(&self, gpu_id: u32) -> fdo::Result<HashMap<String, String>> {
+        let gpu = self.gpu_by_id_or_err(gpu_id)?;
+        if self.is_gpu_blocked(gpu).await? {
+            return Err(fdo::Error::Failed(format!(
+                "GPU {} is blocked, unblock it first",
+                gpu_id
+            )));
+        }
+        let env = gpu.offload_env().ok_or_else(|| {
+            fdo::Error::NotSupported(format!(
+                "No render offload support for GPU {} ({})",
+                gpu_id,
+                gpu.name()
+            ))
+        })?;
+        Ok(env
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect())
+    }
+
+    /// Time GPU enumeration and a map round-trip, in microseconds (diagnostics only).
+    async fn debug_bench(&self) -> fdo::Result<Vec<(String, u64)>> {
+        let micros = |start: Instant| start.elapsed().as_micros() as u64;
+
+        let start = Instant::now();
+        let pci_devices = iommu::read_pci_devices()
+            .map_err(|err| fdo::Error::Failed(format!("read_pci_devices failed: {}", err)))?;
+        let read_pci = micros(start);
+
+        let start = Instant::now();
+        gpu::list_gpu(&pci_devices)
+            .map_err(|err| fdo::Error::Failed(format!("list_gpu failed: {}", err)))?;
+        let list_gpu = micros(start);
+
+        let mut timings = vec![
+            ("read_pci_devices".to_string(), read_pci),
+            ("list_gpu".to_string(), list_gpu),
+        ];
+        // Read-only makes no map writes at all, so the round-trip is left out.
+        if self.read_only.load(Ordering::SeqCst) {
+            return Ok(timings);
+        }
+
+        // An id no renderD/card node can have, so the round-trip never blocks anything.
+        let round_trip = self
+            .with_blocker_write("bench round-trip", move |blocker| {
+                let start = Instant::now();
+                blocker
+                    .block_id(SENTINEL_NODE_ID)
+                    .and_then(|()| blocker.unblock_id(SENTINEL_NODE_ID))
+                    .map(|()| micros(start))
+                    .map_err(|err| err.to_string())
+            })
+            .await?
+            .map_err(|err| fdo::Error::Failed(format!("block/unblock failed: {}", err)))?;
+        timings.push(("block_unblock_round_trip".to_string(), round_trip));
+        Ok(timings)
+    }
+
+    /// Forced DPM performance level of one GPU (`auto`, `low`, `high`, ...).
+    async fn get_gpu_power_profile(&self, gpu_id: u32) -> fdo::Result<String> {
+        let gpu = self.gpu_by_id_or_err(gpu_id)?;
+        gpu.power_profile()
+            .map_err(|err| Self::power_profile_error(gpu_id, err))
+    }
+
+    /// Force a DPM performance level on one GPU.
+    async fn set_gpu_power_profile(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        gpu_id: u32,
+        level: String,
+    ) -> fdo::Result<String> {
+        let operation = format!("set_gpu_power_profile gpu={} level={}", gpu_id, level);
+        self.audited(&header, operation, async {
+            self.check_writable()?;
+            let gpu = self.gpu_by_id_or_err(gpu_id)?;
+            gpu.set_power_profile(&level)
+                .map_err(|err| Self::power_profile_error(gpu_id, err))?;
+            info!(
+                "Set GPU {} ({}) power profile to {}",
+                gpu_id,
+                gpu.pci_address(),
+                level
+            );
+            Ok(format!("GPU {} power profile set to {}", gpu_id, level))
+        })
+        .await
+    }
+
+    /// Emitted after the mode changes through `set_mode`, `set_mode_timed`, a timed revert,
+    /// or an external block change detected by the block watcher.
+    #[zbus(signal)]
+    async fn mode_changed(emitter: &SignalEmitter<'_>, mode: &str) -> zbus::Result<()>;
+
+    /// Emitted when blocking is paused or resumed, including when a timed pause ends.
+    #[zbus(signal)]
+    async fn blocking_enabled_changed(
+        emitter: &SignalEmitter<'_>,
+        enabled: bool,
+    ) -> zbus::Result<()>;
+
+    /// Emitted once a (debounced) GPU block change has been applied.
+    #[zbus(signal)]
+    async fn gpu_block_changed(
+        emitter: &SignalEmitter<'_>,
+        gpu_id: u32,
+        blocked: bool,
+    ) -> zbus::Result<()>;
+
+    /// Export mode and block set as TOML.
+    async fn export_state(&self) -> fdo::Result<String> {
+        let state = self.export_block_state().await?;
+        toml::to_string(&state)
+            .map_err(|err| fdo::Error::Failed(format!("Failed to serialize state: {}", err)))
+    }
+
+    /// Apply a state produced by `export_state`, all or nothing.
+    async fn import_state(
+        &self,
+        #[zbus(header)] header: Header<'_>,
+        data: String,
+    ) -> fdo::Result<String> {
+        let operation = "import_state".to_string();
+        self.audited(&header, operation, async {
+            let state: ExportedState = toml::from_str(&data)
+                .map_err(|err| fdo::Error::InvalidArgs(format!("Invalid state data: {}", err)))?;
+            self.import_block_state(state).await
+        })
+        .await
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+
+    let _ = events::init(
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+            .format_timestamp_millis(),
+    );
+
+    settings::ensure_exists()?;
+    let settings = settings::Settings::load();
+
+    let configured_mode = settings::startup_mode(settings.mode.clone());
+    if settings.require_enforcement {
+        info!("Enforcement required: exiting unless the eBPF blocker is fully attached");
+    } else {
+        info!("Enforcement best effort: a degraded eBPF blocker only logs a warning");
+    }
+    let daemon = Daemon::new(configured_mode.clone(), settings.clone())?;
+    let attach_status = {
+        let mut blocker = daemon.ebpf_blocker.lock().await;
+        for (hook, error) in blocker.extra_hooks() {
+            match error {
+                None => info!("Attached extra LSM hook {}", hook),
+                Some(err) => warn!(
+                    "Extra LSM hook {} not attached, fds opened before a block keep working through it: {}",
+                    hook, err
+                ),
+            }
+        }
+        // Never come up paused: a timed pause doesn't survive a restart.
+        if blocker.is_blocking_enabled().ok() == Some(false) {
+            warn!("Blocking was left paused, resuming");
+            if let Err(err) = blocker.set_blocking_enabled(true) {
+                warn!("Failed to resume blocking: {}", err);
+            }
+        }
+        blocker.attach_status()
+    };
+    if attach_status != AttachStatus::Attached {
+        if settings.require_enforcement {
+            error!(
+                "eBPF blocker is {} and require_enforcement is set, refusing to start",
+                attach_status
+            );
+            std::process::exit(EXIT_NOT_ENFORCING);
+        }
+        warn!(
+            "eBPF blocker is {}: part of the block policy won't be enforced",
+            attach_status
         );
     }
 
-    let hardware_mode = daemon.get_current_hardware_mode()?;
-    info!("Configured mode from config: {}", configured_mode);
-    info!("Current hardware mode: {}", hardware_mode);
-    if hardware_mode != configured_mode {
+    if daemon.gpu_list.is_empty() {
+        warn!(
+            "NO GPUS DETECTED: no PCI display controllers found, every GPU operation will be a no-op (daemon keeps serving)"
+        );
+    }
+    for conflict in daemon.module_param_conflicts() {
+        warn!("Conflicting module parameter {}", conflict);
+    }
+    info!("Detected GPUs:");
+    for gpu in daemon.gpu_list.values() {
         info!(
-            "Hardware mode doesn't match configured mode, applying configured mode {}...",
-            configured_mode
+            "- [#{}] {} | pci={} | render={} | default={}",
+            gpu.id(),
+            gpu.name(),
+            gpu.pci_address(),
+            gpu.render_node(),
+            gpu.is_default()
         );
-        daemon.set_mode(configured_mode).await?;
-    } else {
-        info!("Hardware mode matches configured mode: {}", configured_mode);
     }
-    let conn_builder = connection::Builder::system()?;
-    let _conn = conn_builder
-        .name("com.chainsaw.daemon")?
-        .serve_at("/com/chainsaw/daemon", daemon)?
+
+    // Serve first so the API stays reachable even if applying the mode fails.
+    let conn_builder = if args.session {
+        warn!("Serving on the session bus (development mode)");
+        connection::Builder::session()?
+    } else {
+        connection::Builder::system()?
+    };
+    // Other instances may only take the name with --replace.
+    let built = conn_builder
+        .name(BUS_NAME)?
+        .allow_name_replacements(true)
+        .replace_existing_names(args.replace)
+        .serve_at("/com/chainsaw/daemon", daemon.clone())?
         .build()
+        .await;
+    let conn = match built {
+        Ok(conn) => conn,
+        Err(zbus::Error::NameTaken) => {
+            let owner = match bus_name_owner_pid(args.session).await {
+                Some(pid) => format!(" (PID {})", pid),
+                None => String::new(),
+            };
+            error!(
+                "Another chainsaw-daemon is already running{} and owns {}; stop it, or start with --replace to take over",
+                owner, BUS_NAME
+            );
+            std::process::exit(EXIT_ALREADY_RUNNING);
+        }
+        Err(err) => return Err(err.into()),
+    };
+    if args.replace {
+        info!("Took over {} (--replace)", BUS_NAME);
+    }
+    let mut name_lost = fdo::DBusProxy::new(&conn)
+        .await?
+        .receive_name_lost()
         .await?;
 
     info!("Daemon started");
 
-    pending::<()>().await;
+    if switcheroo_running(&conn).await {
+        match settings.switcheroo_policy.as_str() {
+            "defer" => {
+                daemon.read_only.store(true, Ordering::SeqCst);
+                warn!(
+                    "{} is running and switcheroo_policy = \"defer\": read-only, no GPU will be blocked",
+                    SWITCHEROO_BUS_NAME
+                );
+            }
+            "coexist" => info!(
+                "{} is running, coexisting with it (switcheroo_policy = \"coexist\")",
+                SWITCHEROO_BUS_NAME
+            ),
+            policy => {
+                if !policy.is_empty() {
+                    warn!(
+                        "Unknown switcheroo_policy '{}', expected defer or coexist",
+                        policy
+                    );
+                }
+                warn!(
+                    "{} is running too and both manage GPUs; set switcheroo_policy to \"defer\" or \"coexist\"",
+                    SWITCHEROO_BUS_NAME
+                );
+            }
+        }
+    }
+
+    if daemon.read_only.load(Ordering::SeqCst) {
+        info!(
+            "Read-only: not applying mode {} or saved blocks",
+            configured_mode
+        );
+    } else {
+        info!("Configured mode from config: {}", configured_mode);
+        daemon.reconcile(configured_mode).await;
+        daemon.apply_saved_pci_patterns().await;
+        daemon.apply_saved_driver_blocks().await;
+    }
+
+    if settings.block_watch_interval_ms > 0 {
+        let interval = Duration::from_millis(settings.block_watch_interval_ms);
+        let emitter = SignalEmitter::new(&conn, "/com/chainsaw/daemon")?.into_owned();
+        let watcher = daemon.clone();
+        tokio::spawn(async move { watcher.watch_block_state(emitter, interval).await });
+        info!(
+            "Watching for external block changes every {}ms",
+            interval.as_millis()
+        );
+    }
+
+    if !daemon.read_only.load(Ordering::SeqCst) {
+        let watcher = daemon.clone();
+        tokio::spawn(async move { watcher.watch_gpu_nodes().await });
+    }
+
+    if daemon.ebpf_blocker.lock().await.attach_status() != AttachStatus::Detached {
+        let watcher = daemon.clone();
+        tokio::spawn(async move { watcher.watch_denials().await });
+    }
+
+    daemon.arm_toggle_key(SignalEmitter::new(&conn, "/com/chainsaw/daemon")?.into_owned());
+
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+    let taken_over = tokio::select! {
+        _ = tokio::signal::ctrl_c() => false,
+        _ = sigterm.recv() => false,
+        Some(_) = name_lost.next() => {
+            warn!("{} was taken over by another instance (--replace)", BUS_NAME);
+            true
+        }
+    };
+    info!("Shutting down");
+    daemon.cancel_mode_revert();
+    daemon.cancel_pause_resume();
+    // The new instance already runs over the same pinned maps; clearing them would
+    // undo its blocks.
+    if taken_over && settings.unblock_on_exit {
+        info!("Leaving block entries to the instance that took over");
+    } else if settings.unblock_on_exit {
+        match daemon
+            .with_blocker_write("unblock all", |blocker| {
+                blocker.unblock_all().map_err(|err| err.to_string())
+            })
+            .await
+        {
+            Ok(Ok(cleared)) => info!("Cleared {} block entries on exit", cleared),
+            Ok(Err(err)) => warn!("Failed to clear blocks on exit: {}", err),
+            Err(err) => warn!("Failed to clear blocks on exit: {}", err),
+        }
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_gpu(id: usize, pci: &str, render: &str, card: &str, default: bool) -> gpu::Gpu {
+        gpu::Gpu::new(
+            id,
+            &format!("Test GPU {}", id),
+            pci,
+            render,
+            card,
+            default,
+            gpu::Vendor::Other,
+        )
+    }
+
+    fn daemon_over(gpus: Vec<gpu::Gpu>) -> Daemon {
+        let gpu_list = gpus
+            .into_iter()
+            .map(|gpu| (gpu.id().to_string(), gpu))
+            .collect();
+        Daemon::with_parts(
+            MODE_HYBRID.to_string(),
+            gpu_list,
+            Box::new(MemoryBlocker::new()),
+        )
+    }
+
+    /// Boot GPU 0 and discrete GPU 1, over an in-memory blocker.
+    fn test_daemon() -> Daemon {
+        daemon_over(vec![
+            test_gpu(0, "00:02.0", "/dev/dri/renderD128", "/dev/dri/card0", true),
+            test_gpu(1, "03:00.0", "/dev/dri/renderD129", "/dev/dri/card1", false),
+        ])
+    }
+
+    async fn blocked_pci(daemon: &Daemon) -> Vec<String> {
+        daemon
+            .with_blocker("test", |blocker| blocker.blocked_pci().unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn with_parts_daemon_lists_gpus_and_switches_modes() {
+        let daemon = test_daemon();
+        let blocked = |rows: Vec<GpuRow>| -> Vec<(u32, bool)> {
+            rows.into_iter().map(|row| (row.0, row.5)).collect()
+        };
+        assert_eq!(
+            blocked(daemon.list_gpu_rows().await.unwrap()),
+            [(0, false), (1, false)]
+        );
+
+        daemon.apply_mode(MODE_INTEGRATED, false).await.unwrap();
+        assert_eq!(*daemon.current_mode.read().await, MODE_INTEGRATED);
+        assert_eq!(
+            blocked(daemon.list_gpu_rows().await.unwrap()),
+            [(0, false), (1, true)]
+        );
+        assert_eq!(blocked_pci(&daemon).await, ["0000:03:00.0"]);
+
+        daemon.apply_mode(MODE_HYBRID, false).await.unwrap();
+        assert!(blocked_pci(&daemon).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn added_gpu_gets_only_its_own_policy_applied() {
+        let daemon = daemon_over(vec![
+            test_gpu(0, "00:02.0", "/dev/dri/renderD128", "/dev/dri/card0", true),
+            test_gpu(1, "03:00.0", "/dev/dri/renderD129", "/dev/dri/card1", false),
+            test_gpu(2, "05:00.0", "/dev/dri/renderD130", "/dev/dri/card2", false),
+        ]);
+        let gpu = |id| daemon.gpu_by_id(id).unwrap();
+
+        daemon
+            .apply_policy_to(gpu(2), MODE_INTEGRATED)
+            .await
+            .unwrap();
+        assert_eq!(blocked_pci(&daemon).await, ["0000:05:00.0"]);
+
+        // The boot GPU has no decision under integrated; nothing changes.
+        daemon
+            .apply_policy_to(gpu(0), MODE_INTEGRATED)
+            .await
+            .unwrap();
+        assert_eq!(blocked_pci(&daemon).await, ["0000:05:00.0"]);
+
+        daemon.apply_policy_to(gpu(2), MODE_HYBRID).await.unwrap();
+        assert!(blocked_pci(&daemon).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rescan_applies_the_mode_to_appearing_gpus_and_clears_removed_ones() {
+        let daemon = test_daemon();
+        *daemon.current_mode.write().await = MODE_INTEGRATED.to_string();
+        let mut present = HashMap::from([(0, true), (1, false)]);
+
+        // GPU 1's driver binds: it gets integrated mode's block.
+        daemon.rescan_gpus(&mut present, |_| true, |_| true).await;
+        assert_eq!(blocked_pci(&daemon).await, ["0000:03:00.0"]);
+        // Nothing changed since: nothing is reapplied.
+        daemon
+            .with_blocker("test", |blocker| {
+                blocker.unblock_pci("0000:03:00.0").unwrap()
+            })
+            .await
+            .unwrap();
+        daemon.rescan_gpus(&mut present, |_| true, |_| true).await;
+        assert!(blocked_pci(&daemon).await.is_empty());
+        daemon
+            .apply_policy_to(daemon.gpu_by_id(1).unwrap(), MODE_INTEGRATED)
+            .await
+            .unwrap();
+
+        // Its driver unbinds but the device stays: the block stays too.
+        let not_gpu1 = |gpu: &gpu::Gpu| gpu.id() != 1;
+        daemon.rescan_gpus(&mut present, not_gpu1, |_| true).await;
+        assert_eq!(blocked_pci(&daemon).await, ["0000:03:00.0"]);
+
+        // Plugged back in, then unplugged: its entries go.
+        daemon.rescan_gpus(&mut present, |_| true, |_| true).await;
+        daemon.rescan_gpus(&mut present, not_gpu1, not_gpu1).await;
+        assert!(blocked_pci(&daemon).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn removed_gpu_entries_are_cleared() {
+        let daemon = test_daemon();
+        // GPU 1 is still here; 0000:05:00.0 with node 130 was unplugged.
+        daemon
+            .with_blocker("test", |blocker| {
+                blocker.block_ids(&[129, 130]).unwrap();
+                blocker.block_pci("0000:03:00.0").unwrap();
+                blocker.block_pci("0000:05:00.0").unwrap();
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(daemon.clear_stale_entries().await, Ok(2));
+        let ids = daemon
+            .with_blocker("test", |blocker| blocker.blocked_ids().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(ids, [129]);
+        assert_eq!(blocked_pci(&daemon).await, ["0000:03:00.0"]);
+
+        assert_eq!(daemon.clear_stale_entries().await, Ok(0));
+    }
+
+    #[tokio::test]
+    async fn hardware_mode_is_read_from_the_maps() {
+        let daemon = test_daemon();
+        let hardware_mode = |preferred: &'static str| daemon.get_current_hardware_mode(preferred);
+        assert_eq!(
+            hardware_mode(MODE_HYBRID).await.unwrap().as_deref(),
+            Some(MODE_HYBRID)
+        );
+
+        // As left in pinned maps by an earlier run in integrated mode.
+        daemon
+            .with_blocker("test", |blocker| blocker.block_pci("0000:03:00.0").unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            hardware_mode(MODE_INTEGRATED).await.unwrap().as_deref(),
+            Some(MODE_INTEGRATED)
+        );
+        assert_ne!(
+            hardware_mode(MODE_HYBRID).await.unwrap().as_deref(),
+            Some(MODE_HYBRID)
+        );
+    }
+
+    #[tokio::test]
+    async fn expired_blocks_are_purged_from_the_map() {
+        let daemon = test_daemon();
+        let id_entries = || {
+            daemon.with_blocker("test", |blocker| {
+                blocker
+                    .map_counts()
+                    .unwrap()
+                    .into_iter()
+                    .find(|(name, _)| *name == "BLOCKED_IDS")
+                    .unwrap()
+                    .1
+            })
+        };
+        daemon
+            .with_blocker("test", |blocker| {
+                blocker.block_id(128).unwrap();
+                blocker.block_id_for(129, Duration::from_millis(1)).unwrap();
+            })
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(id_entries().await.unwrap(), 2);
+
+        daemon.purge_expired_blocks().await;
+        assert_eq!(id_entries().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn exclusive_gpu_swaps_the_block_set_at_once() {
+        let daemon = daemon_over(vec![
+            test_gpu(0, "00:02.0", "/dev/dri/renderD128", "/dev/dri/card0", true),
+            test_gpu(1, "03:00.0", "/dev/dri/renderD129", "/dev/dri/card1", false),
+            test_gpu(2, "05:00.0", "/dev/dri/renderD130", "/dev/dri/card2", false),
+        ]);
+        let gpu = |id| daemon.gpu_by_id(id).unwrap();
+        daemon.apply_gpu_block_policy(gpu(1), true).await.unwrap();
+
+        assert_eq!(daemon.make_exclusive(gpu(1)).await.unwrap(), [2]);
+        assert_eq!(blocked_pci(&daemon).await, ["0000:05:00.0"]);
+
+        assert_eq!(daemon.make_exclusive(gpu(2)).await.unwrap(), [1]);
+        assert_eq!(blocked_pci(&daemon).await, ["0000:03:00.0"]);
+    }
+
+    #[tokio::test]
+    async fn rapid_block_requests_coalesce_into_one_apply() {
+        let daemon = test_daemon();
+        let generations: Vec<u64> = [true, false, true, false, true]
+            .into_iter()
+            .map(|blocked| daemon.queue_gpu_block(1, blocked))
+            .collect();
+        let applied: Vec<bool> = generations
+            .iter()
+            .filter_map(|generation| daemon.latest_queued_block(1, *generation))
+            .collect();
+        assert_eq!(applied, [true]);
+
+        let gpu = daemon.gpu_by_id(1).unwrap();
+        daemon
+            .apply_gpu_block_policy(gpu, applied[0])
+            .await
+            .unwrap();
+        assert_eq!(blocked_pci(&daemon).await, ["0000:03:00.0"]);
+    }
+
+    #[test]
+    fn block_then_unblock_nets_out_to_unblocked() {
+        let daemon = test_daemon();
+        let block = daemon.queue_gpu_block(1, true);
+        let unblock = daemon.queue_gpu_block(1, false);
+        assert_eq!(daemon.latest_queued_block(1, block), None);
+        assert_eq!(daemon.latest_queued_block(1, unblock), Some(false));
+        // Another GPU's queue is separate.
+        let other = daemon.queue_gpu_block(0, true);
+        assert_eq!(daemon.latest_queued_block(0, other), Some(true));
+        assert_eq!(daemon.latest_queued_block(1, unblock), Some(false));
+    }
+
+    #[test]
+    fn parse_node_id_reads_drm_node_ids() {
+        assert_eq!(
+            Daemon::parse_node_id("/dev/dri/renderD128", RENDER_NODE_PREFIX),
+            Some(128)
+        );
+        assert_eq!(
+            Daemon::parse_node_id("/dev/dri/card0", CARD_NODE_PREFIX),
+            Some(0)
+        );
+        assert_eq!(
+            Daemon::parse_node_id("/dev/dri/card12", CARD_NODE_PREFIX),
+            Some(12)
+        );
+    }
+
+    #[test]
+    fn parse_node_id_rejects_unexpected_shapes() {
+        for (node, prefix) in [
+            (
+                "/dev/dri/by-path/pci-0000:03:00.0-render",
+                RENDER_NODE_PREFIX,
+            ),
+            ("/dev/dri/renderD", RENDER_NODE_PREFIX),
+            ("/dev/dri/renderD12a", RENDER_NODE_PREFIX),
+            ("renderD128", RENDER_NODE_PREFIX),
+            ("/dev/dri/card1-HDMI-A-1", CARD_NODE_PREFIX),
+            ("/dev/dri/renderD128", CARD_NODE_PREFIX),
+            ("no render node for 0000:03:00.0", RENDER_NODE_PREFIX),
+        ] {
+            assert_eq!(Daemon::parse_node_id(node, prefix), None, "{}", node);
+        }
+    }
+
+    #[test]
+    fn unparsable_nodes_fall_back_to_device_number() {
+        let devno = Daemon::node_devno("/dev/null").unwrap();
+        let target = BlockTarget {
+            pci: "0000:03:00.0".to_string(),
+            nodes: vec![
+                ("render", "/dev/null".to_string(), Some(RENDER_NODE_PREFIX)),
+                (
+                    "card",
+                    "/dev/dri/by-path/missing-card".to_string(),
+                    Some(CARD_NODE_PREFIX),
+                ),
+            ],
+            sharers: HashMap::new(),
+        };
+        let (ids, devs) = Daemon::node_entries(&target, true, |_| None);
+        assert!(ids.is_empty());
+        assert_eq!(devs, [("render", "/dev/null".to_string(), devno)]);
+    }
+
+    #[tokio::test]
+    async fn node_shared_with_an_available_gpu_is_not_blocked() {
+        let daemon = daemon_over(vec![
+            test_gpu(0, "00:02.0", "/dev/dri/renderD128", "/dev/dri/card0", true),
+            test_gpu(1, "03:00.0", "/dev/dri/renderD128", "/dev/dri/card1", false),
+        ]);
+        assert_eq!(
+            daemon.shared_nodes.get("/dev/dri/renderD128"),
+            Some(&vec![0, 1])
+        );
+        let gpu = daemon.gpu_by_id(1).unwrap();
+        let boot = ("0000:00:02.0".to_string(), true);
+        assert_eq!(
+            daemon.node_sharers(gpu).get("/dev/dri/renderD128"),
+            Some(&vec![boot.clone()])
+        );
+
+        daemon.apply_gpu_block_policy(gpu, true).await.unwrap();
+        let ids = daemon
+            .with_blocker("test", |blocker| blocker.blocked_ids().unwrap())
+            .await
+            .unwrap();
+        assert!(!ids.contains(&128));
+        assert_eq!(blocked_pci(&daemon).await, ["0000:03:00.0"]);
+
+        // The same sharing over a node that exists: only the sharing keeps it open.
+        let devno = Daemon::node_devno("/dev/null").unwrap();
+        let target = |sharers| BlockTarget {
+            pci: "0000:03:00.0".to_string(),
+            nodes: vec![("render", "/dev/null".to_string(), None)],
+            sharers,
+        };
+        let shared = target(HashMap::from([("/dev/null".to_string(), vec![boot])]));
+        let unshared = target(HashMap::new());
+        let (shared_blocked, unshared_blocked) = daemon
+            .with_blocker("test", move |blocker| {
+                Daemon::apply_block_locked(blocker, &shared, true).unwrap();
+                let shared_blocked = blocker.is_dev_blocked(devno).unwrap();
+                Daemon::apply_block_locked(blocker, &unshared, true).unwrap();
+                (shared_blocked, blocker.is_dev_blocked(devno).unwrap())
+            })
+            .await
+            .unwrap();
+        assert!(!shared_blocked);
+        assert!(unshared_blocked);
+    }
+
+    #[tokio::test]
+    async fn unusable_nodes_leave_no_phantom_entries() {
+        // renderD0x doesn't parse; card999 parses but isn't a device node.
+        let daemon = daemon_over(vec![
+            test_gpu(0, "00:02.0", "/dev/dri/renderD128", "/dev/dri/card0", true),
+            test_gpu(
+                1,
+                "03:00.0",
+                "/dev/dri/renderD0x",
+                "/dev/dri/card999",
+                false,
+            ),
+        ]);
+        let gpu = daemon.gpu_by_id(1).unwrap();
+        daemon.apply_gpu_block_policy(gpu, true).await.unwrap();
+
+        let counts: HashMap<&str, usize> = daemon
+            .with_blocker("test", |blocker| blocker.map_counts().unwrap())
+            .await
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(counts["BLOCKED_IDS"], 0);
+        assert_eq!(counts["BLOCKED_DEVS"], 0);
+        // PCI access is still blocked.
+        assert_eq!(blocked_pci(&daemon).await, ["0000:03:00.0"]);
+    }
+
+    #[test]
+    fn extra_prefix_matches_owned_char_devices() {
+        assert_eq!(Daemon::nodes_matching("/dev/nul", |_| true), ["/dev/null"]);
+        assert!(Daemon::nodes_matching("/dev/nul", |_| false).is_empty());
+        assert!(Daemon::nodes_matching("/dev/no-such-prefix", |_| true).is_empty());
+    }
+
+    #[tokio::test]
+    async fn extra_prefix_nodes_are_blocked_by_device_number() {
+        let daemon = test_daemon();
+        let devno = Daemon::node_devno("/dev/null").unwrap();
+        let nodes = Daemon::nodes_matching("/dev/nul", |owner| owner == devno)
+            .into_iter()
+            .map(|node| ("extra", node, None))
+            .collect();
+        let target = BlockTarget {
+            pci: "0000:03:00.0".to_string(),
+            nodes,
+            sharers: HashMap::new(),
+        };
+        let (blocked, unblocked) = daemon
+            .with_blocker("test", move |blocker| {
+                Daemon::apply_block_locked(blocker, &target, true).unwrap();
+                let blocked = blocker.is_dev_blocked(devno).unwrap();
+                Daemon::apply_block_locked(blocker, &target, false).unwrap();
+                (blocked, !blocker.is_dev_blocked(devno).unwrap())
+            })
+            .await
+            .unwrap();
+        assert!(blocked);
+        assert!(unblocked);
+    }
+
+    #[tokio::test]
+    async fn exported_state_round_trips_through_toml_and_json() {
+        let daemon = test_daemon();
+        daemon.apply_mode(MODE_INTEGRATED, false).await.unwrap();
+        let state = daemon.export_block_state().await.unwrap();
+        assert_eq!(state.mode, MODE_INTEGRATED);
+        assert_eq!(state.blocked_pci, ["0000:03:00.0"]);
+
+        let from_toml: ExportedState = toml::from_str(&toml::to_string(&state).unwrap()).unwrap();
+        let from_json: ExportedState =
+            serde_json::from_str(&serde_json::to_string(&state).unwrap()).unwrap();
+        for decoded in [from_toml, from_json] {
+            assert_eq!(decoded.mode, state.mode);
+            assert_eq!(decoded.blocked_pci, state.blocked_pci);
+            assert_eq!(decoded.blocked_ids, state.blocked_ids);
+        }
+    }
+
+    #[tokio::test]
+    async fn gpu_info_flags_missing_nodes() {
+        let daemon = daemon_over(vec![test_gpu(
+            0,
+            "03:00.0",
+            "/nonexistent/renderD128",
+            "/nonexistent/card0",
+            false,
+        )]);
+        let info = daemon.gpu_info(daemon.gpu_by_id(0).unwrap()).await.unwrap();
+        let nodes_present = info
+            .iter()
+            .find(|(key, _)| key == "nodes_present")
+            .map(|(_, value)| value.as_str());
+        assert_eq!(nodes_present, Some("false"));
+    }
+
+    #[tokio::test]
+    async fn partially_set_entries_report_partial() {
+        // Real char devices, so both nodes count as block entries (by device number).
+        let daemon = daemon_over(vec![test_gpu(
+            1,
+            "03:00.0",
+            "/dev/null",
+            "/dev/zero",
+            false,
+        )]);
+        let gpu = daemon.gpu_by_id(1).unwrap();
+        let null = Daemon::node_devno("/dev/null").unwrap();
+        let zero = Daemon::node_devno("/dev/zero").unwrap();
+        let states = async || {
+            let row_state = daemon.list_gpu_rows().await.unwrap()[0].8.clone();
+            let state = daemon.block_consistency(gpu).await.unwrap().as_str();
+            assert_eq!(row_state, state);
+            row_state
+        };
+
+        // Only the PCI entry, as if blocking the nodes had failed.
+        daemon
+            .with_blocker("test", |blocker| blocker.block_pci("0000:03:00.0").unwrap())
+            .await
+            .unwrap();
+        assert_eq!(states().await, "partial");
+
+        daemon
+            .with_blocker("test", move |blocker| {
+                blocker.block_dev(null).unwrap();
+                blocker.block_dev(zero).unwrap();
+            })
+            .await
+            .unwrap();
+        assert_eq!(states().await, "blocked");
+
+        daemon
+            .with_blocker("test", move |blocker| {
+                blocker.unblock_pci("0000:03:00.0").unwrap();
+                blocker.unblock_dev(null).unwrap();
+            })
+            .await
+            .unwrap();
+        assert_eq!(states().await, "partial");
+
+        daemon
+            .with_blocker("test", move |blocker| blocker.unblock_dev(zero).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(states().await, "unblocked");
+    }
+}
@@ -0,0 +1,724 @@
+//! Daemon configuration file (`/etc/chainsaw.toml`, or `/etc/chainsaw.json`).
+
+use config::{Config, ConfigError};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::MODE_HYBRID;
+
+pub const CONFIG_PATH: &str = "/etc/chainsaw.toml";
+/// JSON alternative to `CONFIG_PATH`; keys set in the TOML file take precedence.
+pub const JSON_CONFIG_PATH: &str = "/etc/chainsaw.json";
+/// Written before an unconfirmed mode change, removed by `confirm_mode_ok`.
+pub const PENDING_MARKER_PATH: &str = "/etc/chainsaw.pending.toml";
+
+// Keep in sync with the fields of `Settings`.
+const KNOWN_KEYS: &[&str] = &[
+    "mode",
+    "block_debounce_ms",
+    "blocker_timeout_ms",
+    "managed_pci",
+    "extra_block_prefixes",
+    "block_fb_nodes",
+    "unblock_on_exit",
+    "block_watch_interval_ms",
+    "allow_framebuffer_release",
+    "require_enforcement",
+    "integrated_keep_pci",
+    "switcheroo_policy",
+    "blocked_pci_patterns",
+    "blocked_drivers",
+    "toggle_key_device",
+    "toggle_key_code",
+    "toggle_key_debounce_ms",
+    "audit_log",
+    "on_mode_change",
+    "gpu_aliases",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// GPU mode applied at startup.
+    pub mode: String,
+    /// Quiet period before a GPU block toggle is applied, in milliseconds.
+    pub block_debounce_ms: u64,
+    /// Upper bound for one eBPF map operation batch, in milliseconds.
+    pub blocker_timeout_ms: u64,
+    /// PCI addresses chainsaw may block/unblock; empty manages every GPU.
+    pub managed_pci: Vec<String>,
+    /// Extra device node path prefixes to block for each GPU, beyond renderD/card.
+    pub extra_block_prefixes: Vec<String>,
+    /// Also block each GPU's fbdev nodes (`/dev/fb*`), except the boot GPU's.
+    pub block_fb_nodes: bool,
+    /// Clear every block when the daemon exits, instead of keeping the pinned maps'
+    /// entries for the next start.
+    pub unblock_on_exit: bool,
+    /// How often to look for block changes made outside the daemon, in milliseconds;
+    /// 0 disables the check.
+    pub block_watch_interval_ms: u64,
+    /// Allow `release_boot_framebuffer` to unbind efifb/simpledrm (can blank the console).
+    pub allow_framebuffer_release: bool,
+    /// Exit at startup unless the eBPF blocker is fully attached.
+    pub require_enforcement: bool,
+    /// GPU (PCI address) integrated mode keeps instead of the boot GPU; empty keeps
+    /// the boot GPU.
+    pub integrated_keep_pci: String,
+    /// What to do when switcheroo-control is running too: `defer` (chainsaw only
+    /// reports, never blocks), `coexist`, or empty to warn about it.
+    pub switcheroo_policy: String,
+    /// PCI patterns (`bb:dd.*` or `dddd:bb:dd.*`) re-expanded and blocked at startup.
+    pub blocked_pci_patterns: Vec<String>,
+    /// Kernel drivers (e.g. `nouveau`) whose GPUs are blocked at startup.
+    pub blocked_drivers: Vec<String>,
+    /// Short labels shown instead of the detected name, by PCI address.
+    pub gpu_aliases: HashMap<String, String>,
+    /// evdev node (`/dev/input/...`) watched for the mode toggle key; empty disables it.
+    pub toggle_key_device: String,
+    /// Key code that toggles between integrated and hybrid (148 is `KEY_PROG1`).
+    pub toggle_key_code: u16,
+    /// Presses closer together than this are ignored, in milliseconds.
+    pub toggle_key_debounce_ms: u64,
+    /// Append-only file recording every mode change and block operation; empty
+    /// disables it.
+    pub audit_log: String,
+    /// Program run (as the daemon's user) after each mode change, with the new mode as
+    /// its argument; empty disables it.
+    pub on_mode_change: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            mode: MODE_HYBRID.to_string(),
+            block_debounce_ms: 100,
+            blocker_timeout_ms: 2000,
+            managed_pci: Vec::new(),
+            extra_block_prefixes: Vec::new(),
+            block_fb_nodes: false,
+            unblock_on_exit: false,
+            block_watch_interval_ms: 0,
+            allow_framebuffer_release: false,
+            require_enforcement: false,
+            integrated_keep_pci: String::new(),
+            switcheroo_policy: String::new(),
+            blocked_pci_patterns: Vec::new(),
+            blocked_drivers: Vec::new(),
+            gpu_aliases: HashMap::new(),
+            toggle_key_device: String::new(),
+            toggle_key_code: 148,
+            toggle_key_debounce_ms: 1000,
+            audit_log: String::new(),
+            on_mode_change: String::new(),
+        }
+    }
+}
+
+/// Both config files, JSON first so TOML values override it.
+fn build_config() -> Result<Config, ConfigError> {
+    build_config_from(CONFIG_PATH, JSON_CONFIG_PATH)
+}
+
+/// `build_config` over the given TOML and JSON config paths.
+fn build_config_from(toml_path: &str, json_path: &str) -> Result<Config, ConfigError> {
+    Config::builder()
+        .add_source(config::File::new(json_path, config::FileFormat::Json).required(false))
+        .add_source(config::File::new(toml_path, config::FileFormat::Toml).required(false))
+        .build()
+}
+
+impl Settings {
+    /// Load the config file. Problems are logged and never fatal: unknown keys are
+    /// ignored and an unparseable file falls back to the defaults.
+    pub fn load() -> Self {
+        let sources = config_sources(CONFIG_PATH, JSON_CONFIG_PATH);
+        let config = match build_config() {
+            Ok(config) => config,
+            Err(err) => {
+                warn!("Failed to read {}: {}, using defaults", sources, err);
+                return Self::default();
+            }
+        };
+
+        for warning in unknown_key_warnings(&config, &sources) {
+            warn!("{}", warning);
+        }
+
+        config.try_deserialize().unwrap_or_else(|err| {
+            warn!("Invalid config in {}: {}, using defaults", sources, err);
+            Self::default()
+        })
+    }
+}
+
+/// Re-read the config file and report whether it parses cleanly.
+pub fn check() -> Result<(), String> {
+    let config = build_config().map_err(|err| err.to_string())?;
+
+    let unknown = unknown_keys(&config);
+    if !unknown.is_empty() {
+        return Err(format!("unknown keys: {}", unknown.join(", ")));
+    }
+    config
+        .try_deserialize::<Settings>()
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+/// Top-level keys that `Settings` doesn't know about, sorted.
+fn unknown_keys(config: &Config) -> Vec<String> {
+    let mut unknown: Vec<String> = config
+        .clone()
+        .try_deserialize::<HashMap<String, config::Value>>()
+        .map(|table| {
+            table
+                .into_keys()
+                .filter(|key| !KNOWN_KEYS.contains(&key.as_str()))
+                .collect()
+        })
+        .unwrap_or_default();
+    unknown.sort();
+    unknown
+}
+
+/// The config files that exist, for messages about the merged config: both when both
+/// are there, the TOML path when neither is.
+fn config_sources(toml_path: &str, json_path: &str) -> String {
+    let exists = |path: &str| std::path::Path::new(path).exists();
+    match (exists(toml_path), exists(json_path)) {
+        (true, true) => format!("{} or {}", toml_path, json_path),
+        (false, true) => json_path.to_string(),
+        _ => toml_path.to_string(),
+    }
+}
+
+/// What `Settings::load` warns about each unknown key, found in `sources`.
+fn unknown_key_warnings(config: &Config, sources: &str) -> Vec<String> {
+    unknown_keys(config)
+        .into_iter()
+        .map(|key| {
+            format!(
+                "Unknown key '{}' in {} (valid keys: {}), ignoring it",
+                key,
+                sources,
+                KNOWN_KEYS.join(", ")
+            )
+        })
+        .collect()
+}
+
+fn default_contents(mode: &str) -> String {
+    format!(
+        r#"# Chainsaw Daemon Configuration
+# This file was automatically generated
+
+# GPU Mode: "integrated", "hybrid", "gaming"
+mode = "{}"
+
+# Quiet period before a GPU block toggle is applied, in milliseconds
+# block_debounce_ms = 100
+
+# Give up on an eBPF map operation after this long, in milliseconds
+# blocker_timeout_ms = 2000
+
+# Only ever block/unblock these GPUs (empty = all), e.g. to leave passthrough GPUs alone
+# managed_pci = ["0000:01:00.0"]
+
+# Also block device nodes under these path prefixes that belong to a GPU (by sysfs)
+# extra_block_prefixes = ["/dev/accel/accel"]
+
+# Also block the /dev/fb* framebuffer nodes of blocked GPUs, for legacy fbdev apps.
+# The boot GPU's framebuffer (the console) is never blocked.
+# block_fb_nodes = false
+
+# Clear every GPU block when the daemon stops. By default the blocks stay in the pinned
+# maps under /sys/fs/bpf/chainsaw and are picked up again on the next start.
+# unblock_on_exit = false
+
+# Check the eBPF maps for changes made outside the daemon this often, in milliseconds (0 = off)
+# block_watch_interval_ms = 0
+
+# Allow releasing the firmware framebuffer (efifb/simpledrm) of the boot GPU.
+# Risky: this blanks the console until a real driver takes over.
+# allow_framebuffer_release = false
+
+# Refuse to start (exit status 4) unless every blocker map is in place, instead of
+# running with part of the block policy ignored
+# require_enforcement = false
+
+# Keep this GPU in integrated mode and block every other one, including the boot GPU,
+# for when the boot GPU isn't the one to keep (set with `chainsaw set integrated --keep`)
+# integrated_keep_pci = "0000:00:02.0"
+
+# When switcheroo-control (net.hadess.SwitcherooControl) also runs: "defer" makes
+# chainsaw read-only (it reports but never blocks), "coexist" keeps both active.
+# Unset, chainsaw keeps blocking and warns at startup.
+# switcheroo_policy = "defer"
+
+# Block every PCI function matching these patterns, re-expanded at each startup
+# (managed with `chainsaw pci-pattern`)
+# blocked_pci_patterns = ["0000:01:00.*"]
+
+# Block every GPU bound to these drivers at startup (managed with `chainsaw driver`)
+# blocked_drivers = ["nouveau"]
+
+# Toggle between integrated and hybrid with a key, read from this evdev node (empty = off).
+# The daemon needs read access to it; a stable /dev/input/by-id or by-path link is best.
+# toggle_key_device = "/dev/input/by-path/platform-i8042-serio-0-event-kbd"
+# toggle_key_code = 148
+# toggle_key_debounce_ms = 1000
+
+# Record every mode change and block operation (time, D-Bus caller, outcome) in this
+# append-only file, fsynced per record (empty = off). Rotate it with logrotate.
+# audit_log = "/var/log/chainsaw/ops.log"
+
+# Run this program after every mode change, as the daemon's user (root), with the new
+# mode as its argument and in CHAINSAW_MODE (empty = off). Keep it root-owned and
+# not writable by anyone else.
+# on_mode_change = "/usr/local/libexec/chainsaw-mode-changed"
+
+# Short GPU names for the CLI, usable in place of ids
+# [gpu_aliases]
+# "0000:01:00.0" = "main"
+"#,
+        mode
+    )
+}
+
+/// Write a config file, creating its missing parent directories (mode 0755) first.
+fn write_config(path: &str, contents: impl AsRef<[u8]>) -> std::io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    if let Some(parent) = std::path::Path::new(path).parent()
+        && !parent.as_os_str().is_empty()
+    {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o755)
+            .create(parent)?;
+    }
+    std::fs::write(path, contents)
+}
+
+/// Create a default config when there is none. A read-only root only gets a warning:
+/// the daemon then runs on the defaults, and saving changes fails the same way later.
+pub fn ensure_exists() -> Result<(), Box<dyn std::error::Error>> {
+    ensure_exists_at(CONFIG_PATH, JSON_CONFIG_PATH)
+}
+
+fn ensure_exists_at(toml_path: &str, json_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if !std::path::Path::new(toml_path).exists() && !std::path::Path::new(json_path).exists() {
+        info!(
+            "Config file not found, creating default config at {}",
+            toml_path
+        );
+        match write_config(toml_path, default_contents(MODE_HYBRID)) {
+            Err(err) if err.kind() == std::io::ErrorKind::ReadOnlyFilesystem => warn!(
+                "Can't create {} on a read-only filesystem, using defaults",
+                toml_path
+            ),
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+/// Replace the top-level `<key> = ...` line with `line`, keeping every other line
+/// untouched. A missing key goes before the first table (e.g. `[gpu_aliases]`), so it
+/// stays top-level.
+fn with_key_line(contents: &str, key: &str, line: &str) -> String {
+    let mut lines: Vec<String> = contents.lines().map(str::to_string).collect();
+    let first_table = lines
+        .iter()
+        .position(|existing| existing.trim_start().starts_with('['))
+        .unwrap_or(lines.len());
+    let existing_key = lines[..first_table].iter().position(|existing| {
+        existing
+            .trim_start()
+            .strip_prefix(key)
+            .is_some_and(|rest| rest.trim_start().starts_with('='))
+    });
+    match existing_key {
+        Some(index) => lines[index] = line.to_string(),
+        None => lines.insert(first_table, line.to_string()),
+    }
+    lines.join("\n") + "\n"
+}
+
+/// The `mode` value of a config file, if it parses.
+fn saved_mode(contents: &str) -> Option<String> {
+    let table: toml::Table = contents.parse().ok()?;
+    table.get("mode")?.as_str().map(str::to_string)
+}
+
+/// Set `key` in a JSON config, keeping the other keys. Key order and formatting are
+/// not preserved.
+fn with_json_key(
+    contents: &str,
+    key: &str,
+    new_value: serde_json::Value,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut value: serde_json::Value = serde_json::from_str(contents)?;
+    let object = value
+        .as_object_mut()
+        .ok_or_else(|| format!("{} is not a JSON object", JSON_CONFIG_PATH))?;
+    object.insert(key.to_string(), new_value);
+    Ok(serde_json::to_string_pretty(&value)? + "\n")
+}
+
+/// The file settings are read from and saved to: the JSON config when only it
+/// exists, else the TOML one.
+pub fn config_path() -> &'static str {
+    if !std::path::Path::new(CONFIG_PATH).exists()
+        && std::path::Path::new(JSON_CONFIG_PATH).exists()
+    {
+        JSON_CONFIG_PATH
+    } else {
+        CONFIG_PATH
+    }
+}
+
+/// The JSON config, when it's the file settings are saved to (no TOML config exists).
+fn json_config_in_use(toml_path: &str, json_path: &str) -> Option<String> {
+    if std::path::Path::new(toml_path).exists() {
+        return None;
+    }
+    std::fs::read_to_string(json_path).ok()
+}
+
+/// Save `mode` to the file it's read from: the TOML config if present, else the JSON
+/// one, else a new TOML config.
+pub fn save_mode(mode: &str) -> Result<(), Box<dyn std::error::Error>> {
+    save_mode_to(CONFIG_PATH, JSON_CONFIG_PATH, mode)
+}
+
+/// `save_mode` over the given TOML and JSON config paths.
+fn save_mode_to(
+    toml_path: &str,
+    json_path: &str,
+    mode: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(existing) = json_config_in_use(toml_path, json_path) {
+        let saved = serde_json::from_str::<serde_json::Value>(&existing)
+            .ok()
+            .and_then(|value| value.get("mode")?.as_str().map(str::to_string));
+        if saved.as_deref() != Some(mode) {
+            let updated = with_json_key(&existing, "mode", serde_json::Value::from(mode))?;
+            write_config(json_path, updated)?;
+        }
+        return Ok(());
+    }
+
+    let contents = match std::fs::read_to_string(toml_path) {
+        // Leave the file (and its mtime) alone when nothing would change.
+        Ok(existing) if saved_mode(&existing).as_deref() == Some(mode) => return Ok(()),
+        Ok(existing) => with_key_line(&existing, "mode", &format!("mode = \"{}\"", mode)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => default_contents(mode),
+        Err(err) => return Err(err.into()),
+    };
+    write_config(toml_path, contents)?;
+    Ok(())
+}
+
+/// Save a list setting (`blocked_pci_patterns`, `blocked_drivers`) the same way
+/// `save_mode` saves `mode`.
+pub fn save_list(key: &str, values: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    save_key(
+        key,
+        serde_json::Value::from(values.to_vec()),
+        toml::Value::from(values.to_vec()),
+    )
+}
+
+/// Save a string setting (`integrated_keep_pci`) the same way `save_mode` saves `mode`.
+pub fn save_string(key: &str, value: &str) -> Result<(), Box<dyn std::error::Error>> {
+    save_key(
+        key,
+        serde_json::Value::from(value),
+        toml::Value::from(value),
+    )
+}
+
+fn save_key(
+    key: &str,
+    json_value: serde_json::Value,
+    toml_value: toml::Value,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(existing) = json_config_in_use(CONFIG_PATH, JSON_CONFIG_PATH) {
+        let updated = with_json_key(&existing, key, json_value)?;
+        write_config(JSON_CONFIG_PATH, updated)?;
+        return Ok(());
+    }
+
+    let line = format!("{} = {}", key, toml_value);
+    let existing = match std::fs::read_to_string(CONFIG_PATH) {
+        Ok(existing) => existing,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => default_contents(MODE_HYBRID),
+        Err(err) => return Err(err.into()),
+    };
+    write_config(CONFIG_PATH, with_key_line(&existing, key, &line))?;
+    Ok(())
+}
+
+/// A persisted mode change that hasn't been confirmed healthy yet.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingMode {
+    pub pending: String,
+    pub last_good: String,
+}
+
+pub fn read_pending() -> Option<PendingMode> {
+    let contents = std::fs::read_to_string(PENDING_MARKER_PATH).ok()?;
+    toml::from_str(&contents)
+        .inspect_err(|err| warn!("Ignoring invalid {}: {}", PENDING_MARKER_PATH, err))
+        .ok()
+}
+
+pub fn write_pending(marker: &PendingMode) -> Result<(), Box<dyn std::error::Error>> {
+    write_config(PENDING_MARKER_PATH, toml::to_string(marker)?)?;
+    Ok(())
+}
+
+pub fn clear_pending() -> Result<(), Box<dyn std::error::Error>> {
+    match std::fs::remove_file(PENDING_MARKER_PATH) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Startup mode: the configured one, or the last confirmed-good mode if the previous
+/// change was never confirmed (the marker survived a reboot).
+pub fn startup_mode(configured: String) -> String {
+    let Some(marker) = read_pending() else {
+        return configured;
+    };
+    warn!(
+        "Mode {} was never confirmed, reverting to last known good mode {}",
+        marker.pending, marker.last_good
+    );
+    if let Err(err) = save_mode(&marker.last_good) {
+        warn!("Failed to save mode to config: {}", err);
+    }
+    if let Err(err) = clear_pending() {
+        warn!("Failed to remove {}: {}", PENDING_MARKER_PATH, err);
+    }
+    marker.last_good
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toml_config(contents: &str) -> Config {
+        Config::builder()
+            .add_source(config::File::from_str(contents, config::FileFormat::Toml))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn misspelled_key_is_warned_about() {
+        let config = toml_config("mod = \"integrated\"\n");
+        let warnings = unknown_key_warnings(&config, CONFIG_PATH);
+        assert_eq!(warnings.len(), 1);
+        assert!(
+            warnings[0].starts_with("Unknown key 'mod'"),
+            "{}",
+            warnings[0]
+        );
+        assert!(
+            warnings[0].contains("valid keys: mode, "),
+            "{}",
+            warnings[0]
+        );
+
+        // Not fatal: the rest of the config still loads, on the defaults.
+        let settings: Settings = config.try_deserialize().unwrap();
+        assert_eq!(settings.mode, MODE_HYBRID);
+    }
+
+    #[test]
+    fn known_keys_raise_no_warning() {
+        let config = toml_config("mode = \"integrated\"\nblock_debounce_ms = 50\n");
+        assert_eq!(
+            unknown_key_warnings(&config, CONFIG_PATH),
+            Vec::<String>::new()
+        );
+    }
+
+    /// Empty scratch directory for one test.
+    fn scratch_dir(name: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("chainsaw-settings-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn modified(path: &std::path::Path) -> std::time::SystemTime {
+        std::fs::metadata(path).unwrap().modified().unwrap()
+    }
+
+    /// Backdate a file, so a write shows up as a changed mtime.
+    fn backdate(path: &std::path::Path) -> std::time::SystemTime {
+        let past = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(past)
+            .unwrap();
+        past
+    }
+
+    #[test]
+    fn messages_name_the_config_files_in_use() {
+        let dir = scratch_dir("sources");
+        let (toml_path, json_path) = (dir.join("chainsaw.toml"), dir.join("chainsaw.json"));
+        let paths = (toml_path.to_str().unwrap(), json_path.to_str().unwrap());
+        assert_eq!(config_sources(paths.0, paths.1), paths.0);
+
+        std::fs::write(&json_path, "{\"mod\": \"integrated\"}").unwrap();
+        assert_eq!(config_sources(paths.0, paths.1), paths.1);
+        let config = build_config_from(paths.0, paths.1).unwrap();
+        let warnings = unknown_key_warnings(&config, &config_sources(paths.0, paths.1));
+        assert!(
+            warnings[0].starts_with(&format!("Unknown key 'mod' in {} ", paths.1)),
+            "{}",
+            warnings[0]
+        );
+
+        std::fs::write(&toml_path, "mode = \"hybrid\"\n").unwrap();
+        assert_eq!(
+            config_sources(paths.0, paths.1),
+            format!("{} or {}", paths.0, paths.1)
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn nested_config_path_is_created_on_first_run() {
+        let dir = scratch_dir("nested");
+        let toml_path = dir.join("etc/chainsaw/chainsaw.toml");
+        let json_path = dir.join("etc/chainsaw/chainsaw.json");
+        let paths = (toml_path.to_str().unwrap(), json_path.to_str().unwrap());
+        ensure_exists_at(paths.0, paths.1).unwrap();
+
+        let written = std::fs::read_to_string(&toml_path).unwrap();
+        assert_eq!(written, default_contents(MODE_HYBRID));
+
+        // A second run leaves the existing config alone.
+        std::fs::write(&toml_path, "mode = \"integrated\"\n").unwrap();
+        ensure_exists_at(paths.0, paths.1).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&toml_path).unwrap(),
+            "mode = \"integrated\"\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn save_mode_skips_the_write_when_the_mode_matches() {
+        let dir = scratch_dir("unchanged-toml");
+        let (toml_path, json_path) = (dir.join("chainsaw.toml"), dir.join("chainsaw.json"));
+        let contents = "# site config\nmode = \"integrated\"\n";
+        std::fs::write(&toml_path, contents).unwrap();
+        let past = backdate(&toml_path);
+
+        let paths = (toml_path.to_str().unwrap(), json_path.to_str().unwrap());
+        save_mode_to(paths.0, paths.1, "integrated").unwrap();
+        assert_eq!(modified(&toml_path), past);
+        assert_eq!(std::fs::read_to_string(&toml_path).unwrap(), contents);
+
+        save_mode_to(paths.0, paths.1, "hybrid").unwrap();
+        assert_ne!(modified(&toml_path), past);
+        assert_eq!(
+            std::fs::read_to_string(&toml_path).unwrap(),
+            "# site config\nmode = \"hybrid\"\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn save_mode_skips_the_write_to_a_matching_json_config() {
+        let dir = scratch_dir("unchanged-json");
+        let (toml_path, json_path) = (dir.join("chainsaw.toml"), dir.join("chainsaw.json"));
+        std::fs::write(&json_path, "{\"mode\": \"gaming\"}").unwrap();
+        let past = backdate(&json_path);
+
+        let paths = (toml_path.to_str().unwrap(), json_path.to_str().unwrap());
+        save_mode_to(paths.0, paths.1, "gaming").unwrap();
+        assert_eq!(modified(&json_path), past);
+        assert!(!toml_path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn load_from(toml_path: &std::path::Path, json_path: &std::path::Path) -> Settings {
+        build_config_from(toml_path.to_str().unwrap(), json_path.to_str().unwrap())
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn toml_config_round_trips() {
+        let dir = scratch_dir("round-trip-toml");
+        let (toml_path, json_path) = (dir.join("chainsaw.toml"), dir.join("chainsaw.json"));
+        let paths = (toml_path.to_str().unwrap(), json_path.to_str().unwrap());
+
+        save_mode_to(paths.0, paths.1, "integrated").unwrap();
+        assert_eq!(load_from(&toml_path, &json_path).mode, "integrated");
+        save_mode_to(paths.0, paths.1, "gaming").unwrap();
+        let settings = load_from(&toml_path, &json_path);
+        assert_eq!(settings.mode, "gaming");
+        assert_eq!(
+            settings.block_debounce_ms,
+            Settings::default().block_debounce_ms
+        );
+        assert!(!json_path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn json_config_round_trips_as_json() {
+        let dir = scratch_dir("round-trip-json");
+        let (toml_path, json_path) = (dir.join("chainsaw.toml"), dir.join("chainsaw.json"));
+        let paths = (toml_path.to_str().unwrap(), json_path.to_str().unwrap());
+        std::fs::write(
+            &json_path,
+            "{\"mode\": \"hybrid\", \"block_debounce_ms\": 250}",
+        )
+        .unwrap();
+
+        save_mode_to(paths.0, paths.1, "integrated").unwrap();
+        let saved: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&json_path).unwrap()).unwrap();
+        assert_eq!(saved["mode"], "integrated");
+        assert_eq!(saved["block_debounce_ms"], 250);
+        assert!(!toml_path.exists());
+
+        let settings = load_from(&toml_path, &json_path);
+        assert_eq!(settings.mode, "integrated");
+        assert_eq!(settings.block_debounce_ms, 250);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn toml_config_overrides_json_config() {
+        let dir = scratch_dir("precedence");
+        let (toml_path, json_path) = (dir.join("chainsaw.toml"), dir.join("chainsaw.json"));
+        std::fs::write(
+            &json_path,
+            "{\"mode\": \"gaming\", \"block_debounce_ms\": 250}",
+        )
+        .unwrap();
+        std::fs::write(&toml_path, "mode = \"integrated\"\n").unwrap();
+
+        let settings = load_from(&toml_path, &json_path);
+        assert_eq!(settings.mode, "integrated");
+        assert_eq!(settings.block_debounce_ms, 250);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+//! Compares answering "is this GPU blocked?" for a whole GPU list with per-GPU map
+//! lookups (one PCI and two node id lookups each, as `list_gpu_rows` used to) against
+//! one `snapshot()` plus in-memory checks.
+//!
+//! Runs over `MemoryBlocker` by default. With `CHAINSAW_BENCH_EBPF=1` (as root, on a
+//! kernel with BPF LSM) it loads the real blocker with its maps pinned under
+//! `/sys/fs/bpf/chainsaw-bench`, so every lookup is a kernel map read.
+//!
+//! `cargo bench -p chainsaw-ebpf-loader`
+
+use std::hint::black_box;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use chainsaw_ebpf_loader::{Blocker, EbpfBlocker, MemoryBlocker};
+
+const GPUS: u32 = 16;
+const ROUNDS: u32 = 2000;
+// Far from any real DRM node id, so a loaded hook never denies a real open.
+const FIRST_ID: u32 = u32::MAX - 2 * GPUS - 1;
+
+struct TestGpu {
+    render: u32,
+    card: u32,
+    pci: String,
+}
+
+fn test_gpus() -> Vec<TestGpu> {
+    (0..GPUS)
+        .map(|index| TestGpu {
+            render: FIRST_ID + 2 * index,
+            card: FIRST_ID + 2 * index + 1,
+            pci: format!("0000:{:02x}:00.0", 0xe0 + index),
+        })
+        .collect()
+}
+
+fn per_gpu_lookups(blocker: &mut dyn Blocker, gpus: &[TestGpu]) -> usize {
+    gpus.iter()
+        .filter(|gpu| {
+            blocker.is_pci_blocked(&gpu.pci).unwrap()
+                || blocker.is_id_blocked(gpu.render).unwrap()
+                || blocker.is_id_blocked(gpu.card).unwrap()
+        })
+        .count()
+}
+
+fn one_snapshot(blocker: &mut dyn Blocker, gpus: &[TestGpu]) -> usize {
+    let snapshot = blocker.snapshot().unwrap();
+    gpus.iter()
+        .filter(|gpu| {
+            snapshot.is_pci_blocked(&gpu.pci)
+                || snapshot.is_id_blocked(gpu.render)
+                || snapshot.is_id_blocked(gpu.card)
+        })
+        .count()
+}
+
+fn time(
+    blocker: &mut dyn Blocker,
+    gpus: &[TestGpu],
+    list: fn(&mut dyn Blocker, &[TestGpu]) -> usize,
+) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        black_box(list(blocker, gpus));
+    }
+    start.elapsed() / ROUNDS
+}
+
+fn run(name: &str, blocker: &mut dyn Blocker) {
+    let gpus = test_gpus();
+    // Block every other GPU by node id, so half the lookups miss: the worst case for
+    // the per-GPU path, which stops at the first hit.
+    for gpu in gpus.iter().step_by(2) {
+        blocker.block_id(gpu.render).unwrap();
+    }
+    assert_eq!(
+        per_gpu_lookups(blocker, &gpus),
+        one_snapshot(blocker, &gpus)
+    );
+
+    let lookups = time(blocker, &gpus, per_gpu_lookups);
+    let snapshot = time(blocker, &gpus, one_snapshot);
+    println!("{} ({} GPUs, {} rounds):", name, GPUS, ROUNDS);
+    println!(
+        "  per-GPU lookups: {:>10.2?} per list, up to {} map reads",
+        lookups,
+        3 * GPUS
+    );
+    println!(
+        "  one snapshot:    {:>10.2?} per list, 3 map reads",
+        snapshot
+    );
+
+    let ids: Vec<u32> = gpus.iter().map(|gpu| gpu.render).collect();
+    blocker.unblock_ids(&ids).unwrap();
+}
+
+fn main() {
+    run("MemoryBlocker", &mut MemoryBlocker::new());
+
+    if std::env::var_os("CHAINSAW_BENCH_EBPF").is_some() {
+        match EbpfBlocker::new_with_pin_path(Path::new("/sys/fs/bpf/chainsaw-bench")) {
+            Ok(mut blocker) => run("EbpfBlocker", &mut blocker),
+            Err(err) => eprintln!("EbpfBlocker: failed to load ({}), skipped", err),
+        }
+    }
+}
@@ -1,15 +1,143 @@
 use std::env;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
+use sha2::{Digest, Sha256};
+
+/// Width of a BLOCKED_PCI key, including the NUL terminator. Fits extended
+/// (8 hex digit) domains: `DDDDDDDD:BB:DD.F`.
+const PCI_KEY_LEN: usize = 24;
+/// Set in BLOCK_HITS keys counting device-number matches, unset for node id matches.
+const HIT_DEV_FLAG: u64 = 1 << 32;
+/// `kind` of a denial event: what matched (node id, device number, PCI address).
+const DENIAL_KIND_ID: u32 = 0;
+const DENIAL_KIND_DEV: u32 = 1;
+const DENIAL_KIND_PCI: u32 = 2;
+
+/// Write the constants shared by bpf.c and the Rust loader so they can't drift.
+fn write_shared_consts(out_dir: &Path) {
+    fs::write(
+        out_dir.join("chainsaw_consts.h"),
+        format!(
+            "// Generated by build.rs, do not edit.\n#pragma once\n#define PCI_KEY_LEN {}\n#define HIT_DEV_FLAG {}ULL\n#define DENIAL_KIND_ID {}\n#define DENIAL_KIND_DEV {}\n#define DENIAL_KIND_PCI {}\n",
+            PCI_KEY_LEN, HIT_DEV_FLAG, DENIAL_KIND_ID, DENIAL_KIND_DEV, DENIAL_KIND_PCI
+        ),
+    )
+    .expect("Failed to write chainsaw_consts.h");
+    fs::write(
+        out_dir.join("consts.rs"),
+        format!(
+            "// Generated by build.rs, do not edit.\npub const PCI_KEY_LEN: usize = {};\npub const HIT_DEV_FLAG: u64 = {};\npub const DENIAL_KIND_ID: u32 = {};\npub const DENIAL_KIND_DEV: u32 = {};\npub const DENIAL_KIND_PCI: u32 = {};\n",
+            PCI_KEY_LEN, HIT_DEV_FLAG, DENIAL_KIND_ID, DENIAL_KIND_DEV, DENIAL_KIND_PCI
+        ),
+    )
+    .expect("Failed to write consts.rs");
+}
+
+/// `e_machine` of BPF objects, at byte 18 of the ELF header.
+const EM_BPF: u16 = 247;
+
+/// Panic with a clear message unless `path` looks like a BPF ELF object, so a bad
+/// object fails the build instead of `Ebpf::load` at runtime.
+fn check_bpf_object(path: &Path) {
+    let bytes = fs::read(path)
+        .unwrap_or_else(|err| panic!("BPF object {} not produced: {}", path.display(), err));
+    if bytes.is_empty() {
+        panic!("BPF object {} is empty", path.display());
+    }
+    if !bytes.starts_with(b"\x7fELF") {
+        panic!("BPF object {} is not an ELF file", path.display());
+    }
+    // Both BPF byte orders store e_machine in the object's own endianness (EI_DATA).
+    let machine = bytes.get(18..20).map(|field| {
+        let field = [field[0], field[1]];
+        if bytes[5] == 2 {
+            u16::from_be_bytes(field)
+        } else {
+            u16::from_le_bytes(field)
+        }
+    });
+    if machine != Some(EM_BPF) {
+        panic!(
+            "BPF object {} is not a BPF object (e_machine {:?}); check -target in CHAINSAW_BPF_CFLAGS",
+            path.display(),
+            machine
+        );
+    }
+}
+
+/// Hand the SHA-256 of the embedded object to the crate as `CHAINSAW_BPF_SHA256`, so a
+/// running daemon can say which build of bpf.o it loaded.
+fn export_object_hash(path: &Path) {
+    let bytes = fs::read(path)
+        .unwrap_or_else(|err| panic!("Failed to read BPF object {}: {}", path.display(), err));
+    let hash: String = Sha256::digest(&bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    println!("cargo:rustc-env=CHAINSAW_BPF_SHA256={}", hash);
+}
+
+/// Extra clang flags from `CHAINSAW_BPF_CFLAGS`, split on whitespace. They go after
+/// the defaults, so for options clang resolves last-wins (`-O`, `-g0`, `-mcpu`,
+/// `-target`) they override them. The input and output stay build.rs's.
+fn extra_cflags() -> Vec<String> {
+    let flags: Vec<String> = env::var("CHAINSAW_BPF_CFLAGS")
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(str::to_string)
+        .collect();
+    if let Some(flag) = flags
+        .iter()
+        .find(|flag| matches!(flag.as_str(), "-c" | "-o" | "-E" | "-S"))
+    {
+        panic!(
+            "CHAINSAW_BPF_CFLAGS can't contain {}: build.rs sets the input and output",
+            flag
+        );
+    }
+    flags
+}
+
 fn main() {
-    let out_dir = env::var("OUT_DIR").unwrap();
-    let out_path = PathBuf::from(out_dir).join("bpf.o");
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let out_path = out_dir.join("bpf.o");
     let source_path = "src/bpf.c";
 
     println!("cargo:rerun-if-changed={}", source_path);
+    println!("cargo:rerun-if-env-changed=CHAINSAW_BPF_DEBUG");
+    println!("cargo:rerun-if-env-changed=CHAINSAW_PREBUILT_BPF");
+    println!("cargo:rerun-if-env-changed=CHAINSAW_BPF_CFLAGS");
 
-    let status = Command::new("clang")
+    write_shared_consts(&out_dir);
+
+    // Packagers can build bpf.o out-of-band (with the same PCI_KEY_LEN) and skip clang.
+    if let Some(prebuilt) = env::var_os("CHAINSAW_PREBUILT_BPF").filter(|path| !path.is_empty()) {
+        let prebuilt = PathBuf::from(prebuilt);
+        println!("cargo:rerun-if-changed={}", prebuilt.display());
+        check_bpf_object(&prebuilt);
+        fs::copy(&prebuilt, &out_path).unwrap_or_else(|err| {
+            panic!(
+                "Failed to copy prebuilt BPF object {}: {}",
+                prebuilt.display(),
+                err
+            )
+        });
+        export_object_hash(&out_path);
+        return;
+    }
+
+    // CHAINSAW_BPF_DEBUG=1 compiles in bpf_printk traces of every block decision.
+    let debug = env::var("CHAINSAW_BPF_DEBUG").is_ok_and(|value| !value.is_empty() && value != "0");
+
+    // A stale object from an earlier build must not pass for this one's.
+    let _ = fs::remove_file(&out_path);
+    let mut clang = Command::new("clang");
+    if debug {
+        clang.arg("-DDEBUG");
+    }
+    let status = clang
         .args([
             "-O2",
             "-g",
@@ -19,11 +147,11 @@ fn main() {
             "-fno-ident",
             "-fno-unwind-tables",
             "-fno-asynchronous-unwind-tables",
-            "-c",
-            source_path,
-            "-o",
-            out_path.to_str().unwrap(),
+            "-I",
+            out_dir.to_str().unwrap(),
         ])
+        .args(extra_cflags())
+        .args(["-c", source_path, "-o", out_path.to_str().unwrap()])
         .env("NIX_HARDENING_ENABLE", "")
         .status()
         .expect("Failed to execute clang");
@@ -31,4 +159,6 @@ fn main() {
     if !status.success() {
         panic!("Failed to compile BPF program");
     }
+    check_bpf_object(&out_path);
+    export_object_hash(&out_path);
 }
@@ -3,7 +3,11 @@
 #include <bpf/bpf_tracing.h>
 #include <bpf/bpf_core_read.h>
 
+// Generated by build.rs, shared with the Rust loader.
+#include "chainsaw_consts.h"
+
 // Minimal CO-RE type definitions.
+typedef unsigned long long u64;
 typedef unsigned int u32;
 typedef unsigned char u8;
 
@@ -20,28 +24,198 @@ struct path {
     struct dentry *dentry;
 } __attribute__((preserve_access_index));
 
+struct inode {
+    u32 i_rdev;
+} __attribute__((preserve_access_index));
+
 struct file {
     struct path f_path;
+    struct inode *f_inode;
 } __attribute__((preserve_access_index));
 
+// Decision traces for trace_pipe, only in CHAINSAW_BPF_DEBUG builds.
+#ifdef DEBUG
+#define DEBUG_PRINTK(fmt, ...) bpf_printk(fmt, ##__VA_ARGS__)
+#else
+#define DEBUG_PRINTK(fmt, ...) do { } while (0)
+#endif
+
 char _license[] SEC("license") = "GPL";
 
+// Value of a BLOCKED_IDS entry; `IdBlock` in lib.rs mirrors it.
+struct id_block {
+    u64 expires_ns;        // bpf_ktime_get_ns() deadline, 0 = never expires
+    u8 blocked;
+    u8 _pad[7];
+};
+
+// BLOCKED_IDS and BLOCKED_PCI are pinned, so blocks survive a daemon restart. Keep
+// their layout in sync with PINNED_MAPS in lib.rs.
 struct {
     __uint(type, BPF_MAP_TYPE_HASH);
     __uint(max_entries, 1024);
     __type(key, u32);
-    __type(value, u8);
+    __type(value, struct id_block);
+    __uint(pinning, LIBBPF_PIN_BY_NAME);
 } BLOCKED_IDS SEC(".maps");
 
 struct {
     __uint(type, BPF_MAP_TYPE_HASH);
     __uint(max_entries, 1024);
-    __type(key, char[16]);
+    __type(key, char[PCI_KEY_LEN]);
     __type(value, u8);
+    __uint(pinning, LIBBPF_PIN_BY_NAME);
 } BLOCKED_PCI SEC(".maps");
 
-SEC("lsm/file_open")
-int BPF_PROG(file_open, struct file *file) {
+// Device numbers (kernel MKDEV encoding) for nodes whose name can't be matched.
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, 1024);
+    __type(key, u32);
+    __type(value, u8);
+} BLOCKED_DEVS SEC(".maps");
+
+// cgroup v2 ids whose tasks lose every DRM node.
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, 1024);
+    __type(key, u64);
+    __type(value, u8);
+} BLOCKED_CGROUPS SEC(".maps");
+
+// Enforcement switch at index 0: non-zero passes every open through while keeping
+// the maps intact. Arrays are zero-filled, so a fresh load always enforces.
+struct {
+    __uint(type, BPF_MAP_TYPE_ARRAY);
+    __uint(max_entries, 1);
+    __type(key, u32);
+    __type(value, u8);
+} BLOCKING_PAUSED SEC(".maps");
+
+// Processes (by tgid) the hook never blocks; the loader adds the daemon itself, so it
+// can always stat and read the nodes of GPUs it has blocked. Anything that inspects
+// node holders from another process (rather than inside the daemon) needs its tgid
+// here too, or it will see blocked nodes as missing.
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, 64);
+    __type(key, u32);
+    __type(value, u8);
+} BLOCKED_EXEMPT_PIDS SEC(".maps");
+
+// Denied opens per CPU, keyed by node id, or by kernel device number | HIT_DEV_FLAG.
+struct {
+    __uint(type, BPF_MAP_TYPE_PERCPU_HASH);
+    __uint(max_entries, 1024);
+    __type(key, u64);
+    __type(value, u64);
+} BLOCK_HITS SEC(".maps");
+
+// Denied PCI config reads per CPU, keyed like BLOCKED_PCI.
+struct {
+    __uint(type, BPF_MAP_TYPE_PERCPU_HASH);
+    __uint(max_entries, 1024);
+    __type(key, char[PCI_KEY_LEN]);
+    __type(value, u64);
+} BLOCK_HITS_PCI SEC(".maps");
+
+// One denied access, drained by EbpfBlocker::poll_events. The layout (64 bytes, no
+// padding) is shared with the #[repr(C)] DenialEvent in lib.rs; change both together.
+struct denial_event {
+    u64 timestamp_ns;      // bpf_ktime_get_ns(), CLOCK_MONOTONIC
+    u32 pid;               // tgid of the denied task
+    u32 uid;
+    u32 id;                // node id, or kernel device number; 0 for PCI
+    u32 kind;              // DENIAL_KIND_*
+    char comm[16];         // task name, NUL-padded
+    char pci[PCI_KEY_LEN]; // PCI address for DENIAL_KIND_PCI, else empty
+};
+
+// Denial events for the daemon to log. When it's full, new events are dropped; the
+// BLOCK_HITS counters still count every denial.
+struct {
+    __uint(type, BPF_MAP_TYPE_RINGBUF);
+    __uint(max_entries, 64 * 1024);
+} DENIAL_EVENTS SEC(".maps");
+
+// At most one denial event per process and target per interval. Without it a process
+// spinning on an fd that was open before its block (file_permission runs on every
+// read and write) would flood DENIAL_EVENTS.
+#define DENIAL_EVENT_INTERVAL_NS 1000000000ULL
+
+struct denial_key {
+    u32 pid;
+    u32 kind;
+    u32 id;
+    char pci[PCI_KEY_LEN];
+};
+
+// When each (tgid, target) last got a denial event. LRU, so exited processes age out.
+struct {
+    __uint(type, BPF_MAP_TYPE_LRU_HASH);
+    __uint(max_entries, 1024);
+    __type(key, struct denial_key);
+    __type(value, u64);
+} DENIAL_EVENT_LAST SEC(".maps");
+
+static __always_inline void emit_denial(u32 kind, u32 id, const char *pci) {
+    u64 now = bpf_ktime_get_ns();
+    struct denial_key key;
+    __builtin_memset(&key, 0, sizeof(key));
+    key.pid = bpf_get_current_pid_tgid() >> 32;
+    key.kind = kind;
+    key.id = id;
+    if (pci) {
+        __builtin_memcpy(key.pci, pci, sizeof(key.pci));
+    }
+    u64 *last = bpf_map_lookup_elem(&DENIAL_EVENT_LAST, &key);
+    if (last && now - *last < DENIAL_EVENT_INTERVAL_NS) {
+        return;
+    }
+    bpf_map_update_elem(&DENIAL_EVENT_LAST, &key, &now, BPF_ANY);
+
+    struct denial_event *event = bpf_ringbuf_reserve(&DENIAL_EVENTS, sizeof(*event), 0);
+    if (!event) {
+        return;
+    }
+    event->timestamp_ns = now;
+    event->pid = key.pid;
+    event->uid = (u32)bpf_get_current_uid_gid();
+    event->id = id;
+    event->kind = kind;
+    bpf_get_current_comm(&event->comm, sizeof(event->comm));
+    __builtin_memset(event->pci, 0, sizeof(event->pci));
+    if (pci) {
+        __builtin_memcpy(event->pci, pci, sizeof(event->pci));
+    }
+    bpf_ringbuf_submit(event, 0);
+}
+
+static __always_inline void count_hit(void *map, const void *key) {
+    u64 *hits = bpf_map_lookup_elem(map, key);
+    if (hits) {
+        *hits += 1;
+    } else {
+        u64 first = 1;
+        bpf_map_update_elem(map, key, &first, BPF_NOEXIST);
+    }
+}
+
+// Whether the policy denies `file` to the current task. Shared by every hook, so
+// they all enforce the same maps; denials are counted in BLOCK_HITS(_PCI).
+static __always_inline int is_denied(struct file *file) {
+    u32 paused_key = 0;
+    u8 *paused = bpf_map_lookup_elem(&BLOCKING_PAUSED, &paused_key);
+    if (paused && *paused) {
+        return 0;
+    }
+
+    u32 tgid = bpf_get_current_pid_tgid() >> 32;
+    u8 *exempt = bpf_map_lookup_elem(&BLOCKED_EXEMPT_PIDS, &tgid);
+    if (exempt && *exempt == 1) {
+        return 0;
+    }
+
     struct dentry *dentry = BPF_CORE_READ(file, f_path.dentry);
     const unsigned char *name = BPF_CORE_READ(dentry, d_name.name);
     
@@ -49,6 +223,22 @@ int BPF_PROG(file_open, struct file *file) {
         return 0;
     }
 
+    // Match by device number.
+    struct inode *inode = BPF_CORE_READ(file, f_inode);
+    if (inode) {
+        u32 rdev = BPF_CORE_READ(inode, i_rdev);
+        if (rdev) {
+            u8 *dev_value = bpf_map_lookup_elem(&BLOCKED_DEVS, &rdev);
+            if (dev_value && *dev_value == 1) {
+                u64 hit_key = HIT_DEV_FLAG | rdev;
+                count_hit(&BLOCK_HITS, &hit_key);
+                emit_denial(DENIAL_KIND_DEV, rdev, 0);
+                DEBUG_PRINTK("chainsaw: blocked dev %x", rdev);
+                return 1;
+            }
+        }
+    }
+
     // Read file name prefix.
     char buf[8] = {0};
     bpf_probe_read_kernel_str(&buf, sizeof(buf), name);
@@ -95,31 +285,74 @@ int BPF_PROG(file_open, struct file *file) {
         const unsigned char *parent_name = BPF_CORE_READ(parent, d_name.name);
         
         if (parent_name) {
-            char pci_addr[16] = {0};
-            bpf_probe_read_kernel_str(&pci_addr, sizeof(pci_addr), parent_name);
+            char pci_addr[PCI_KEY_LEN] = {0};
+            long len = bpf_probe_read_kernel_str(&pci_addr, sizeof(pci_addr), parent_name);
             
-            // Accept only PCI-like parent names: 0000:00:00.0.
-            if (pci_addr[4] == ':' && pci_addr[7] == ':' && pci_addr[10] == '.') {
-                // PCI address length is 12 chars.
-                pci_addr[12] = '\0';
+            // Accept only PCI-like parent names ending in :BB:DD.F, with a domain of
+            // 4 hex digits (0000:00:00.0) or wider for extended domains.
+            // len includes the trailing NUL.
+            if (len >= 13 && len <= PCI_KEY_LEN &&
+                pci_addr[len - 3] == '.' && pci_addr[len - 6] == ':' &&
+                pci_addr[len - 9] == ':') {
                 
-                bpf_printk("Checking config for PCI: %s", pci_addr);
+                DEBUG_PRINTK("chainsaw: checking config for PCI %s", pci_addr);
                 
                 u8 *value = bpf_map_lookup_elem(&BLOCKED_PCI, &pci_addr);
                 if (value && *value == 1) {
-                    bpf_printk("Blocked config for PCI: %s", pci_addr);
-                    return -2; // -ENOENT
+                    count_hit(&BLOCK_HITS_PCI, &pci_addr);
+                    emit_denial(DENIAL_KIND_PCI, 0, pci_addr);
+                    DEBUG_PRINTK("chainsaw: blocked config for PCI %s", pci_addr);
+                    return 1;
                 }
             }
         }
     }
 
     if (is_match) {
-        u8 *value = bpf_map_lookup_elem(&BLOCKED_IDS, &id);
-        if (value && *value == 1) {
-            return -2; // -ENOENT
+        // Match by the opening task's cgroup.
+        u64 cgroup_id = bpf_get_current_cgroup_id();
+        u8 *cgroup_value = bpf_map_lookup_elem(&BLOCKED_CGROUPS, &cgroup_id);
+        u64 hit_key = id;
+        if (cgroup_value && *cgroup_value == 1) {
+            count_hit(&BLOCK_HITS, &hit_key);
+            emit_denial(DENIAL_KIND_ID, id, 0);
+            DEBUG_PRINTK("chainsaw: blocked id %u for cgroup %llu", id, cgroup_id);
+            return 1;
+        }
+
+        struct id_block *value = bpf_map_lookup_elem(&BLOCKED_IDS, &id);
+        if (value && value->blocked == 1 &&
+            (value->expires_ns == 0 || bpf_ktime_get_ns() < value->expires_ns)) {
+            count_hit(&BLOCK_HITS, &hit_key);
+            emit_denial(DENIAL_KIND_ID, id, 0);
+            DEBUG_PRINTK("chainsaw: blocked id %u", id);
+            return 1;
         }
+        DEBUG_PRINTK("chainsaw: allowed id %u", id);
     }
     
     return 0;
 }
+
+// Baseline hook: blocked nodes look missing.
+SEC("lsm/file_open")
+int BPF_PROG(file_open, struct file *file) {
+    return is_denied(file) ? -2 : 0; // -ENOENT
+}
+
+// Reads and writes through an fd opened before the block went in. Runs on every
+// read/write, so it stays as cheap as the open check.
+SEC("lsm/file_permission")
+int BPF_PROG(file_permission, struct file *file, int mask) {
+    return is_denied(file) ? -13 : 0; // -EACCES
+}
+
+// Mapping an fd opened before the block went in. Anonymous mappings have no file.
+SEC("lsm/mmap_file")
+int BPF_PROG(mmap_file, struct file *file, unsigned long reqprot, unsigned long prot,
+             unsigned long flags) {
+    if (!file) {
+        return 0;
+    }
+    return is_denied(file) ? -13 : 0; // -EACCES
+}
@@ -0,0 +1,58 @@
+//! Kernel BPF/LSM features the optional blocker features depend on.
+
+use aya::util::KernelVersion;
+
+const LSM_PATH: &str = "/sys/kernel/security/lsm";
+const MOUNTS_PATH: &str = "/proc/mounts";
+const BPFFS_PATH: &str = "/sys/fs/bpf";
+
+/// Feature availability, probed once when the blocker is loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// `bpf` is in the active LSM list and the kernel has BPF LSM programs (5.7+).
+    pub lsm_bpf_available: bool,
+    /// `BPF_MAP_TYPE_RINGBUF` (5.8+), needed for audit streaming.
+    pub ringbuf_supported: bool,
+    /// bpffs is mounted on /sys/fs/bpf, so maps can be pinned.
+    pub pinning_supported: bool,
+    /// `bpf_get_current_cgroup_id` (4.18+), needed for cgroup blocking.
+    pub cgroup_id_helper_available: bool,
+}
+
+impl Capabilities {
+    pub fn probe() -> Self {
+        let kernel = KernelVersion::current().ok();
+        let at_least = |major, minor| {
+            kernel.is_some_and(|kernel| kernel >= KernelVersion::new(major, minor, 0))
+        };
+
+        let bpf_lsm_enabled = std::fs::read_to_string(LSM_PATH)
+            .is_ok_and(|lsm| lsm.trim().split(',').any(|name| name == "bpf"));
+        let bpffs_mounted = std::fs::read_to_string(MOUNTS_PATH).is_ok_and(|mounts| {
+            mounts.lines().any(|line| {
+                let mut fields = line.split_whitespace().skip(1);
+                fields.next() == Some(BPFFS_PATH) && fields.next() == Some("bpf")
+            })
+        });
+
+        Self {
+            lsm_bpf_available: bpf_lsm_enabled && at_least(5, 7),
+            ringbuf_supported: at_least(5, 8),
+            pinning_supported: bpffs_mounted,
+            cgroup_id_helper_available: at_least(4, 18),
+        }
+    }
+
+    /// (name, available) pairs in a stable order.
+    pub fn as_pairs(&self) -> Vec<(&'static str, bool)> {
+        vec![
+            ("lsm_bpf_available", self.lsm_bpf_available),
+            ("ringbuf_supported", self.ringbuf_supported),
+            ("pinning_supported", self.pinning_supported),
+            (
+                "cgroup_id_helper_available",
+                self.cgroup_id_helper_available,
+            ),
+        ]
+    }
+}
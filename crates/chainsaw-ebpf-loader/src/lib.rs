@@ -1,10 +1,338 @@
-use aya::maps::{HashMap, MapError};
+use aya::maps::{Array, HashMap, MapData, MapError, MapType, PerCpuHashMap, RingBuf};
 use aya::programs::Lsm;
-use aya::{Btf, Ebpf};
+use aya::util::KernelVersion;
+use aya::{Btf, Ebpf, EbpfLoader};
+use chainsaw_core::iommu::normalize_pci;
+use log::warn;
+use std::collections::HashSet;
+use std::fmt;
 use std::io::{Error as IoError, ErrorKind};
+use std::path::Path;
+use std::time::Duration;
+
+mod capabilities;
+mod memory;
+
+pub use capabilities::Capabilities;
+pub use memory::MemoryBlocker;
+
+include!(concat!(env!("OUT_DIR"), "/consts.rs"));
+
+const PROGRAM_NAME: &str = "file_open";
+// Hooks that close paths around `file_open` (I/O and mmap on fds opened before a
+// block). Attached when the kernel supports them; the blocker works without them.
+const EXTRA_PROGRAM_NAMES: [&str; 2] = ["file_permission", "mmap_file"];
+const PAUSED_MAP: &str = "BLOCKING_PAUSED";
+const MAP_NAMES: [&str; 5] = [
+    "BLOCKED_IDS",
+    "BLOCKED_PCI",
+    "BLOCKED_DEVS",
+    "BLOCKED_CGROUPS",
+    "BLOCKED_EXEMPT_PIDS",
+];
+/// bpffs directory the block maps are pinned in, so their entries outlive the daemon.
+pub const DEFAULT_PIN_PATH: &str = "/sys/fs/bpf/chainsaw";
+// Maps bpf.c pins by name, with the layout this build expects:
+// (name, key size, value size, max entries).
+const PINNED_MAPS: [(&str, u32, u32, u32); 2] = [
+    (
+        "BLOCKED_IDS",
+        4,
+        std::mem::size_of::<IdBlock>() as u32,
+        1024,
+    ),
+    ("BLOCKED_PCI", PCI_KEY_LEN as u32, 1, 1024),
+];
+/// Node id for test writes to `BLOCKED_IDS` (`self_test`, benchmarks). The hook parses
+/// at most 3 digits, so no real renderD/card node can ever have it.
+pub const SENTINEL_NODE_ID: u32 = u32::MAX;
+// Per-CPU denial counters, read by `block_hits`.
+const HIT_MAP: &str = "BLOCK_HITS";
+const PCI_HIT_MAP: &str = "BLOCK_HITS_PCI";
+// Ring buffer of `DenialEvent`s, drained by `poll_events`.
+const DENIAL_MAP: &str = "DENIAL_EVENTS";
+
+/// What a `block_hits` counter is keyed by.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum HitKey {
+    /// renderD/card node id.
+    Id(u32),
+    /// Device number (userspace `st_rdev`) of a node blocked by number.
+    Dev(u64),
+    /// PCI address whose config space was denied.
+    Pci(String),
+}
+
+/// One access the hook denied, as bpf.c's `struct denial_event` writes it to the
+/// `DENIAL_EVENTS` ring buffer. The two layouts must match field for field.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct DenialEvent {
+    /// `CLOCK_MONOTONIC` time of the denial, in nanoseconds.
+    pub timestamp_ns: u64,
+    /// Process (tgid) that was denied.
+    pub pid: u32,
+    pub uid: u32,
+    /// Node id, or kernel device number (`MKDEV`); 0 for a PCI denial.
+    pub id: u32,
+    /// `DENIAL_KIND_ID`, `DENIAL_KIND_DEV` or `DENIAL_KIND_PCI`.
+    pub kind: u32,
+    /// Task name, NUL-padded.
+    pub comm: [u8; 16],
+    /// PCI address of a PCI denial, NUL-terminated; empty otherwise.
+    pub pci: [u8; PCI_KEY_LEN],
+}
+
+impl DenialEvent {
+    /// Decode one ring buffer record, `None` if it's too short.
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < std::mem::size_of::<Self>() {
+            return None;
+        }
+        // SAFETY: the length is checked and every bit pattern is a valid DenialEvent.
+        Some(unsafe { std::ptr::read_unaligned(bytes.as_ptr().cast::<Self>()) })
+    }
+
+    /// Task name of the denied process.
+    pub fn command(&self) -> String {
+        let len = self
+            .comm
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(self.comm.len());
+        String::from_utf8_lossy(&self.comm[..len]).into_owned()
+    }
+
+    /// The blocked entry that matched, keyed like `block_hits`.
+    pub fn target(&self) -> HitKey {
+        match self.kind {
+            DENIAL_KIND_PCI => HitKey::Pci(EbpfBlocker::pci_from_key(&self.pci)),
+            DENIAL_KIND_DEV => HitKey::Dev(EbpfBlocker::rdev_from_key(self.id)),
+            _ => HitKey::Id(self.id),
+        }
+    }
+}
+
+/// Value of a `BLOCKED_IDS` entry, laid out like bpf.c's `struct id_block`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct IdBlock {
+    /// `CLOCK_MONOTONIC` deadline in nanoseconds; 0 never expires.
+    expires_ns: u64,
+    blocked: u8,
+    _pad: [u8; 7],
+}
+
+// SAFETY: plain integers with explicit padding, so every bit pattern is valid.
+unsafe impl aya::Pod for IdBlock {}
+
+impl IdBlock {
+    fn until(expires_ns: u64) -> Self {
+        Self {
+            expires_ns,
+            blocked: 1,
+            _pad: [0; 7],
+        }
+    }
+
+    fn permanent() -> Self {
+        Self::until(0)
+    }
+
+    /// Whether the hook still denies this entry at `now_ns`.
+    fn is_live(&self, now_ns: u64) -> bool {
+        self.blocked == 1 && (self.expires_ns == 0 || now_ns < self.expires_ns)
+    }
+}
+
+/// The node id, PCI address and device number block sets, read once so many
+/// membership checks (e.g. for a whole GPU list) cost no map lookups.
+#[derive(Debug, Clone, Default)]
+pub struct BlockSnapshot {
+    ids: HashSet<u32>,
+    // Normalized addresses.
+    pci: HashSet<String>,
+    // Userspace `st_rdev` values.
+    devs: HashSet<u64>,
+}
+
+impl BlockSnapshot {
+    pub fn is_id_blocked(&self, id: u32) -> bool {
+        self.ids.contains(&id)
+    }
+
+    pub fn is_pci_blocked(&self, pci: &str) -> bool {
+        self.pci.contains(&normalize_pci(pci))
+    }
+
+    pub fn is_dev_blocked(&self, rdev: u64) -> bool {
+        self.devs.contains(&rdev)
+    }
+}
+
+/// Whether the LSM hook is actually enforcing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachStatus {
+    /// Hook attached and every map present.
+    Attached,
+    /// Hook attached but some maps are missing, so part of the policy is ignored.
+    Degraded,
+    /// Hook not attached, nothing is blocked.
+    Detached,
+}
+
+impl fmt::Display for AttachStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AttachStatus::Attached => "attached",
+            AttachStatus::Degraded => "degraded",
+            AttachStatus::Detached => "detached",
+        })
+    }
+}
+
+/// Why `self_test` failed: a step's map call returned an error, or the map didn't hold
+/// what the previous step should have left in it.
+#[derive(Debug)]
+pub enum BlockerError {
+    /// `step` failed with `reason`.
+    Map { step: String, reason: String },
+    /// The map round-trip gave the wrong answer.
+    Mismatch(String),
+}
+
+impl BlockerError {
+    pub(crate) fn map(step: impl Into<String>, err: impl fmt::Display) -> Self {
+        BlockerError::Map {
+            step: step.into(),
+            reason: err.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for BlockerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockerError::Map { step, reason } => {
+                write!(f, "self-test: {} failed: {}", step, reason)
+            }
+            BlockerError::Mismatch(message) => write!(f, "self-test: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for BlockerError {}
+
+/// Operations the daemon needs from a GPU blocker, so it can run over a stand-in
+/// instead of the loaded eBPF program.
+pub trait Blocker: Send {
+    fn attach_status(&self) -> AttachStatus;
+    /// Name of the LSM hook the program attaches to.
+    fn hook_name(&self) -> &'static str;
+    /// Each additional LSM hook, with why it didn't attach (`None` when it did).
+    fn extra_hooks(&self) -> Vec<(&'static str, Option<String>)>;
+    /// Kernel features probed at load time.
+    fn capabilities(&self) -> Capabilities;
+    fn pinned_maps_reused(&self) -> bool;
+    /// Number of entries in each blocker map.
+    fn map_counts(&self) -> Result<Vec<(&'static str, usize)>, Box<dyn std::error::Error>>;
+
+    /// Pause (`false`) or resume (`true`) enforcement without touching the block maps.
+    fn set_blocking_enabled(&mut self, enabled: bool) -> Result<(), Box<dyn std::error::Error>>;
+    fn is_blocking_enabled(&self) -> Result<bool, Box<dyn std::error::Error>>;
+
+    fn block_id(&mut self, id: u32) -> Result<(), Box<dyn std::error::Error>>;
+    /// Block node `id` for `ttl` only: once it passes the hook stops denying the entry,
+    /// and `purge_expired` removes it. An id already blocked with no expiry stays so.
+    fn block_id_for(&mut self, id: u32, ttl: Duration) -> Result<(), Box<dyn std::error::Error>>;
+    /// Remove the node id entries whose TTL has passed, returning how many.
+    fn purge_expired(&mut self) -> Result<usize, Box<dyn std::error::Error>>;
+    fn unblock_id(&mut self, id: u32) -> Result<(), Box<dyn std::error::Error>>;
+    /// Block several node ids at once.
+    fn block_ids(&mut self, ids: &[u32]) -> Result<(), Box<dyn std::error::Error>>;
+    fn unblock_ids(&mut self, ids: &[u32]) -> Result<(), Box<dyn std::error::Error>>;
+    fn is_id_blocked(&mut self, id: u32) -> Result<bool, Box<dyn std::error::Error>>;
+
+    fn block_pci(&mut self, pci: &str) -> Result<(), Box<dyn std::error::Error>>;
+    fn unblock_pci(&mut self, pci: &str) -> Result<(), Box<dyn std::error::Error>>;
+    fn is_pci_blocked(&mut self, pci: &str) -> Result<bool, Box<dyn std::error::Error>>;
+
+    /// Every node id currently blocked.
+    fn blocked_ids(&self) -> Result<Vec<u32>, Box<dyn std::error::Error>>;
+    /// Every PCI address currently blocked.
+    fn blocked_pci(&self) -> Result<Vec<String>, Box<dyn std::error::Error>>;
+    /// Make the node id and PCI maps hold exactly `ids` and `pci`, touching only the
+    /// entries that differ. Additions go in before removals, so no moment has fewer
+    /// blocks than both the old and the new set.
+    fn apply_block_set(
+        &mut self,
+        ids: &[u32],
+        pci: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Accesses denied since the last call, oldest first.
+    fn poll_events(&mut self) -> Vec<DenialEvent>;
+
+    /// Read the node id, PCI and device number maps once each.
+    fn snapshot(&self) -> Result<BlockSnapshot, Box<dyn std::error::Error>>;
+
+    /// Empty the node id, PCI and device number maps, returning how many entries were
+    /// cleared. Idempotent: an entry that's already gone is no error. cgroup blocks are
+    /// left alone.
+    fn unblock_all(&mut self) -> Result<usize, Box<dyn std::error::Error>> {
+        // The snapshot skips expired TTL entries, so those go first.
+        let expired = self.purge_expired()?;
+        let snapshot = self.snapshot()?;
+        let ids: Vec<u32> = snapshot.ids.iter().copied().collect();
+        self.unblock_ids(&ids)?;
+        for pci in &snapshot.pci {
+            self.unblock_pci(pci)?;
+        }
+        for rdev in &snapshot.devs {
+            self.unblock_dev(*rdev)?;
+        }
+        Ok(expired + ids.len() + snapshot.pci.len() + snapshot.devs.len())
+    }
+
+    /// Block a device node by its userspace `st_rdev`.
+    fn block_dev(&mut self, rdev: u64) -> Result<(), Box<dyn std::error::Error>>;
+    fn unblock_dev(&mut self, rdev: u64) -> Result<(), Box<dyn std::error::Error>>;
+    fn is_dev_blocked(&mut self, rdev: u64) -> Result<bool, Box<dyn std::error::Error>>;
+
+    /// Block every DRM node for tasks in a cgroup v2 (id = inode number of its directory).
+    fn block_cgroup(&mut self, id: u64) -> Result<(), Box<dyn std::error::Error>>;
+    fn unblock_cgroup(&mut self, id: u64) -> Result<(), Box<dyn std::error::Error>>;
+    fn is_cgroup_blocked(&mut self, id: u64) -> Result<bool, Box<dyn std::error::Error>>;
+
+    /// Let a process open every node, whatever is blocked. `pid` is a tgid (what
+    /// userspace calls the pid), so every thread of the process is exempt.
+    fn exempt_pid(&mut self, pid: u32) -> Result<(), Box<dyn std::error::Error>>;
+    /// Undo `exempt_pid`. Not exempt already is no error.
+    fn unexempt_pid(&mut self, pid: u32) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Denied opens per blocked entry since it was blocked, summed over CPUs.
+    fn block_hits(
+        &self,
+    ) -> Result<std::collections::HashMap<HitKey, u64>, Box<dyn std::error::Error>>;
+    /// Denied opens of node `id` since it was blocked, summed over CPUs; 0 when it
+    /// was never hit. Reset by unblocking it.
+    fn denial_count(&self, id: u32) -> Result<u64, Box<dyn std::error::Error>>;
+
+    /// Round-trip a sentinel node id through the id map (block, read back, unblock,
+    /// read back) without touching any real GPU.
+    fn self_test(&mut self) -> Result<(), BlockerError>;
+}
 
 pub struct EbpfBlocker {
     ebpf: Ebpf,
+    attached: bool,
+    // Outcome of attaching each of `EXTRA_PROGRAM_NAMES`.
+    extra_hooks: Vec<(&'static str, Option<String>)>,
+    capabilities: Capabilities,
+    // Every map in `PINNED_MAPS` was already pinned by an earlier run and reused.
+    pinned_maps_reused: bool,
+    // `DENIAL_MAP`, taken out of `ebpf` at load; `None` if the object has none.
+    denial_events: Option<RingBuf<MapData>>,
 }
 
 impl EbpfBlocker {
@@ -12,54 +340,396 @@ impl EbpfBlocker {
         IoError::new(ErrorKind::NotFound, format!("{} not found: {}", kind, name))
     }
 
-    fn pci_key(pci: &str) -> [u8; 16] {
-        let mut key = [0u8; 16];
+    fn pci_key(pci: &str) -> [u8; PCI_KEY_LEN] {
+        let mut key = [0u8; PCI_KEY_LEN];
+        let pci = normalize_pci(pci);
         let bytes = pci.as_bytes();
-        let len = bytes.len().min(15);
+        let len = bytes.len().min(PCI_KEY_LEN - 1);
         key[..len].copy_from_slice(&bytes[..len]);
         key[len] = 0;
         key
     }
 
+    /// Kernel-internal encoding (`MKDEV`) of a userspace `st_rdev`, as seen in `inode->i_rdev`.
+    fn dev_key(rdev: u64) -> u32 {
+        let major = ((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff);
+        let minor = (rdev & 0xff) | ((rdev >> 12) & !0xff);
+        ((major << 20) | (minor & 0xfffff)) as u32
+    }
+
+    /// Inverse of `dev_key`: userspace `st_rdev` of a kernel `MKDEV` value.
+    fn rdev_from_key(key: u32) -> u64 {
+        let major = u64::from(key >> 20);
+        let minor = u64::from(key & 0xfffff);
+        ((major & 0xfff) << 8) | ((major & !0xfff) << 32) | (minor & 0xff) | ((minor & !0xff) << 12)
+    }
+
+    /// Drop the hit counter of an entry that's no longer blocked. Counters are
+    /// diagnostics only, so a missing map never fails the unblock.
+    fn clear_hits(&mut self, key: u64) {
+        if let Some(map) = self.ebpf.map_mut(HIT_MAP)
+            && let Ok(mut hits) = PerCpuHashMap::<_, u64, u64>::try_from(map)
+        {
+            let _ = hits.remove(&key);
+        }
+    }
+
+    fn pci_from_key(key: &[u8; PCI_KEY_LEN]) -> String {
+        let len = key.iter().position(|byte| *byte == 0).unwrap_or(key.len());
+        String::from_utf8_lossy(&key[..len]).into_owned()
+    }
+
+    fn clear_pci_hits(&mut self, key: &[u8; PCI_KEY_LEN]) {
+        if let Some(map) = self.ebpf.map_mut(PCI_HIT_MAP)
+            && let Ok(mut hits) = PerCpuHashMap::<_, [u8; PCI_KEY_LEN], u64>::try_from(map)
+        {
+            let _ = hits.remove(key);
+        }
+    }
+
+    /// Load and attach the blocker with its block maps pinned under `DEFAULT_PIN_PATH`.
     pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        let mut ebpf = Ebpf::load(aya::include_bytes_aligned!(concat!(
-            env!("OUT_DIR"),
-            "/bpf.o"
-        )))?;
+        Self::new_with_pin_path(Path::new(DEFAULT_PIN_PATH))
+    }
+
+    /// Load and attach the blocker with `BLOCKED_IDS` and `BLOCKED_PCI` pinned under
+    /// `pin_path`, which must be on bpffs. Maps an earlier run pinned there are reused
+    /// with their entries, so GPUs stay blocked across a restart; one whose layout
+    /// doesn't match this build is recreated empty, with a warning.
+    pub fn new_with_pin_path(pin_path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let capabilities = Capabilities::probe();
+        std::fs::create_dir_all(pin_path).map_err(|err| {
+            format!(
+                "Failed to create map pin directory {} (is bpffs mounted?): {}",
+                pin_path.display(),
+                err
+            )
+        })?;
+        let pinned_maps_reused = Self::check_pinned_maps(pin_path);
+        let mut ebpf =
+            EbpfLoader::new()
+                .map_pin_path(pin_path)
+                .load(aya::include_bytes_aligned!(concat!(
+                    env!("OUT_DIR"),
+                    "/bpf.o"
+                )))?;
 
         let btf = Btf::from_sys_fs()?;
         let program: &mut Lsm = ebpf
-            .program_mut("file_open")
-            .ok_or_else(|| Self::missing_entity("program", "file_open"))?
+            .program_mut(PROGRAM_NAME)
+            .ok_or_else(|| Self::missing_entity("program", PROGRAM_NAME))?
+            .try_into()?;
+        program.load(PROGRAM_NAME, &btf)?;
+        // A loaded but detached blocker still reports its state (as `Detached`), so the
+        // daemon can decide whether to run without enforcement.
+        let attached = match program.attach() {
+            Ok(_) => true,
+            Err(err) => {
+                warn!("Failed to attach {}: {}", PROGRAM_NAME, err);
+                false
+            }
+        };
+
+        let extra_hooks = EXTRA_PROGRAM_NAMES
+            .into_iter()
+            .map(|name| {
+                let error = Self::attach_extra(&mut ebpf, name, &btf)
+                    .err()
+                    .map(|err| err.to_string());
+                (name, error)
+            })
+            .collect();
+
+        let denial_events = ebpf
+            .take_map(DENIAL_MAP)
+            .and_then(|map| RingBuf::try_from(map).ok());
+
+        Ok(Self {
+            denial_events,
+            ebpf,
+            attached,
+            extra_hooks,
+            capabilities,
+            pinned_maps_reused,
+        })
+    }
+
+    /// Unpin each pinned map whose layout doesn't match `PINNED_MAPS`, so loading
+    /// creates it afresh. Returns whether every one is pinned and will be reused.
+    fn check_pinned_maps(pin_path: &Path) -> bool {
+        let mut reused = true;
+        for (name, key_size, value_size, max_entries) in PINNED_MAPS {
+            let path = pin_path.join(name);
+            if !path.exists() {
+                reused = false;
+                continue;
+            }
+            let mismatch = match MapData::from_pin(&path).and_then(|map| map.info()) {
+                Ok(info)
+                    if info.map_type().ok() == Some(MapType::Hash)
+                        && info.key_size() == key_size
+                        && info.value_size() == value_size
+                        && info.max_entries() == max_entries =>
+                {
+                    continue;
+                }
+                Ok(info) => format!(
+                    "key size {}, value size {}, {} entries",
+                    info.key_size(),
+                    info.value_size(),
+                    info.max_entries()
+                ),
+                Err(err) => err.to_string(),
+            };
+            warn!(
+                "Pinned map {} doesn't match this build ({}), recreating it empty",
+                path.display(),
+                mismatch
+            );
+            if let Err(err) = std::fs::remove_file(&path) {
+                warn!("Failed to unpin {}: {}", path.display(), err);
+            }
+            reused = false;
+        }
+        reused
+    }
+
+    fn attach_extra(
+        ebpf: &mut Ebpf,
+        name: &str,
+        btf: &Btf,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let program: &mut Lsm = ebpf
+            .program_mut(name)
+            .ok_or_else(|| Self::missing_entity("program", name))?
             .try_into()?;
-        program.load("file_open", &btf)?;
+        program.load(name, btf)?;
         program.attach()?;
+        Ok(())
+    }
 
-        Ok(Self { ebpf })
+    /// Node ids blocked with no expiry, i.e. not by `block_id_for`.
+    fn permanent_ids(&self) -> Result<HashSet<u32>, Box<dyn std::error::Error>> {
+        let map: HashMap<_, u32, IdBlock> = HashMap::try_from(
+            self.ebpf
+                .map("BLOCKED_IDS")
+                .ok_or_else(|| Self::missing_entity("map", "BLOCKED_IDS"))?,
+        )?;
+        let mut ids = HashSet::new();
+        for entry in map.iter() {
+            let (id, block) = entry?;
+            if block.blocked == 1 && block.expires_ns == 0 {
+                ids.insert(id);
+            }
+        }
+        Ok(ids)
     }
 
-    pub fn block_id(&mut self, id: u32) -> Result<(), Box<dyn std::error::Error>> {
-        let mut map: HashMap<_, u32, u8> = HashMap::try_from(
+    /// `CLOCK_MONOTONIC` now, the clock `bpf_ktime_get_ns()` reads.
+    fn monotonic_ns() -> Result<u64, IoError> {
+        let mut now = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: `now` is a valid timespec for the call to fill in.
+        if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) } != 0 {
+            return Err(IoError::last_os_error());
+        }
+        Ok(now.tv_sec as u64 * 1_000_000_000 + now.tv_nsec as u64)
+    }
+
+    /// Running kernel version, as seen by the loader.
+    pub fn kernel_version() -> Result<String, Box<dyn std::error::Error>> {
+        Ok(KernelVersion::current()?.to_string())
+    }
+
+    /// SHA-256 (lowercase hex) of the bpf.o embedded at build time.
+    pub fn object_hash() -> &'static str {
+        env!("CHAINSAW_BPF_SHA256")
+    }
+}
+
+impl Blocker for EbpfBlocker {
+    fn attach_status(&self) -> AttachStatus {
+        if !self.attached || self.ebpf.program(PROGRAM_NAME).is_none() {
+            AttachStatus::Detached
+        } else if MAP_NAMES
+            .iter()
+            .chain([&PAUSED_MAP, &HIT_MAP, &PCI_HIT_MAP])
+            .any(|name| self.ebpf.map(name).is_none())
+        {
+            AttachStatus::Degraded
+        } else {
+            AttachStatus::Attached
+        }
+    }
+
+    fn hook_name(&self) -> &'static str {
+        PROGRAM_NAME
+    }
+
+    fn extra_hooks(&self) -> Vec<(&'static str, Option<String>)> {
+        self.extra_hooks.clone()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    fn pinned_maps_reused(&self) -> bool {
+        self.pinned_maps_reused
+    }
+
+    fn map_counts(&self) -> Result<Vec<(&'static str, usize)>, Box<dyn std::error::Error>> {
+        let mut counts = Vec::with_capacity(MAP_NAMES.len());
+        for name in MAP_NAMES {
+            let map = self
+                .ebpf
+                .map(name)
+                .ok_or_else(|| Self::missing_entity("map", name))?;
+            let count = match name {
+                "BLOCKED_PCI" => HashMap::<_, [u8; PCI_KEY_LEN], u8>::try_from(map)?
+                    .keys()
+                    .count(),
+                "BLOCKED_IDS" => HashMap::<_, u32, IdBlock>::try_from(map)?.keys().count(),
+                "BLOCKED_CGROUPS" => HashMap::<_, u64, u8>::try_from(map)?.keys().count(),
+                _ => HashMap::<_, u32, u8>::try_from(map)?.keys().count(),
+            };
+            counts.push((name, count));
+        }
+        Ok(counts)
+    }
+
+    fn block_id(&mut self, id: u32) -> Result<(), Box<dyn std::error::Error>> {
+        let mut map: HashMap<_, u32, IdBlock> = HashMap::try_from(
             self.ebpf
                 .map_mut("BLOCKED_IDS")
                 .ok_or_else(|| Self::missing_entity("map", "BLOCKED_IDS"))?,
         )?;
-        map.insert(id, 1, 0)?;
+        map.insert(id, IdBlock::permanent(), 0)?;
+        Ok(())
+    }
+
+    fn exempt_pid(&mut self, pid: u32) -> Result<(), Box<dyn std::error::Error>> {
+        let mut map: HashMap<_, u32, u8> = HashMap::try_from(
+            self.ebpf
+                .map_mut("BLOCKED_EXEMPT_PIDS")
+                .ok_or_else(|| Self::missing_entity("map", "BLOCKED_EXEMPT_PIDS"))?,
+        )?;
+        map.insert(pid, 1, 0)?;
         Ok(())
     }
 
-    pub fn unblock_id(&mut self, id: u32) -> Result<(), Box<dyn std::error::Error>> {
+    fn unexempt_pid(&mut self, pid: u32) -> Result<(), Box<dyn std::error::Error>> {
         let mut map: HashMap<_, u32, u8> = HashMap::try_from(
+            self.ebpf
+                .map_mut("BLOCKED_EXEMPT_PIDS")
+                .ok_or_else(|| Self::missing_entity("map", "BLOCKED_EXEMPT_PIDS"))?,
+        )?;
+        let _ = map.remove(&pid);
+        Ok(())
+    }
+
+    fn block_id_for(&mut self, id: u32, ttl: Duration) -> Result<(), Box<dyn std::error::Error>> {
+        let ttl_ns = u64::try_from(ttl.as_nanos()).unwrap_or(u64::MAX);
+        let expires_ns = Self::monotonic_ns()?.saturating_add(ttl_ns.max(1));
+        let mut map: HashMap<_, u32, IdBlock> = HashMap::try_from(
+            self.ebpf
+                .map_mut("BLOCKED_IDS")
+                .ok_or_else(|| Self::missing_entity("map", "BLOCKED_IDS"))?,
+        )?;
+        match map.get(&id, 0) {
+            Ok(block) if block.blocked == 1 && block.expires_ns == 0 => return Ok(()),
+            Ok(_) | Err(MapError::KeyNotFound) => {}
+            Err(err) => return Err(err.into()),
+        }
+        map.insert(id, IdBlock::until(expires_ns), 0)?;
+        Ok(())
+    }
+
+    fn purge_expired(&mut self) -> Result<usize, Box<dyn std::error::Error>> {
+        let now = Self::monotonic_ns()?;
+        let mut map: HashMap<_, u32, IdBlock> = HashMap::try_from(
+            self.ebpf
+                .map_mut("BLOCKED_IDS")
+                .ok_or_else(|| Self::missing_entity("map", "BLOCKED_IDS"))?,
+        )?;
+        let mut expired = Vec::new();
+        for entry in map.iter() {
+            let (id, block) = entry?;
+            if !block.is_live(now) {
+                expired.push(id);
+            }
+        }
+        for id in &expired {
+            let _ = map.remove(id);
+        }
+        for id in &expired {
+            self.clear_hits(u64::from(*id));
+        }
+        Ok(expired.len())
+    }
+
+    fn unblock_id(&mut self, id: u32) -> Result<(), Box<dyn std::error::Error>> {
+        let mut map: HashMap<_, u32, IdBlock> = HashMap::try_from(
             self.ebpf
                 .map_mut("BLOCKED_IDS")
                 .ok_or_else(|| Self::missing_entity("map", "BLOCKED_IDS"))?,
         )?;
         let _ = map.remove(&id);
+        self.clear_hits(u64::from(id));
+        Ok(())
+    }
+
+    fn set_blocking_enabled(&mut self, enabled: bool) -> Result<(), Box<dyn std::error::Error>> {
+        let mut map: Array<_, u8> = Array::try_from(
+            self.ebpf
+                .map_mut(PAUSED_MAP)
+                .ok_or_else(|| Self::missing_entity("map", PAUSED_MAP))?,
+        )?;
+        map.set(0, u8::from(!enabled), 0)?;
         Ok(())
     }
 
-    pub fn block_pci(&mut self, pci: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let mut map: HashMap<_, [u8; 16], u8> = HashMap::try_from(
+    fn is_blocking_enabled(&self) -> Result<bool, Box<dyn std::error::Error>> {
+        let map: Array<_, u8> = Array::try_from(
+            self.ebpf
+                .map(PAUSED_MAP)
+                .ok_or_else(|| Self::missing_entity("map", PAUSED_MAP))?,
+        )?;
+        Ok(map.get(&0, 0)? == 0)
+    }
+
+    /// Block several node ids with one map handle.
+    fn block_ids(&mut self, ids: &[u32]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut map: HashMap<_, u32, IdBlock> = HashMap::try_from(
+            self.ebpf
+                .map_mut("BLOCKED_IDS")
+                .ok_or_else(|| Self::missing_entity("map", "BLOCKED_IDS"))?,
+        )?;
+        for id in ids {
+            map.insert(id, IdBlock::permanent(), 0)?;
+        }
+        Ok(())
+    }
+
+    fn unblock_ids(&mut self, ids: &[u32]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut map: HashMap<_, u32, IdBlock> = HashMap::try_from(
+            self.ebpf
+                .map_mut("BLOCKED_IDS")
+                .ok_or_else(|| Self::missing_entity("map", "BLOCKED_IDS"))?,
+        )?;
+        for id in ids {
+            let _ = map.remove(id);
+        }
+        for id in ids {
+            self.clear_hits(u64::from(*id));
+        }
+        Ok(())
+    }
+
+    fn block_pci(&mut self, pci: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let mut map: HashMap<_, [u8; PCI_KEY_LEN], u8> = HashMap::try_from(
             self.ebpf
                 .map_mut("BLOCKED_PCI")
                 .ok_or_else(|| Self::missing_entity("map", "BLOCKED_PCI"))?,
@@ -69,32 +739,183 @@ impl EbpfBlocker {
         Ok(())
     }
 
-    pub fn unblock_pci(&mut self, pci: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let mut map: HashMap<_, [u8; 16], u8> = HashMap::try_from(
+    fn unblock_pci(&mut self, pci: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let mut map: HashMap<_, [u8; PCI_KEY_LEN], u8> = HashMap::try_from(
             self.ebpf
                 .map_mut("BLOCKED_PCI")
                 .ok_or_else(|| Self::missing_entity("map", "BLOCKED_PCI"))?,
         )?;
         let key = Self::pci_key(pci);
         let _ = map.remove(&key);
+        self.clear_pci_hits(&key);
         Ok(())
     }
 
-    pub fn is_id_blocked(&mut self, id: u32) -> Result<bool, Box<dyn std::error::Error>> {
-        let map: HashMap<_, u32, u8> = HashMap::try_from(
+    fn blocked_ids(&self) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+        let map: HashMap<_, u32, IdBlock> = HashMap::try_from(
+            self.ebpf
+                .map("BLOCKED_IDS")
+                .ok_or_else(|| Self::missing_entity("map", "BLOCKED_IDS"))?,
+        )?;
+        let now = Self::monotonic_ns()?;
+        let mut ids = Vec::new();
+        for entry in map.iter() {
+            let (id, block) = entry?;
+            if block.is_live(now) {
+                ids.push(id);
+            }
+        }
+        Ok(ids)
+    }
+
+    fn blocked_pci(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let map: HashMap<_, [u8; PCI_KEY_LEN], u8> = HashMap::try_from(
+            self.ebpf
+                .map("BLOCKED_PCI")
+                .ok_or_else(|| Self::missing_entity("map", "BLOCKED_PCI"))?,
+        )?;
+        let mut pci = Vec::new();
+        for key in map.keys() {
+            pci.push(Self::pci_from_key(&key?));
+        }
+        Ok(pci)
+    }
+
+    fn poll_events(&mut self) -> Vec<DenialEvent> {
+        let mut events = Vec::new();
+        if let Some(ring) = self.denial_events.as_mut() {
+            while let Some(item) = ring.next() {
+                events.extend(DenialEvent::from_bytes(&item));
+            }
+        }
+        events
+    }
+
+    fn snapshot(&self) -> Result<BlockSnapshot, Box<dyn std::error::Error>> {
+        let devs: HashMap<_, u32, u8> = HashMap::try_from(
+            self.ebpf
+                .map("BLOCKED_DEVS")
+                .ok_or_else(|| Self::missing_entity("map", "BLOCKED_DEVS"))?,
+        )?;
+        let mut snapshot = BlockSnapshot {
+            ids: self.blocked_ids()?.into_iter().collect(),
+            pci: self.blocked_pci()?.into_iter().collect(),
+            devs: HashSet::new(),
+        };
+        for key in devs.keys() {
+            snapshot.devs.insert(Self::rdev_from_key(key?));
+        }
+        Ok(snapshot)
+    }
+
+    fn apply_block_set(
+        &mut self,
+        ids: &[u32],
+        pci: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let wanted_ids: std::collections::HashSet<u32> = ids.iter().copied().collect();
+        let wanted_pci: std::collections::HashSet<[u8; PCI_KEY_LEN]> =
+            pci.iter().map(|address| Self::pci_key(address)).collect();
+        // Temporary blocks aren't part of the set: a wanted id gets a permanent entry
+        // over its TTL one, and an unwanted one is left to expire.
+        let current_ids = self.permanent_ids()?;
+        let current_pci: std::collections::HashSet<[u8; PCI_KEY_LEN]> = self
+            .blocked_pci()?
+            .iter()
+            .map(|address| Self::pci_key(address))
+            .collect();
+
+        let mut id_map: HashMap<_, u32, IdBlock> = HashMap::try_from(
+            self.ebpf
+                .map_mut("BLOCKED_IDS")
+                .ok_or_else(|| Self::missing_entity("map", "BLOCKED_IDS"))?,
+        )?;
+        for id in wanted_ids.difference(&current_ids) {
+            id_map.insert(id, IdBlock::permanent(), 0)?;
+        }
+        let mut pci_map: HashMap<_, [u8; PCI_KEY_LEN], u8> = HashMap::try_from(
+            self.ebpf
+                .map_mut("BLOCKED_PCI")
+                .ok_or_else(|| Self::missing_entity("map", "BLOCKED_PCI"))?,
+        )?;
+        for key in wanted_pci.difference(&current_pci) {
+            pci_map.insert(key, 1, 0)?;
+        }
+
+        let stale_ids: Vec<u32> = current_ids.difference(&wanted_ids).copied().collect();
+        self.unblock_ids(&stale_ids)?;
+        let mut pci_map: HashMap<_, [u8; PCI_KEY_LEN], u8> = HashMap::try_from(
+            self.ebpf
+                .map_mut("BLOCKED_PCI")
+                .ok_or_else(|| Self::missing_entity("map", "BLOCKED_PCI"))?,
+        )?;
+        let stale_pci: Vec<[u8; PCI_KEY_LEN]> =
+            current_pci.difference(&wanted_pci).copied().collect();
+        for key in &stale_pci {
+            let _ = pci_map.remove(key);
+        }
+        for key in &stale_pci {
+            self.clear_pci_hits(key);
+        }
+        Ok(())
+    }
+
+    fn block_dev(&mut self, rdev: u64) -> Result<(), Box<dyn std::error::Error>> {
+        let mut map: HashMap<_, u32, u8> = HashMap::try_from(
+            self.ebpf
+                .map_mut("BLOCKED_DEVS")
+                .ok_or_else(|| Self::missing_entity("map", "BLOCKED_DEVS"))?,
+        )?;
+        map.insert(Self::dev_key(rdev), 1, 0)?;
+        Ok(())
+    }
+
+    fn unblock_dev(&mut self, rdev: u64) -> Result<(), Box<dyn std::error::Error>> {
+        let mut map: HashMap<_, u32, u8> = HashMap::try_from(
+            self.ebpf
+                .map_mut("BLOCKED_DEVS")
+                .ok_or_else(|| Self::missing_entity("map", "BLOCKED_DEVS"))?,
+        )?;
+        let _ = map.remove(&Self::dev_key(rdev));
+        self.clear_hits(HIT_DEV_FLAG | u64::from(Self::dev_key(rdev)));
+        Ok(())
+    }
+
+    fn block_cgroup(&mut self, id: u64) -> Result<(), Box<dyn std::error::Error>> {
+        let mut map: HashMap<_, u64, u8> = HashMap::try_from(
+            self.ebpf
+                .map_mut("BLOCKED_CGROUPS")
+                .ok_or_else(|| Self::missing_entity("map", "BLOCKED_CGROUPS"))?,
+        )?;
+        map.insert(id, 1, 0)?;
+        Ok(())
+    }
+
+    fn unblock_cgroup(&mut self, id: u64) -> Result<(), Box<dyn std::error::Error>> {
+        let mut map: HashMap<_, u64, u8> = HashMap::try_from(
+            self.ebpf
+                .map_mut("BLOCKED_CGROUPS")
+                .ok_or_else(|| Self::missing_entity("map", "BLOCKED_CGROUPS"))?,
+        )?;
+        let _ = map.remove(&id);
+        Ok(())
+    }
+
+    fn is_id_blocked(&mut self, id: u32) -> Result<bool, Box<dyn std::error::Error>> {
+        let map: HashMap<_, u32, IdBlock> = HashMap::try_from(
             self.ebpf
                 .map("BLOCKED_IDS")
                 .ok_or_else(|| Self::missing_entity("map", "BLOCKED_IDS"))?,
         )?;
         match map.get(&id, 0) {
-            Ok(_) => Ok(true),
+            Ok(block) => Ok(block.is_live(Self::monotonic_ns()?)),
             Err(MapError::KeyNotFound) => Ok(false),
             Err(err) => Err(err.into()),
         }
     }
 
-    pub fn is_pci_blocked(&mut self, pci: &str) -> Result<bool, Box<dyn std::error::Error>> {
-        let map: HashMap<_, [u8; 16], u8> = HashMap::try_from(
+    fn is_pci_blocked(&mut self, pci: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        let map: HashMap<_, [u8; PCI_KEY_LEN], u8> = HashMap::try_from(
             self.ebpf
                 .map("BLOCKED_PCI")
                 .ok_or_else(|| Self::missing_entity("map", "BLOCKED_PCI"))?,
@@ -106,4 +927,133 @@ impl EbpfBlocker {
             Err(err) => Err(err.into()),
         }
     }
+
+    fn is_dev_blocked(&mut self, rdev: u64) -> Result<bool, Box<dyn std::error::Error>> {
+        let map: HashMap<_, u32, u8> = HashMap::try_from(
+            self.ebpf
+                .map("BLOCKED_DEVS")
+                .ok_or_else(|| Self::missing_entity("map", "BLOCKED_DEVS"))?,
+        )?;
+        match map.get(&Self::dev_key(rdev), 0) {
+            Ok(_) => Ok(true),
+            Err(MapError::KeyNotFound) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn is_cgroup_blocked(&mut self, id: u64) -> Result<bool, Box<dyn std::error::Error>> {
+        let map: HashMap<_, u64, u8> = HashMap::try_from(
+            self.ebpf
+                .map("BLOCKED_CGROUPS")
+                .ok_or_else(|| Self::missing_entity("map", "BLOCKED_CGROUPS"))?,
+        )?;
+        match map.get(&id, 0) {
+            Ok(_) => Ok(true),
+            Err(MapError::KeyNotFound) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn denial_count(&self, id: u32) -> Result<u64, Box<dyn std::error::Error>> {
+        let map: PerCpuHashMap<_, u64, u64> = PerCpuHashMap::try_from(
+            self.ebpf
+                .map(HIT_MAP)
+                .ok_or_else(|| Self::missing_entity("map", HIT_MAP))?,
+        )?;
+        match map.get(&u64::from(id), 0) {
+            Ok(per_cpu) => Ok(per_cpu.iter().sum()),
+            Err(MapError::KeyNotFound) => Ok(0),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn block_hits(
+        &self,
+    ) -> Result<std::collections::HashMap<HitKey, u64>, Box<dyn std::error::Error>> {
+        let mut hits = std::collections::HashMap::new();
+
+        let map: PerCpuHashMap<_, u64, u64> = PerCpuHashMap::try_from(
+            self.ebpf
+                .map(HIT_MAP)
+                .ok_or_else(|| Self::missing_entity("map", HIT_MAP))?,
+        )?;
+        for entry in map.iter() {
+            let (key, per_cpu) = entry?;
+            let key = if key & HIT_DEV_FLAG != 0 {
+                HitKey::Dev(Self::rdev_from_key(key as u32))
+            } else {
+                HitKey::Id(key as u32)
+            };
+            hits.insert(key, per_cpu.iter().sum());
+        }
+
+        let map: PerCpuHashMap<_, [u8; PCI_KEY_LEN], u64> = PerCpuHashMap::try_from(
+            self.ebpf
+                .map(PCI_HIT_MAP)
+                .ok_or_else(|| Self::missing_entity("map", PCI_HIT_MAP))?,
+        )?;
+        for entry in map.iter() {
+            let (key, per_cpu) = entry?;
+            hits.insert(HitKey::Pci(Self::pci_from_key(&key)), per_cpu.iter().sum());
+        }
+
+        Ok(hits)
+    }
+
+    fn self_test(&mut self) -> Result<(), BlockerError> {
+        // Left over from an interrupted run; start clean.
+        let leftover = self
+            .is_id_blocked(SENTINEL_NODE_ID)
+            .map_err(|err| BlockerError::map("checking for a leftover id", err))?;
+        if leftover {
+            self.unblock_id(SENTINEL_NODE_ID)
+                .map_err(|err| BlockerError::map("removing a leftover id", err))?;
+        }
+
+        self.block_id(SENTINEL_NODE_ID)
+            .map_err(|err| BlockerError::map(format!("inserting id {}", SENTINEL_NODE_ID), err))?;
+        let present = self.is_id_blocked(SENTINEL_NODE_ID);
+        let removed = self.unblock_id(SENTINEL_NODE_ID);
+        match present {
+            Ok(true) => {}
+            Ok(false) => {
+                return Err(BlockerError::Mismatch(
+                    "inserted id not found in BLOCKED_IDS".to_string(),
+                ));
+            }
+            Err(err) => return Err(BlockerError::map("reading id back", err)),
+        }
+        removed
+            .map_err(|err| BlockerError::map(format!("removing id {}", SENTINEL_NODE_ID), err))?;
+        let still_present = self
+            .is_id_blocked(SENTINEL_NODE_ID)
+            .map_err(|err| BlockerError::map("re-checking the removed id", err))?;
+        if still_present {
+            return Err(BlockerError::Mismatch(
+                "id still in BLOCKED_IDS after removal".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pci_key_fits_extended_domains() {
+        let extended = "10000000:01:00.0";
+        let key = EbpfBlocker::pci_key(extended);
+        assert_eq!(EbpfBlocker::pci_from_key(&key), extended);
+        assert_ne!(key, EbpfBlocker::pci_key("0000:01:00.0"));
+    }
+
+    #[test]
+    fn pci_key_normalizes_first() {
+        assert_eq!(
+            EbpfBlocker::pci_key("01:00.0"),
+            EbpfBlocker::pci_key("0000:01:00.0")
+        );
+    }
 }
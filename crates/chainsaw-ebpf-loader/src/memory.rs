@@ -0,0 +1,419 @@
+//! In-memory stand-in for the eBPF blocker, for running the daemon without the hook.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::{
+    AttachStatus, BlockSnapshot, Blocker, BlockerError, Capabilities, DenialEvent, HitKey,
+    MAP_NAMES, SENTINEL_NODE_ID,
+};
+
+/// Keeps the block sets in memory and enforces nothing, e.g. for a daemon over fake
+/// GPUs. It reports itself as detached, and no denied opens are ever counted.
+#[derive(Debug)]
+pub struct MemoryBlocker {
+    ids: HashSet<u32>,
+    // Deadlines of the ids blocked by `block_id_for`; the others never expire.
+    id_expiries: HashMap<u32, Instant>,
+    pci: HashSet<String>,
+    devs: HashSet<u64>,
+    cgroups: HashSet<u64>,
+    exempt_pids: HashSet<u32>,
+    enabled: bool,
+}
+
+impl MemoryBlocker {
+    pub fn new() -> Self {
+        Self {
+            ids: HashSet::new(),
+            id_expiries: HashMap::new(),
+            pci: HashSet::new(),
+            devs: HashSet::new(),
+            cgroups: HashSet::new(),
+            exempt_pids: HashSet::new(),
+            enabled: true,
+        }
+    }
+}
+
+impl MemoryBlocker {
+    /// Whether a blocked id would still be denied now, like the hook's expiry check.
+    fn is_live(&self, id: u32) -> bool {
+        self.ids.contains(&id)
+            && self
+                .id_expiries
+                .get(&id)
+                .is_none_or(|expires| Instant::now() < *expires)
+    }
+
+    fn live_ids(&self) -> HashSet<u32> {
+        self.ids
+            .iter()
+            .copied()
+            .filter(|id| self.is_live(*id))
+            .collect()
+    }
+}
+
+impl Default for MemoryBlocker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Blocker for MemoryBlocker {
+    fn attach_status(&self) -> AttachStatus {
+        AttachStatus::Detached
+    }
+
+    fn hook_name(&self) -> &'static str {
+        "in-memory"
+    }
+
+    fn extra_hooks(&self) -> Vec<(&'static str, Option<String>)> {
+        Vec::new()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            lsm_bpf_available: false,
+            ringbuf_supported: false,
+            pinning_supported: false,
+            cgroup_id_helper_available: false,
+        }
+    }
+
+    fn pinned_maps_reused(&self) -> bool {
+        false
+    }
+
+    fn map_counts(&self) -> Result<Vec<(&'static str, usize)>, Box<dyn std::error::Error>> {
+        Ok(MAP_NAMES
+            .iter()
+            .map(|name| {
+                let count = match *name {
+                    "BLOCKED_IDS" => self.ids.len(),
+                    "BLOCKED_PCI" => self.pci.len(),
+                    "BLOCKED_DEVS" => self.devs.len(),
+                    "BLOCKED_CGROUPS" => self.cgroups.len(),
+                    "BLOCKED_EXEMPT_PIDS" => self.exempt_pids.len(),
+                    _ => 0,
+                };
+                (*name, count)
+            })
+            .collect())
+    }
+
+    fn set_blocking_enabled(&mut self, enabled: bool) -> Result<(), Box<dyn std::error::Error>> {
+        self.enabled = enabled;
+        Ok(())
+    }
+
+    fn is_blocking_enabled(&self) -> Result<bool, Box<dyn std::error::Error>> {
+        Ok(self.enabled)
+    }
+
+    fn block_id(&mut self, id: u32) -> Result<(), Box<dyn std::error::Error>> {
+        self.ids.insert(id);
+        self.id_expiries.remove(&id);
+        Ok(())
+    }
+
+    fn block_id_for(&mut self, id: u32, ttl: Duration) -> Result<(), Box<dyn std::error::Error>> {
+        if self.ids.contains(&id) && !self.id_expiries.contains_key(&id) {
+            return Ok(());
+        }
+        self.ids.insert(id);
+        self.id_expiries
+            .insert(id, Instant::now() + ttl.max(Duration::from_nanos(1)));
+        Ok(())
+    }
+
+    fn purge_expired(&mut self) -> Result<usize, Box<dyn std::error::Error>> {
+        let expired: Vec<u32> = self
+            .ids
+            .iter()
+            .copied()
+            .filter(|id| !self.is_live(*id))
+            .collect();
+        for id in &expired {
+            self.ids.remove(id);
+            self.id_expiries.remove(id);
+        }
+        Ok(expired.len())
+    }
+
+    fn unblock_id(&mut self, id: u32) -> Result<(), Box<dyn std::error::Error>> {
+        self.ids.remove(&id);
+        self.id_expiries.remove(&id);
+        Ok(())
+    }
+
+    fn block_ids(&mut self, ids: &[u32]) -> Result<(), Box<dyn std::error::Error>> {
+        for id in ids {
+            self.block_id(*id)?;
+        }
+        Ok(())
+    }
+
+    fn unblock_ids(&mut self, ids: &[u32]) -> Result<(), Box<dyn std::error::Error>> {
+        for id in ids {
+            self.unblock_id(*id)?;
+        }
+        Ok(())
+    }
+
+    fn is_id_blocked(&mut self, id: u32) -> Result<bool, Box<dyn std::error::Error>> {
+        Ok(self.is_live(id))
+    }
+
+    fn block_pci(&mut self, pci: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.pci.insert(chainsaw_core::iommu::normalize_pci(pci));
+        Ok(())
+    }
+
+    fn unblock_pci(&mut self, pci: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.pci.remove(&chainsaw_core::iommu::normalize_pci(pci));
+        Ok(())
+    }
+
+    fn is_pci_blocked(&mut self, pci: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        Ok(self.pci.contains(&chainsaw_core::iommu::normalize_pci(pci)))
+    }
+
+    fn blocked_ids(&self) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+        let mut ids: Vec<u32> = self.live_ids().into_iter().collect();
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    fn blocked_pci(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut pci: Vec<String> = self.pci.iter().cloned().collect();
+        pci.sort();
+        Ok(pci)
+    }
+
+    fn apply_block_set(
+        &mut self,
+        ids: &[u32],
+        pci: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Like the eBPF maps, unwanted TTL entries are left to expire.
+        let wanted: HashSet<u32> = ids.iter().copied().collect();
+        self.ids
+            .retain(|id| wanted.contains(id) || self.id_expiries.contains_key(id));
+        for id in ids {
+            self.block_id(*id)?;
+        }
+        self.pci = pci
+            .iter()
+            .map(|address| chainsaw_core::iommu::normalize_pci(address))
+            .collect();
+        Ok(())
+    }
+
+    fn poll_events(&mut self) -> Vec<DenialEvent> {
+        Vec::new()
+    }
+
+    fn snapshot(&self) -> Result<BlockSnapshot, Box<dyn std::error::Error>> {
+        Ok(BlockSnapshot {
+            ids: self.live_ids(),
+            pci: self.pci.clone(),
+            devs: self.devs.clone(),
+        })
+    }
+
+    fn block_dev(&mut self, rdev: u64) -> Result<(), Box<dyn std::error::Error>> {
+        self.devs.insert(rdev);
+        Ok(())
+    }
+
+    fn unblock_dev(&mut self, rdev: u64) -> Result<(), Box<dyn std::error::Error>> {
+        self.devs.remove(&rdev);
+        Ok(())
+    }
+
+    fn is_dev_blocked(&mut self, rdev: u64) -> Result<bool, Box<dyn std::error::Error>> {
+        Ok(self.devs.contains(&rdev))
+    }
+
+    fn block_cgroup(&mut self, id: u64) -> Result<(), Box<dyn std::error::Error>> {
+        self.cgroups.insert(id);
+        Ok(())
+    }
+
+    fn unblock_cgroup(&mut self, id: u64) -> Result<(), Box<dyn std::error::Error>> {
+        self.cgroups.remove(&id);
+        Ok(())
+    }
+
+    fn is_cgroup_blocked(&mut self, id: u64) -> Result<bool, Box<dyn std::error::Error>> {
+        Ok(self.cgroups.contains(&id))
+    }
+
+    fn exempt_pid(&mut self, pid: u32) -> Result<(), Box<dyn std::error::Error>> {
+        self.exempt_pids.insert(pid);
+        Ok(())
+    }
+
+    fn unexempt_pid(&mut self, pid: u32) -> Result<(), Box<dyn std::error::Error>> {
+        self.exempt_pids.remove(&pid);
+        Ok(())
+    }
+
+    fn block_hits(&self) -> Result<HashMap<HitKey, u64>, Box<dyn std::error::Error>> {
+        Ok(HashMap::new())
+    }
+
+    fn denial_count(&self, _id: u32) -> Result<u64, Box<dyn std::error::Error>> {
+        Ok(0)
+    }
+
+    fn self_test(&mut self) -> Result<(), BlockerError> {
+        self.block_id(SENTINEL_NODE_ID)
+            .map_err(|err| BlockerError::map("inserting the sentinel id", err))?;
+        let present = self
+            .is_id_blocked(SENTINEL_NODE_ID)
+            .map_err(|err| BlockerError::map("reading the sentinel id back", err))?;
+        if !present {
+            return Err(BlockerError::Mismatch(
+                "sentinel id missing after block".to_string(),
+            ));
+        }
+        self.unblock_id(SENTINEL_NODE_ID)
+            .map_err(|err| BlockerError::map("removing the sentinel id", err))?;
+        let still_present = self
+            .is_id_blocked(SENTINEL_NODE_ID)
+            .map_err(|err| BlockerError::map("re-checking the sentinel id", err))?;
+        if still_present {
+            return Err(BlockerError::Mismatch(
+                "sentinel id still present after unblock".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut values: Vec<String>) -> Vec<String> {
+        values.sort();
+        values
+    }
+
+    #[test]
+    fn apply_block_set_leaves_exactly_the_requested_set() {
+        let wanted_ids = [129, 1];
+        let wanted_pci = ["03:00.0".to_string(), "0000:00:02.0".to_string()];
+        let priors: [(&[u32], &[&str]); 3] = [
+            (&[], &[]),
+            (&[1, 128, 130], &["0000:03:00.0", "0000:04:00.0"]),
+            (&[129, 1], &["0000:00:02.0", "0000:03:00.0"]),
+        ];
+        for (prior_ids, prior_pci) in priors {
+            let mut blocker = MemoryBlocker::new();
+            blocker.block_ids(prior_ids).unwrap();
+            for pci in prior_pci {
+                blocker.block_pci(pci).unwrap();
+            }
+
+            blocker.apply_block_set(&wanted_ids, &wanted_pci).unwrap();
+            assert_eq!(blocker.blocked_ids().unwrap(), [1, 129]);
+            assert_eq!(
+                sorted(blocker.blocked_pci().unwrap()),
+                ["0000:00:02.0", "0000:03:00.0"]
+            );
+        }
+    }
+
+    #[test]
+    fn apply_block_set_to_nothing_clears_ids_and_pci() {
+        let mut blocker = MemoryBlocker::new();
+        blocker.block_ids(&[128, 129]).unwrap();
+        blocker.block_pci("0000:03:00.0").unwrap();
+        blocker.apply_block_set(&[], &[]).unwrap();
+        assert!(blocker.blocked_ids().unwrap().is_empty());
+        assert!(blocker.blocked_pci().unwrap().is_empty());
+    }
+
+    fn entries(blocker: &MemoryBlocker, map: &str) -> usize {
+        let counts = blocker.map_counts().unwrap();
+        counts
+            .iter()
+            .find(|(name, _)| *name == map)
+            .map_or(0, |(_, count)| *count)
+    }
+
+    fn id_entries(blocker: &MemoryBlocker) -> usize {
+        entries(blocker, "BLOCKED_IDS")
+    }
+
+    #[test]
+    fn expired_ids_stop_counting_and_are_purged() {
+        let mut blocker = MemoryBlocker::new();
+        blocker.block_id(128).unwrap();
+        blocker.block_id_for(129, Duration::from_millis(1)).unwrap();
+        // Already blocked with no expiry: stays permanent.
+        blocker.block_id_for(128, Duration::from_millis(1)).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+
+        assert!(!blocker.is_id_blocked(129).unwrap());
+        assert_eq!(blocker.blocked_ids().unwrap(), [128]);
+        assert_eq!(id_entries(&blocker), 2);
+
+        assert_eq!(blocker.purge_expired().unwrap(), 1);
+        assert_eq!(id_entries(&blocker), 1);
+        assert_eq!(blocker.blocked_ids().unwrap(), [128]);
+    }
+
+    #[test]
+    fn unblock_all_removes_expired_entries_too() {
+        let mut blocker = MemoryBlocker::new();
+        blocker.block_id(128).unwrap();
+        blocker.block_id_for(129, Duration::from_millis(1)).unwrap();
+        blocker.block_pci("0000:03:00.0").unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+
+        assert_eq!(blocker.unblock_all().unwrap(), 3);
+        assert_eq!(id_entries(&blocker), 0);
+        assert!(blocker.blocked_pci().unwrap().is_empty());
+    }
+
+    #[test]
+    fn exempt_pids_come_and_go() {
+        let mut blocker = MemoryBlocker::new();
+        blocker.exempt_pid(1234).unwrap();
+        blocker.exempt_pid(1234).unwrap();
+        blocker.exempt_pid(5678).unwrap();
+        assert_eq!(entries(&blocker, "BLOCKED_EXEMPT_PIDS"), 2);
+
+        blocker.unexempt_pid(1234).unwrap();
+        // Not exempt already is no error.
+        blocker.unexempt_pid(1234).unwrap();
+        assert_eq!(entries(&blocker, "BLOCKED_EXEMPT_PIDS"), 1);
+    }
+
+    #[test]
+    fn cgroups_are_keyed_by_id() {
+        let mut blocker = MemoryBlocker::new();
+        blocker.block_cgroup(4026531835).unwrap();
+        blocker.block_cgroup(4026531835).unwrap();
+        blocker.block_cgroup(42).unwrap();
+        assert!(blocker.is_cgroup_blocked(4026531835).unwrap());
+        assert!(!blocker.is_cgroup_blocked(7).unwrap());
+        assert_eq!(entries(&blocker, "BLOCKED_CGROUPS"), 2);
+
+        blocker.unblock_cgroup(42).unwrap();
+        // Not blocked already is no error.
+        blocker.unblock_cgroup(42).unwrap();
+        assert!(!blocker.is_cgroup_blocked(42).unwrap());
+        assert_eq!(entries(&blocker, "BLOCKED_CGROUPS"), 1);
+
+        // cgroup blocks survive clearing the GPU maps.
+        blocker.unblock_all().unwrap();
+        assert!(blocker.is_cgroup_blocked(4026531835).unwrap());
+    }
+}